@@ -1,17 +1,45 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 use crate::libnxbd;
 use crate::libnxbd::configcheck::IgnoreMap;
+use crate::libnxbd::nixcommands::{
+    ActivationMode, ConnectionOptions, PrivilegeEscalation, TargetHosts,
+};
+use crate::libnxbd::projectconfig::ProjectConfig;
+
+const DEFAULT_IGNORE_FILE: &str = ".nxbd-ignore.yaml";
 
 const SYSTEMS_HELP: &str = "System selection in flakes attribute syntax (e.g., `.#hostname` or `github:user/repo#hostname`).";
 const SYSTEMS_ALL_HELP: &str = "Can be one or many. Will select all systems in the flake in the current directory if not specified.";
+const BUILDERS_HELP: &str = "Override nix's `builders` setting for this run. The value is passed verbatim to `nix build`, e.g. `ssh://builder aarch64-linux`. An empty string disables remote builders, so systems are only built locally (or on the target host if the local machine can't build them).";
+const NO_SUBSTITUTE_HELP: &str = "Build without substituters (binary caches), so everything is built from source. Useful to verify builds, but much slower. Only applies to builds started from this machine, not to systems built on their target host.";
+const NO_SUBSTITUTE_ON_DESTINATION_HELP: &str = "Copy the full closures from this machine instead of letting the target hosts download paths from their own substituters. Use this for air-gapped targets that can't reach any binary cache.";
+const SUBSTITUTERS_HELP: &str = "Space-separated substituter URLs to use for `nix copy` instead of those from `nix.conf`, e.g. a cache that is closer to the target hosts.";
+const MAX_JOBS_HELP: &str = "Build at most N derivations in parallel, or `auto` for one per CPU. Passed as `--max-jobs` to `nix build`, like the other build options only for builds started from this machine.";
+const CORES_HELP: &str = "Let each build use at most N CPU cores, 0 for all of them. Passed as `--cores` to `nix build`.";
+const NIX_ARG_HELP: &str = "Pass this argument verbatim to `nix build`, e.g. `--nix-arg=--option --nix-arg=keep-going --nix-arg=true`. Can be given multiple times.";
+const ACTIVATION_MODE_HELP: &str = "How to activate the new system: `switch` activates it and makes it the boot default, `boot` only makes it the boot default, `test` only activates it, and `dry-activate` shows what activation would do.";
+const FAIL_ON_WARN_HELP: &str =
+    "Treat failing checks with warning severity like errors. By default, they are only reported.";
+const BUILD_HOST_HELP: &str = "Build all systems on this host via ssh, regardless of which machines could build them. The results are copied back to this machine.";
+const DEPLOY_BUILD_HOST_HELP: &str = "Build all systems on this host via ssh, regardless of which machines could build them. The results are copied from there to the target hosts, without going through the local nix store. Useful if the target hosts are too small to build their systems.";
+const EXCLUDE_HELP: &str = "Skip the system with this attribute name, or all systems matching this glob. Can be given multiple times.";
+const IGNORE_FILE_HELP: &str =
+    "Path to the ignore file. Can be given multiple times to merge several files in order.";
+const KEEP_GOING_HELP: &str =
+    "Continue with the other systems if a system fails to evaluate or build, and fail at the end.";
+const KEEP_RESULT_SYMLINKS_HELP: &str = "Keep symlinks to the built system closures in this directory, named after the system attribute. They are GC roots that protect the closures from garbage collection until the symlinks are deleted.";
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
 #[command(name = "nxbd")]
 #[command(about = "Build and deploy NixOS systems using flakes")]
 #[command(
     long_about = "A tool for building and deploying NixOS systems using flakes. \
-    It supports local and remote deployment, configuration checks, and automated system updates."
+    It supports local and remote deployment, configuration checks, and automated system updates. \
+    Defaults for `--remote-user`, `--ssh-opts`, `--ignore-file` and `--max-parallel` can be set \
+    as `remote-user`, `ssh-opts`, `ignore-file` and `max-parallel` in a `.nxbd.toml` in the \
+    root of the flake, if it is a local path."
 )]
 pub struct Cli {
     #[arg(
@@ -22,10 +50,151 @@ pub struct Cli {
     )]
     pub verbose: bool,
 
+    #[arg(
+        long,
+        global = true,
+        help = "Evaluate the configurations even if the evaluation cache has results for them. Results are cached until the flake's sources or its lock file change."
+    )]
+    pub no_cache: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Show the full trace of nix evaluation errors, and keep the build directories of failed local builds for debugging. Passes `--show-trace` and `--keep-failed` to nix."
+    )]
+    pub show_trace: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Evaluate the configurations in impure mode, for flakes that read the environment or files outside the flake. Impure evaluations are never cached."
+    )]
+    pub impure: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Don't let nix fetch anything from the network, and use cached flake inputs instead. Passes `--offline` to nix evaluations and local builds."
+    )]
+    pub offline: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Don't resolve indirect flake references like `nixpkgs` via the flake registries, so only locked inputs are used"
+    )]
+    pub no_registries: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATTERN",
+        help = "Only use the systems whose attribute names match this glob, e.g. `web-*`. Applies to the listed systems or, without any, to all systems of the flake."
+    )]
+    pub filter: Option<String>,
+
+    #[arg(
+        short = 'j',
+        long,
+        global = true,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Evaluate, check and deploy at most N systems at a time. Defaults to the number of CPUs."
+    )]
+    pub max_parallel: Option<usize>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Also append everything nxbd reports to this file, with a timestamp on every line, e.g. as a record of what a deployment changed. The output of nix itself is not included."
+    )]
+    pub log_file: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "FORMAT",
+        default_value = "human",
+        help = "How to report errors. With `json`, failed checks are additionally printed to stdout as an object that maps each system attribute to its failed `[group, check]` pairs."
+    )]
+    pub format: OutputFormat,
+
     #[command(subcommand)]
     pub command: Command,
 }
 
+impl Cli {
+    /// The flake of the systems the command works on, the first one if
+    /// systems from several flakes are given
+    pub fn flake_url(&self) -> &str {
+        let system = match &self.command {
+            Command::Build { systems, .. }
+            | Command::SwitchRemote { systems, .. }
+            | Command::Diff { systems, .. }
+            | Command::Copy { systems, .. }
+            | Command::Gc { systems, .. }
+            | Command::Generations { systems, .. }
+            | Command::Rollback { systems, .. }
+            | Command::Check { systems, .. }
+            | Command::Validate { systems, .. }
+            | Command::Status { systems, .. } => systems.first(),
+            Command::SwitchLocal { system, .. } => system.as_ref(),
+            Command::Inspect { system } | Command::Ssh { system, .. } => Some(system),
+            Command::Checks { .. } | Command::GenerateDocs { .. } => None,
+        };
+        system.map_or(".", |system| system.url.as_str())
+    }
+
+    /// Fills in the options that weren't given on the command line from the
+    /// project config
+    pub fn apply_project_config(&mut self, config: &ProjectConfig) {
+        if self.max_parallel.is_none() {
+            self.max_parallel = config.max_parallel;
+        }
+        match &mut self.command {
+            Command::SwitchRemote {
+                ignore_file,
+                connection,
+                ..
+            }
+            | Command::Check {
+                ignore_file,
+                connection,
+                ..
+            } => {
+                apply_ignore_file(ignore_file, config);
+                connection.apply_project_config(config);
+            }
+            Command::SwitchLocal { ignore_file, .. } => apply_ignore_file(ignore_file, config),
+            Command::Diff { connection, .. }
+            | Command::Copy { connection, .. }
+            | Command::Gc { connection, .. }
+            | Command::Generations { connection, .. }
+            | Command::Rollback { connection, .. }
+            | Command::Ssh { connection, .. }
+            | Command::Status { connection, .. } => connection.apply_project_config(config),
+            Command::Build { .. }
+            | Command::Checks { .. }
+            | Command::Inspect { .. }
+            | Command::Validate { .. }
+            | Command::GenerateDocs { .. } => {}
+        }
+    }
+}
+
+/// clap can't tell whether the default ignore file was given explicitly, but
+/// then using the project's ignore files instead makes no difference
+fn apply_ignore_file(ignore_file: &mut Vec<String>, config: &ProjectConfig) {
+    if let Some(config_files) = &config.ignore_file {
+        if *ignore_file == [DEFAULT_IGNORE_FILE] {
+            ignore_file.clone_from(config_files);
+        }
+    }
+}
+
+// Parsed once per run, so the size of the largest command doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Command {
     #[command(about = "Build NixOS configurations without deploying")]
@@ -37,6 +206,30 @@ pub enum Command {
         #[arg(help = &format!("{} {}", SYSTEMS_HELP, SYSTEMS_ALL_HELP))]
         #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
         systems: Vec<libnxbd::FlakeReference>,
+
+        #[arg(long, value_name = "DIR", help = KEEP_RESULT_SYMLINKS_HELP)]
+        keep_result_symlinks: Option<String>,
+
+        #[arg(long, value_name = "SPEC", help = BUILDERS_HELP)]
+        builders: Option<String>,
+
+        #[arg(long, help = NO_SUBSTITUTE_HELP)]
+        no_substitute: bool,
+
+        #[arg(long, value_name = "N", help = MAX_JOBS_HELP)]
+        max_jobs: Option<String>,
+
+        #[arg(long, value_name = "N", help = CORES_HELP)]
+        cores: Option<u32>,
+
+        #[arg(long, value_name = "ARG", allow_hyphen_values = true, help = NIX_ARG_HELP)]
+        nix_arg: Vec<String>,
+
+        #[arg(long, value_name = "SSH_HOST", conflicts_with_all = ["builders", "no_substitute", "max_jobs", "cores", "nix_arg"], help = BUILD_HOST_HELP)]
+        build_host: Option<String>,
+
+        #[arg(long, help = KEEP_GOING_HELP)]
+        keep_going: bool,
     },
 
     #[command(about = "Deploy configurations to remote systems")]
@@ -49,13 +242,24 @@ This command assumes:
 - Deployment target host address is defined by the hostname
 and the (optional) FQDN and is obtained via `config.networking.fqdnOrHostName`.
 - The local user account that runs `nxbd` is used for connecting to the target
-  host via SSH."#
+  host via SSH, unless `--remote-user` names another one.
+
+Copied system closures are protected from garbage collection on the target host
+by the GC root `/nix/var/nix/gcroots/nxbd-staged`. It always points to the most
+recently deployed system, so it does not need to be cleaned up.
+
+While deploying to a host, nxbd holds an advisory lock on `/run/nxbd.lock` on
+it. Another nxbd run that wants to deploy to the same host fails and reports
+who holds the lock, unless `--lock-timeout` lets it wait."#
     )]
     SwitchRemote {
         #[arg(help = &format!("{} {}", SYSTEMS_HELP, SYSTEMS_ALL_HELP))]
         #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
         systems: Vec<libnxbd::FlakeReference>,
 
+        #[arg(long, value_name = "ATTR", help = EXCLUDE_HELP)]
+        exclude: Vec<String>,
+
         #[arg(long, help = "Skip pre-deployment configuration checks")]
         ignore_checks: bool,
 
@@ -65,11 +269,271 @@ and the (optional) FQDN and is obtained via `config.networking.fqdnOrHostName`.
         )]
         reboot: bool,
 
+        #[arg(
+            long,
+            requires = "reboot",
+            help = "After rebooting, wait for the hosts to come back and verify that they run the deployed system without requiring another reboot"
+        )]
+        wait_for_reboot: bool,
+
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "5m",
+            value_parser = libnxbd::humanize::parse_duration,
+            requires = "wait_for_reboot",
+            help = "How long --wait-for-reboot waits for each host, e.g. `90s` or `10m`"
+        )]
+        reboot_timeout: u64,
+
         #[arg(
             long,
             help = "Comma-separated list of checks to ignore in format group.check or group.* (e.g., 'remote_deployment.ssh_enabled,hardware_configuration.*')"
         )]
         ignored_checks: Option<IgnoreMap>,
+
+        #[arg(long, value_name = "PATH", default_value = DEFAULT_IGNORE_FILE, help = IGNORE_FILE_HELP)]
+        ignore_file: Vec<String>,
+
+        #[arg(long, value_name = "DIR", help = KEEP_RESULT_SYMLINKS_HELP)]
+        keep_result_symlinks: Option<String>,
+
+        #[arg(long, value_name = "SPEC", help = BUILDERS_HELP)]
+        builders: Option<String>,
+
+        #[arg(long, help = NO_SUBSTITUTE_HELP)]
+        no_substitute: bool,
+
+        #[arg(long, value_name = "N", help = MAX_JOBS_HELP)]
+        max_jobs: Option<String>,
+
+        #[arg(long, value_name = "N", help = CORES_HELP)]
+        cores: Option<u32>,
+
+        #[arg(long, value_name = "ARG", allow_hyphen_values = true, help = NIX_ARG_HELP)]
+        nix_arg: Vec<String>,
+
+        #[arg(
+            long,
+            help = "Build all systems locally and copy the results, even if they would be built on the target host. Foreign architectures are built via qemu emulation (`boot.binfmt.emulatedSystems`), which can be slow."
+        )]
+        local_build_then_copy: bool,
+
+        #[arg(long, value_name = "SSH_HOST", conflicts_with_all = ["local_build_then_copy", "builders", "no_substitute", "max_jobs", "cores", "nix_arg"], help = DEPLOY_BUILD_HOST_HELP)]
+        build_host: Option<String>,
+
+        #[arg(
+            long,
+            help = "Deploy one host at a time and ask before continuing with the next one. Answering `rollback` reverts the host that was just deployed and stops."
+        )]
+        confirm_each: bool,
+
+        #[arg(
+            long,
+            help = "After switching a host, check that it runs the new system without failed systemd units, and count the deployment as failed otherwise"
+        )]
+        health_gate: bool,
+
+        #[arg(
+            long,
+            help = "Ask every host which system it runs before deploying, and skip the hosts that already run the new system. They are neither built nor switched, and are listed as unchanged."
+        )]
+        only_changed: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "confirm_each",
+            help = "Deploy one host at a time and stop at the first host whose deployment fails, so a broken configuration isn't rolled out to all hosts. Combine with --health-gate to also stop at hosts with failed units."
+        )]
+        halt_on_failure: bool,
+
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+            conflicts_with_all = ["confirm_each", "halt_on_failure"],
+            help = "Deploy to N hosts at a time, and only continue with the next batch if all hosts of the previous one run the new system without failed units. Implies --health-gate."
+        )]
+        batch_size: Option<usize>,
+
+        #[arg(
+            short,
+            long,
+            help = "Don't ask for confirmation before deploying. Without a terminal, nxbd never asks."
+        )]
+        yes: bool,
+
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            conflicts_with = "confirm_each",
+            help = "Print the deployment plan (target hosts, build strategy, whether hosts are up to date) and exit without deploying"
+        )]
+        dump_plan: Option<PlanFormat>,
+
+        #[arg(
+            long,
+            help = "Don't take the deploy lock on the target hosts, which prevents concurrent nxbd runs from deploying to the same host"
+        )]
+        no_lock: bool,
+
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "0s",
+            value_parser = libnxbd::humanize::parse_duration,
+            conflicts_with = "no_lock",
+            help = "How long to wait for the deploy lock of a host that another nxbd run is deploying to, e.g. `30s` or `5m`"
+        )]
+        lock_timeout: u64,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["confirm_each", "reboot"],
+            help = "Build the systems and copy them to their hosts, but don't activate them. A later run without this flag finds the closures already in place and only switches."
+        )]
+        dry_run: bool,
+
+        #[arg(long, value_name = "MODE", default_value = "switch", help = ACTIVATION_MODE_HELP)]
+        activation_mode: ActivationMode,
+
+        #[arg(long, help = FAIL_ON_WARN_HELP)]
+        fail_on_warn: bool,
+
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 0,
+            help = "Retry copying to and building on a host up to N times if the connection fails, waiting 1s, 2s, 4s, ... in between. Build and activation failures are never retried."
+        )]
+        retries: u32,
+
+        #[arg(long, help = NO_SUBSTITUTE_ON_DESTINATION_HELP)]
+        no_substitute_on_destination: bool,
+
+        #[arg(long, value_name = "URLS", help = SUBSTITUTERS_HELP)]
+        substituters: Option<String>,
+
+        #[command(flatten)]
+        connection: ConnectionArgs,
+    },
+
+    #[command(about = "Show what a deployment would change on remote systems")]
+    #[command(
+        long_about = r#"Build one or more NixOS configurations and compare them with the generation
+that currently runs on their hosts, without switching.
+
+The built system closures are copied to the target hosts, where
+`nix store diff-closures` lists the package version and size changes."#
+    )]
+    Diff {
+        #[arg(help = &format!("{} {}", SYSTEMS_HELP, SYSTEMS_ALL_HELP))]
+        #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
+        systems: Vec<libnxbd::FlakeReference>,
+
+        #[arg(long, value_name = "SPEC", help = BUILDERS_HELP)]
+        builders: Option<String>,
+
+        #[command(flatten)]
+        connection: ConnectionArgs,
+    },
+
+    #[command(about = "Copy system closures to a host without deploying them")]
+    #[command(
+        long_about = r#"Build one or more NixOS configurations and copy their closures to a host,
+without activating them.
+
+By default, every system is copied to its own host, which warms it up for a later
+deployment. With --to, all systems are copied to the given host instead, e.g. to
+seed a machine that serves as a binary cache."#
+    )]
+    Copy {
+        #[arg(help = &format!("{} {}", SYSTEMS_HELP, SYSTEMS_ALL_HELP))]
+        #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
+        systems: Vec<libnxbd::FlakeReference>,
+
+        #[arg(
+            long,
+            value_name = "HOST",
+            conflicts_with = "target_host",
+            help = "Copy all systems to this host instead of their own hosts"
+        )]
+        to: Option<String>,
+
+        #[arg(long, value_name = "SPEC", help = BUILDERS_HELP)]
+        builders: Option<String>,
+
+        #[arg(long, help = NO_SUBSTITUTE_ON_DESTINATION_HELP)]
+        no_substitute_on_destination: bool,
+
+        #[arg(long, value_name = "URLS", help = SUBSTITUTERS_HELP)]
+        substituters: Option<String>,
+
+        #[command(flatten)]
+        connection: ConnectionArgs,
+    },
+
+    #[command(about = "Collect garbage on remote systems")]
+    #[command(
+        long_about = r#"Run the nix garbage collector on one or more remote systems, like
+`nix-collect-garbage`. With `--older-than`, generations of all profiles that are
+older than that are deleted first. Reports how much space was freed on each host.
+
+This is especially useful after reducing the number of kept boot generations."#
+    )]
+    Gc {
+        #[arg(help = &format!("{} {}", SYSTEMS_HELP, SYSTEMS_ALL_HELP))]
+        #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
+        systems: Vec<libnxbd::FlakeReference>,
+
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = libnxbd::humanize::parse_nix_days,
+            help = "Delete generations older than this first, e.g. `14d` or `2w`. Without this, no generations are deleted and only unreferenced store paths are collected."
+        )]
+        older_than: Option<String>,
+
+        #[command(flatten)]
+        connection: ConnectionArgs,
+    },
+
+    #[command(about = "List the system generations of remote systems")]
+    #[command(
+        long_about = "List the generations of the system profile on one or more remote systems, \
+        marking the current one and the one that matches the configuration in the flake. \
+        This helps to decide whether and where to roll back."
+    )]
+    Generations {
+        #[arg(help = &format!("{} {}", SYSTEMS_HELP, SYSTEMS_ALL_HELP))]
+        #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
+        systems: Vec<libnxbd::FlakeReference>,
+
+        #[command(flatten)]
+        connection: ConnectionArgs,
+    },
+
+    #[command(about = "Roll back remote systems to their previous generation")]
+    #[command(
+        long_about = r#"Switch one or more remote systems back to the previous generation of their
+system profile, like `nixos-rebuild switch --rollback`.
+
+The flake is only evaluated to find the target hosts. The previous generation is
+whatever the host ran before its last switch, regardless of the flake contents."#
+    )]
+    Rollback {
+        #[arg(help = &format!("{} {}", SYSTEMS_HELP, SYSTEMS_ALL_HELP))]
+        #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
+        systems: Vec<libnxbd::FlakeReference>,
+
+        #[arg(
+            long,
+            help = "Automatically reboot if required by kernel/initrd changes"
+        )]
+        reboot: bool,
+
+        #[command(flatten)]
+        connection: ConnectionArgs,
     },
 
     #[command(about = "Deploy configuration to the local system")]
@@ -95,6 +559,21 @@ If no system is specified, it uses the current hostname as the flake attribute s
             help = "Comma-separated list of checks to ignore in format group.check or group.* (e.g., 'remote_deployment.ssh_enabled,hardware_configuration.*')"
         )]
         ignored_checks: Option<IgnoreMap>,
+
+        #[arg(long, value_name = "PATH", default_value = DEFAULT_IGNORE_FILE, help = IGNORE_FILE_HELP)]
+        ignore_file: Vec<String>,
+
+        #[arg(long, value_name = "MODE", default_value = "switch", help = ACTIVATION_MODE_HELP)]
+        activation_mode: ActivationMode,
+
+        #[arg(long, help = FAIL_ON_WARN_HELP)]
+        fail_on_warn: bool,
+
+        #[arg(
+            long,
+            help = "Automatically reboot if required by kernel/initrd changes"
+        )]
+        reboot: bool,
     },
 
     #[command(about = "Run configuration checks")]
@@ -105,6 +584,9 @@ If no system is specified, it uses the current hostname as the flake attribute s
         #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
         systems: Vec<libnxbd::FlakeReference>,
 
+        #[arg(long, value_name = "ATTR", help = EXCLUDE_HELP)]
+        exclude: Vec<String>,
+
         #[arg(
             long,
             help = "Save any failing checks to the ignore file. They will be ignored in future runs."
@@ -113,23 +595,118 @@ If no system is specified, it uses the current hostname as the flake attribute s
 
         #[arg(
             long,
-            help = "Path to the ignore file",
-            default_value = ".nxbd-ignore.yaml"
+            help = "Path to the ignore file. Can be given multiple times to merge several files in order, in which case --save-ignore writes to the last one.",
+            default_value = DEFAULT_IGNORE_FILE
         )]
-        ignore_file: String,
+        ignore_file: Vec<String>,
 
         #[arg(
             long,
             help = "Comma-separated list of checks to ignore in format group.check or group.* (e.g., 'remote_deployment.ssh_enabled,hardware_configuration.*')"
         )]
         ignored_checks: Option<IgnoreMap>,
+
+        #[arg(
+            long,
+            conflicts_with = "systems",
+            help = "Run the checks on already evaluated configurations from a JSON file instead of evaluating the flake. The file contains either a single configuration or an object of configurations keyed by system attribute. Nix is not invoked in this mode."
+        )]
+        from_json: Option<String>,
+
+        #[arg(
+            long,
+            help = "Compare each evaluated configuration with the generation that is currently running on the host, and warn if they differ. In that case, the check results describe the configuration in the flake, not the running system."
+        )]
+        baseline_from_running: bool,
+
+        #[arg(
+            long,
+            help = "Show the current and the recommended value of the option behind each failing check, where the check knows them"
+        )]
+        diff_advice: bool,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Compare the results with a baseline saved by `nxbd --format json check > FILE`, and show which checks started or stopped failing since then, e.g. after a flake update"
+        )]
+        baseline: Option<String>,
+
+        #[arg(long, help = FAIL_ON_WARN_HELP)]
+        fail_on_warn: bool,
+
+        #[arg(long, help = KEEP_GOING_HELP)]
+        keep_going: bool,
+
+        #[command(flatten)]
+        connection: ConnectionArgs,
     },
 
     #[command(about = "List all available configuration checks")]
     #[command(
         long_about = "List all available configuration checks along with all their descriptions."
     )]
-    Checks,
+    Checks {
+        #[arg(
+            long,
+            value_name = "GROUP.CHECK",
+            help = "Only explain this check, e.g. `remote_deployment.user_access`, including the configuration attributes it inspects"
+        )]
+        explain: Option<String>,
+    },
+
+    #[command(
+        about = "Check that NixOS configurations evaluate, without building or checking them"
+    )]
+    #[command(
+        long_about = "Evaluate the derivation of one or more NixOS systems and report the \
+        evaluation errors. Nothing is built and the configuration checks don't run, so this is \
+        a fast gate for CI that catches syntax and evaluation errors."
+    )]
+    Validate {
+        #[arg(help = &format!("{} {}", SYSTEMS_HELP, SYSTEMS_ALL_HELP))]
+        #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
+        systems: Vec<libnxbd::FlakeReference>,
+
+        #[arg(long, value_name = "ATTR", help = EXCLUDE_HELP)]
+        exclude: Vec<String>,
+    },
+
+    #[command(about = "Print the evaluated configuration attributes of a system")]
+    #[command(
+        long_about = "Evaluate a NixOS configuration and print all attributes that nxbd extracts \
+        from it as JSON. The checks and deployments work on exactly these values, so this is \
+        useful to find out why a check reports what it does. The output can be passed to \
+        `nxbd check --from-json`."
+    )]
+    Inspect {
+        #[arg(help = SYSTEMS_HELP)]
+        #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
+        system: libnxbd::FlakeReference,
+    },
+
+    #[command(about = "Open an ssh session on the host of a system")]
+    #[command(
+        long_about = "Connect to the host of a system via ssh, with the same address, user and \
+        ssh options that deployments use. Arguments after the system are run as a command on \
+        the host instead of a login shell, e.g. `nxbd ssh .#web1 -- journalctl -u nginx`."
+    )]
+    Ssh {
+        #[arg(help = SYSTEMS_HELP)]
+        #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
+        system: libnxbd::FlakeReference,
+
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "COMMAND",
+            help = "Command to run on the host, followed by its arguments"
+        )]
+        args: Vec<String>,
+
+        #[command(flatten)]
+        connection: ConnectionArgs,
+    },
 
     #[command(about = "Show status of NixOS systems")]
     #[command(
@@ -140,6 +717,41 @@ If no system is specified, it uses the current hostname as the flake attribute s
         #[arg(help = &format!("{} {}", SYSTEMS_HELP, SYSTEMS_ALL_HELP))]
         #[arg(value_parser = libnxbd::flakeref::parse_flake_reference)]
         systems: Vec<libnxbd::FlakeReference>,
+
+        #[arg(long, value_name = "ATTR", help = EXCLUDE_HELP)]
+        exclude: Vec<String>,
+
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = libnxbd::humanize::parse_duration,
+            help = "Warn about hosts that have been up for longer than this, e.g. `90d` or `2w`"
+        )]
+        max_uptime: Option<u64>,
+
+        #[arg(
+            long,
+            requires = "max_uptime",
+            help = "Exit with an error if any host exceeds --max-uptime"
+        )]
+        fail_on_stale_uptime: bool,
+
+        #[arg(
+            long,
+            value_name = "LAYOUT",
+            default_value = "auto",
+            help = "How to show the hosts: `table` shows one line per host, `hosts` shows a block with all details per host, and `auto` uses the table for many hosts"
+        )]
+        layout: StatusLayout,
+
+        #[arg(
+            long,
+            help = "Also compare the SSH host keys of the hosts and report keys that several hosts share, e.g. because they were cloned from the same VM image without regenerating their host keys"
+        )]
+        host_keys: bool,
+
+        #[command(flatten)]
+        connection: ConnectionArgs,
     },
 
     #[command(hide = true)]
@@ -148,3 +760,98 @@ If no system is specified, it uses the current hostname as the flake attribute s
         output_dir: String,
     },
 }
+
+#[derive(Args, Debug)]
+pub struct ConnectionArgs {
+    #[arg(
+        long,
+        value_name = "USER",
+        help = "User to log in as on the target hosts instead of the local user. Commands that need root privileges are run via sudo or doas unless this is `root`, see `--privilege-escalation`."
+    )]
+    pub remote_user: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "TOOL",
+        default_value = "sudo",
+        help = "How commands that need root privileges are run on the target hosts. `none` runs them as the remote user. Neither sudo nor doas may ask for a password."
+    )]
+    pub privilege_escalation: PrivilegeEscalation,
+
+    #[arg(
+        long,
+        value_name = "OPTS",
+        env = "NXBD_SSH_OPTS",
+        allow_hyphen_values = true,
+        help = "Extra options for every ssh connection to the target hosts, including `nix copy`, e.g. `-p 2222 -i ~/.ssh/deploy_key`. They are added to the options from `NIX_SSHOPTS`."
+    )]
+    pub ssh_opts: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "10s",
+        value_parser = libnxbd::humanize::parse_duration,
+        help = "How long to wait for ssh connections to the target hosts, e.g. `10s`. Hosts that don't answer in time are reported as unreachable. `0s` waits until the TCP connection times out."
+    )]
+    pub connect_timeout: u64,
+
+    #[arg(
+        long,
+        value_name = "[ATTR=]ADDR",
+        help = "Connect to the system with attribute ATTR at ADDR instead of its `networking.fqdnOrHostName`, e.g. behind NAT. Without `ATTR=`, the address applies to the only selected system. Can be given multiple times."
+    )]
+    pub target_host: Vec<String>,
+}
+
+impl ConnectionArgs {
+    fn apply_project_config(&mut self, config: &ProjectConfig) {
+        if self.remote_user.is_none() {
+            self.remote_user.clone_from(&config.remote_user);
+        }
+        if self.ssh_opts.is_none() {
+            self.ssh_opts.clone_from(&config.ssh_opts);
+        }
+    }
+
+    pub fn target_hosts(&self) -> TargetHosts {
+        TargetHosts::parse(&self.target_host)
+    }
+
+    pub fn options(&self) -> ConnectionOptions {
+        ConnectionOptions {
+            user: self.remote_user.clone(),
+            // Like nix, every ssh connection uses the options from NIX_SSHOPTS
+            ssh_opts: ConnectionOptions::env_ssh_opts()
+                .into_iter()
+                .chain(
+                    self.ssh_opts
+                        .as_deref()
+                        .map(ConnectionOptions::parse_ssh_opts)
+                        .unwrap_or_default(),
+                )
+                .collect(),
+            connect_timeout: (self.connect_timeout > 0).then_some(self.connect_timeout),
+            privilege_escalation: self.privilege_escalation,
+            ..ConnectionOptions::default()
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusLayout {
+    Auto,
+    Table,
+    Hosts,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PlanFormat {
+    Json,
+}
@@ -1,16 +1,47 @@
+use super::nixcommands::PrivilegeEscalation;
 use super::FlakeReference;
 use super::{nixosattributes::ConfigInfo, userinfo::UserInfo};
 use serde::{Deserialize, Serialize};
 use serde_yaml;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::fs;
 use std::str::FromStr;
 
+/// The observed value of a NixOS option that made a check fail, along with
+/// the value the check recommends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub option: String,
+    pub current: String,
+    pub recommended: String,
+}
+
+impl Finding {
+    pub fn new(option: &str, current: impl fmt::Display, recommended: impl fmt::Display) -> Self {
+        Finding {
+            option: option.to_string(),
+            current: current.to_string(),
+            recommended: recommended.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: currently {}, recommended {}",
+            self.option, self.current, self.recommended
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct CheckError {
     pub check_name: String,
     pub message: String,
+    pub finding: Option<Finding>,
 }
 
 impl fmt::Display for CheckError {
@@ -19,29 +50,116 @@ impl fmt::Display for CheckError {
     }
 }
 
+/// How a failing check affects deployments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Severity {
+    /// Blocks deployments
+    #[default]
+    Error,
+    /// Only reported, unless warnings are treated as errors
+    Warning,
+    /// Reports a setting whatever its value, never blocks
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+            Self::Info => write!(f, "info"),
+        }
+    }
+}
+
 #[allow(clippy::struct_field_names, clippy::type_complexity)]
 pub struct Check {
     pub id: String,
     pub description: String,
     pub advice: String,
-    check_fn: Box<dyn Fn(&ConfigInfo, &UserInfo) -> Result<(), CheckError>>,
+    pub severity: Severity,
+    /// The `ConfigInfo` fields the check looks at
+    pub inspects: &'static [&'static str],
+    check_fn: Box<dyn Fn(&ConfigInfo, &UserInfo, &[String]) -> Result<(), CheckError>>,
+    warning_if: Option<Box<dyn Fn(&ConfigInfo, &UserInfo) -> bool>>,
 }
 
 impl Check {
     pub fn new<F>(id: &str, description: &str, advice: &str, check_fn: F) -> Self
     where
         F: Fn(&ConfigInfo, &UserInfo) -> Result<(), CheckError> + 'static,
+    {
+        Self::with_allowed(
+            id,
+            description,
+            advice,
+            move |config, user_info, _allowed| check_fn(config, user_info),
+        )
+    }
+
+    /// A check that accepts a list of allowed values, which are given as
+    /// `check_id:value` entries in the ignore map of the check's group
+    pub fn with_allowed<F>(id: &str, description: &str, advice: &str, check_fn: F) -> Self
+    where
+        F: Fn(&ConfigInfo, &UserInfo, &[String]) -> Result<(), CheckError> + 'static,
     {
         Check {
             id: id.to_string(),
             description: description.to_string(),
             advice: advice.to_string(),
+            severity: Severity::Error,
+            inspects: &[],
             check_fn: Box::new(check_fn),
+            warning_if: None,
         }
     }
 
-    pub fn check(&self, config: &ConfigInfo, user_info: &UserInfo) -> Result<(), CheckError> {
-        (self.check_fn)(config, user_info)
+    /// Documents which `ConfigInfo` fields the check looks at
+    #[must_use]
+    pub fn inspects(mut self, fields: &'static [&'static str]) -> Self {
+        self.inspects = fields;
+        self
+    }
+
+    /// Makes the check advisory, see `Severity::Warning`
+    #[must_use]
+    pub fn warning(mut self) -> Self {
+        self.severity = Severity::Warning;
+        self
+    }
+
+    /// Makes the check informational, see `Severity::Info`
+    #[must_use]
+    pub fn info(mut self) -> Self {
+        self.severity = Severity::Info;
+        self
+    }
+
+    /// Makes the check advisory for the systems and users `condition` holds for
+    #[must_use]
+    pub fn warning_if<F>(mut self, condition: F) -> Self
+    where
+        F: Fn(&ConfigInfo, &UserInfo) -> bool + 'static,
+    {
+        self.warning_if = Some(Box::new(condition));
+        self
+    }
+
+    /// The severity of the check for a system and user
+    pub fn severity_for(&self, config: &ConfigInfo, user_info: &UserInfo) -> Severity {
+        match &self.warning_if {
+            Some(condition) if condition(config, user_info) => Severity::Warning,
+            _ => self.severity,
+        }
+    }
+
+    pub fn check(
+        &self,
+        config: &ConfigInfo,
+        user_info: &UserInfo,
+        allowed: &[String],
+    ) -> Result<(), CheckError> {
+        (self.check_fn)(config, user_info, allowed)
     }
 }
 
@@ -59,6 +177,23 @@ pub struct CheckResult {
     pub advice: String,
     pub passed: bool,
     pub ignored: bool,
+    pub severity: Severity,
+    pub finding: Option<Finding>,
+    /// What the check reported if it didn't pass
+    pub message: Option<String>,
+}
+
+impl CheckResult {
+    /// Whether the check found a problem. Informational checks only report a
+    /// value, so they never fail.
+    pub fn is_failure(&self) -> bool {
+        !self.passed && self.severity != Severity::Info
+    }
+
+    /// Whether the result fails the run. Warnings only do if `fail_on_warn`.
+    pub fn blocks(&self, fail_on_warn: bool) -> bool {
+        self.is_failure() && !self.ignored && (fail_on_warn || self.severity == Severity::Error)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -69,6 +204,14 @@ pub struct CheckGroupResult {
     pub checks: Vec<CheckResult>,
 }
 
+impl CheckGroupResult {
+    pub fn all_passed_or_ignored(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| !check.is_failure() || check.ignored)
+    }
+}
+
 /// Map of group IDs to check IDs to ignore
 ///
 /// A key with an empty vector means "ignore all checks in this group"
@@ -99,6 +242,17 @@ impl IgnoreMap {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    /// The values of the `check:value` entries of a group, which checks
+    /// like `open_ports` treat as allowed instead of ignoring themselves
+    pub fn allowed_values(&self, group: &str, check: &str) -> Vec<String> {
+        self.get(group)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.strip_prefix(check)?.strip_prefix(':'))
+            .map(String::from)
+            .collect()
+    }
 }
 
 impl FromStr for IgnoreMap {
@@ -118,6 +272,37 @@ impl<'a> IntoIterator for &'a IgnoreMap {
     }
 }
 
+/// journald.conf settings that limit the disk space of the journal
+const JOURNALD_SPACE_SETTINGS: [&str; 3] = ["SystemMaxUse", "SystemMaxFileSize", "SystemKeepFree"];
+
+/// Ports the `open_ports` check always allows: SSH, HTTP and HTTPS
+const DEFAULT_ALLOWED_PORTS: [u16; 3] = [22, 80, 443];
+
+/// Longest boot menu timeout in seconds the `boot_loader_timeout` check
+/// accepts on servers. This is the NixOS default.
+const MAX_SERVER_BOOT_LOADER_TIMEOUT: i32 = 5;
+
+/// A public FQDN is the best hint we have that a host is reachable from the
+/// internet
+fn is_internet_facing(config: &ConfigInfo) -> bool {
+    config.fqdn.is_some()
+}
+
+/// The value of a setting in journald.conf text like
+/// `services.journald.extraConfig`. Like journald, the last assignment wins.
+fn journald_setting<'a>(config: &'a str, setting: &str) -> Option<&'a str> {
+    config
+        .lines()
+        .rev()
+        .find_map(|line| {
+            line.trim()
+                .strip_prefix(setting)?
+                .trim_start()
+                .strip_prefix('=')
+        })
+        .map(str::trim)
+}
+
 pub fn run_all_checks(
     config: &ConfigInfo,
     user_info: &UserInfo,
@@ -130,7 +315,15 @@ pub fn run_all_checks(
                 .checks
                 .iter()
                 .map(|check| {
-                    let passed = check.check(config, user_info).is_ok();
+                    let allowed = ignored_checks
+                        .map(|map| map.allowed_values(&group.id, &check.id))
+                        .unwrap_or_default();
+                    let result = check.check(config, user_info, &allowed);
+                    let passed = result.is_ok();
+                    let (finding, message) = match result {
+                        Ok(()) => (None, None),
+                        Err(error) => (error.finding, Some(error.message)),
+                    };
                     // A check is ignored if:
                     // 1. It's failed (not passed) AND
                     // 2. Either:
@@ -147,6 +340,9 @@ pub fn run_all_checks(
                         advice: check.advice.clone(),
                         passed,
                         ignored,
+                        severity: check.severity_for(config, user_info),
+                        finding,
+                        message,
                     }
                 })
                 .collect();
@@ -183,42 +379,81 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "SSH".to_string(),
                                 message: "SSH service is not enabled".to_string(),
+                                finding: Some(Finding::new("services.openssh.enable", false, true)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["ssh_enabled"]),
                 Check::new(
                     "sudo_enabled",
                     "Sudo must be available",
                     "Set `security.sudo.enable = true`",
-                    |config, _user_info| {
-                        if !config.sudo_enabled {
+                    |config, user_info| {
+                        if user_info.privilege_escalation == PrivilegeEscalation::Sudo
+                            && !config.sudo_enabled
+                        {
                             Err(CheckError {
                                 check_name: "Sudo".to_string(),
                                 message: "Sudo is not enabled".to_string(),
+                                finding: Some(Finding::new("security.sudo.enable", false, true)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["sudo_enabled"]),
+                Check::new(
+                    "doas_enabled",
+                    "Doas must be available when deploying with `--privilege-escalation doas`",
+                    "Set `security.doas.enable = true`",
+                    |config, user_info| {
+                        if user_info.privilege_escalation == PrivilegeEscalation::Doas
+                            && !config.doas_enabled
+                        {
+                            Err(CheckError {
+                                check_name: "Doas".to_string(),
+                                message: "Doas is not enabled".to_string(),
+                                finding: Some(Finding::new("security.doas.enable", false, true)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["doas_enabled"]),
                 Check::new(
                     "wheel_passwordless",
-                    "Wheel group should not require password for sudo",
-                    "Set  `security.sudo.wheelNeedsPassword = false`",
-                    |config, _user_info| {
-                        if config.wheel_needs_password {
+                    "Wheel group should not require password for sudo or doas",
+                    "Set  `security.sudo.wheelNeedsPassword = false`, or `security.doas.wheelNeedsPassword = false` with doas",
+                    |config, user_info| {
+                        let (needs_password, option) = match user_info.privilege_escalation {
+                            PrivilegeEscalation::Sudo => {
+                                (config.wheel_needs_password, "security.sudo.wheelNeedsPassword")
+                            }
+                            PrivilegeEscalation::Doas => {
+                                (config.doas_wheel_needs_password, "security.doas.wheelNeedsPassword")
+                            }
+                            PrivilegeEscalation::None => return Ok(()),
+                        };
+                        if needs_password {
                             Err(CheckError {
                                 check_name: "Sudo Password".to_string(),
-                                message: "Wheel group members need password for sudo".to_string(),
+                                message: format!(
+                                    "Wheel group members need password for {}",
+                                    user_info.privilege_escalation
+                                ),
+                                finding: Some(Finding::new(option, true, false)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["wheel_needs_password", "doas_wheel_needs_password"]),
                 Check::new(
                     "nix_trusts_wheel",
                     "Wheel group must be trusted by Nix",
@@ -228,22 +463,25 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Nix Trust".to_string(),
                                 message: "`wheel` group is not trusted by nix".to_string(),
+                                finding: None,
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["nix_trusts_wheel"]),
                 Check::new(
                     "user_access",
                     "Current user must have SSH access",
                     "Add your SSH key to the user's authorized_keys",
                     |config, user_info| {
-                        let current_user = &user_info.username;
-                        match config.users.iter().find(|u| u.name == *current_user) {
+                        let current_user = user_info.remote_username();
+                        match config.users.iter().find(|u| u.name == current_user) {
                             None => Err(CheckError {
                                 check_name: "User Access".to_string(),
                                 message: format!("User '{}' does not exist on target system", current_user),
+                                finding: None,
                             }),
                             Some(user) => {
                                 let has_matching_key = user_info
@@ -258,6 +496,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                             "User '{}' exists but none of their local SSH keys are authorized",
                                             current_user
                                         ),
+                                        finding: None,
                                     })
                                 } else {
                                     Ok(())
@@ -265,26 +504,61 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             }
                         }
                     },
-                ),
+                )
+                .inspects(&["users"]),
+                Check::new(
+                    "user_key_type",
+                    "The deploying user's SSH keys should not use deprecated key types",
+                    "Create an ed25519 key with `ssh-keygen -t ed25519` and use it instead of DSA or RSA keys",
+                    |_config, user_info| {
+                        let deprecated: Vec<&str> = user_info
+                            .ssh_keys
+                            .iter()
+                            .map(|key| key.key_type.as_str())
+                            .filter(|key_type| matches!(*key_type, "ssh-dss" | "ssh-rsa"))
+                            .collect();
+                        if !deprecated.is_empty() {
+                            Err(CheckError {
+                                check_name: "SSH Key Type".to_string(),
+                                message: format!(
+                                    "Local SSH keys use deprecated key types: {}",
+                                    deprecated.join(", ")
+                                ),
+                                finding: None,
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                // DSA keys are already disabled by default in OpenSSH, RSA keys will follow
+                .warning_if(|_config, user_info| {
+                    !user_info.ssh_keys.iter().any(|key| key.key_type == "ssh-dss")
+                }),
                 Check::new(
                     "user_in_wheel",
                     "Current user must be in wheel group",
                     "Add your user to the wheel group",
                     |config, user_info| {
-                        let current_user = &user_info.username;
-                        match config.users.iter().find(|u| u.name == *current_user) {
+                        let current_user = user_info.remote_username();
+                        match config.users.iter().find(|u| u.name == current_user) {
                             None => Err(CheckError {
                                 check_name: "Wheel Group".to_string(),
                                 message: format!("User '{}' does not exist on target system", current_user),
+                                finding: None,
                             }),
                             Some(user) => {
-                                if !user.extra_groups.contains(&"wheel".to_string()) {
+                                // root doesn't need sudo, so it doesn't need wheel either
+                                if user.name != "root"
+                                    && !user.extra_groups.contains(&"wheel".to_string())
+                                {
                                     Err(CheckError {
                                         check_name: "Wheel Group".to_string(),
                                         message: format!(
                                             "User '{}' is not in the wheel group",
                                             current_user
                                         ),
+                                        finding: None,
                                     })
                                 } else {
                                     Ok(())
@@ -292,7 +566,29 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             }
                         }
                     },
-                ),
+                )
+                .inspects(&["users"]),
+                Check::new(
+                    "wheel_user_has_key",
+                    "Some user in the wheel group must have an authorized SSH key if password authentication is disabled",
+                    "Add an SSH key to `users.users.<name>.openssh.authorizedKeys.keys` of a user in the wheel group",
+                    |config, _user_info| {
+                        let wheel_user_has_key = config.users.iter().any(|user| {
+                            user.extra_groups.contains(&"wheel".to_string())
+                                && !user.ssh_keys.is_empty()
+                        });
+                        if !config.ssh_password_authentication && !wheel_user_has_key {
+                            Err(CheckError {
+                                check_name: "Wheel SSH Keys".to_string(),
+                                message: "No user in the wheel group has an authorized SSH key, and SSH password authentication is disabled. Nobody could administrate the system after the deployment".to_string(),
+                                finding: None,
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["users", "ssh_password_authentication"]),
             ],
         },
         CheckGroup {
@@ -309,12 +605,14 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Sudo Wheel Only".to_string(),
                                 message: "Users outside wheel group can use sudo".to_string(),
+                                finding: Some(Finding::new("security.sudo.execWheelOnly", false, true)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["sudo_wheel_only"]),
                 Check::new(
                     "ssh_password_authentication",
                     "Password authentication should be disabled for SSH",
@@ -324,12 +622,14 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "SSH Password Auth".to_string(),
                                 message: "SSH password authentication is enabled. Consider disabling it and using only key-based authentication for better security".to_string(),
+                                finding: Some(Finding::new("services.openssh.settings.PasswordAuthentication", true, false)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["ssh_password_authentication"]),
                 Check::new(
                     "users_immutable",
                     "Users should be managed through NixOS configuration",
@@ -339,12 +639,31 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Mutable Users".to_string(),
                                 message: "Users can be modified outside of the NixOS configuration. Consider setting  `users.mutableUsers = false` for better system reproducibility".to_string(),
+                                finding: Some(Finding::new("users.mutableUsers", true, false)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["users_mutable"]),
+                Check::new(
+                    "passwordless_sudo_mutable_users",
+                    "Passwordless sudo for wheel requires users to be managed declaratively, as anyone who can add a local user to wheel would get passwordless root",
+                    "Set `users.mutableUsers = false`, or `security.sudo.wheelNeedsPassword = true`",
+                    |config, _user_info| {
+                        if config.users_mutable && !config.wheel_needs_password {
+                            Err(CheckError {
+                                check_name: "Passwordless Sudo".to_string(),
+                                message: "Wheel members can sudo without a password while users can be added outside of the NixOS configuration".to_string(),
+                                finding: Some(Finding::new("users.mutableUsers", true, false)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["users_mutable", "wheel_needs_password"]),
                 Check::new(
                     "firewall_enabled",
                     "The system firewall should be enabled for better security",
@@ -354,12 +673,101 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Firewall".to_string(),
                                 message: "System firewall is not enabled. Consider setting  `networking.firewall.enable = true`".to_string(),
+                                finding: Some(Finding::new("networking.firewall.enable", false, true)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["networking_firewall_enabled"]),
+                Check::new(
+                    "firewall_icmp",
+                    "Reports whether the firewall answers pings, as monitoring and health checks often rely on ICMP",
+                    "Set `networking.firewall.allowPing` explicitly to whether the host should answer pings",
+                    |config, _user_info| {
+                        if !config.networking_firewall_enabled {
+                            return Ok(());
+                        }
+                        let message = if config.firewall_allow_ping {
+                            "The firewall answers ICMP echo requests (`networking.firewall.allowPing = true`)"
+                        } else {
+                            "The firewall drops ICMP echo requests (`networking.firewall.allowPing = false`), so the host doesn't answer pings"
+                        };
+                        Err(CheckError {
+                            check_name: "Firewall ICMP".to_string(),
+                            message: message.to_string(),
+                            finding: None,
+                        })
+                    },
+                )
+                .inspects(&["networking_firewall_enabled", "firewall_allow_ping"])
+                .info(),
+                Check::new(
+                    "ssh_permit_root_login",
+                    "SSH should not accept root logins with a password",
+                    "Set `services.openssh.settings.PermitRootLogin = \"prohibit-password\"`, or `\"no\"` if nobody logs in as root",
+                    |config, _user_info| {
+                        if config.ssh_enabled && config.ssh_permit_root_login == "yes" {
+                            Err(CheckError {
+                                check_name: "SSH Root Login".to_string(),
+                                message: "SSH permits root logins, including with a password".to_string(),
+                                finding: Some(Finding::new("services.openssh.settings.PermitRootLogin", "yes", "prohibit-password")),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["ssh_enabled", "ssh_permit_root_login"])
+                // Deploying as root needs root logins, just not with a password
+                .warning_if(|_config, user_info| user_info.remote_username() == "root"),
+                Check::new(
+                    "fail2ban",
+                    "Internet-facing hosts with SSH enabled should run fail2ban to throttle brute-force login attempts",
+                    "Set `services.fail2ban.enable = true`",
+                    |config, _user_info| {
+                        if is_internet_facing(config) && config.ssh_enabled && !config.fail2ban_enabled {
+                            Err(CheckError {
+                                check_name: "Fail2ban".to_string(),
+                                message: "SSH is enabled on a host with a public FQDN, but fail2ban is not".to_string(),
+                                finding: Some(Finding::new("services.fail2ban.enable", false, true)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["fqdn", "ssh_enabled", "fail2ban_enabled"]),
+                Check::with_allowed(
+                    "open_ports",
+                    "Internet-facing hosts should only open the firewall for expected ports, so that debugging ports aren't left open in production",
+                    "Close the port in `networking.firewall.allowedTCPPorts`/`allowedUDPPorts`, or allow it by ignoring e.g. `system_security.open_ports:8080`",
+                    |config, _user_info, allowed| {
+                        let unexpected: Vec<String> = config
+                            .firewall_allowed_tcp_ports
+                            .iter()
+                            .map(|port| (port, "tcp"))
+                            .chain(config.firewall_allowed_udp_ports.iter().map(|port| (port, "udp")))
+                            .filter(|(port, _)| {
+                                !DEFAULT_ALLOWED_PORTS.contains(port)
+                                    && !allowed.contains(&port.to_string())
+                            })
+                            .map(|(port, protocol)| format!("{port}/{protocol}"))
+                            .collect();
+                        if is_internet_facing(config) && config.networking_firewall_enabled && !unexpected.is_empty() {
+                            Err(CheckError {
+                                check_name: "Open Ports".to_string(),
+                                message: format!("The firewall allows unexpected ports: {}", unexpected.join(", ")),
+                                finding: None,
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["firewall_allowed_tcp_ports", "firewall_allowed_udp_ports", "fqdn", "networking_firewall_enabled"])
+                .warning(),
                 Check::new(
                     "log_refused_connections",
                     "The logging of refused connections should be deactivated to avoid flooding the logs and possibly leaving important messages unseen. Consider using it only for debugging firewall rules.",
@@ -369,12 +777,14 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Log refused connections".to_string(),
                                 message: "Logging of refused connections should be disabled. Consider setting  `networking.firewall.logRefusedConnections = false`".to_string(),
+                                finding: Some(Finding::new("networking.firewall.logRefusedConnections", true, false)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["log_refused_connections"]),
             ],
         },
         CheckGroup {
@@ -387,7 +797,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                     "The retention of old system generations should be limited, as these are protected from garbage collection and consume disk space unnecessarily.",
                     "Set `boot.systemd.generations = 10` or less for systemd-boot, or `boot.grub.generations = 10` or less for GRUB",
                     |config, _user_info| {
-                        fn check_generations(enabled: bool, limit: Option<i32>, bootloader: &str) -> Result<(), CheckError> {
+                        fn check_generations(enabled: bool, limit: Option<i32>, bootloader: &str, option: &str) -> Result<(), CheckError> {
                             if !enabled {
                                 return Ok(());
                             }
@@ -398,6 +808,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                         "Too many {} generations kept ({}). Consider reducing to 10 or less",
                                         bootloader, limit
                                     ),
+                                    finding: Some(Finding::new(option, limit, 10)),
                                 }),
                                 None => Err(CheckError {
                                     check_name: "Boot Generations".to_string(),
@@ -405,15 +816,154 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                         "No {} generation limit set. This may prevent old generations from being garbage collected",
                                         bootloader
                                     ),
+                                    finding: Some(Finding::new(option, "null", 10)),
                                 }),
                                 _ => Ok(()),
                             }
                         }
 
-                        check_generations(config.boot_systemd, config.boot_systemd_generations, "systemd-boot")
-                            .or_else(|_| check_generations(config.boot_grub, config.boot_grub_generations, "GRUB"))
+                        // Containers don't have a bootloader of their own
+                        if config.boot_is_container {
+                            return Ok(());
+                        }
+                        check_generations(config.boot_systemd, config.boot_systemd_generations, "systemd-boot", "boot.loader.systemd-boot.configurationLimit")
+                            .or_else(|_| check_generations(config.boot_grub, config.boot_grub_generations, "GRUB", "boot.loader.grub.configurationLimit"))
+                    },
+                )
+                .inspects(&["boot_is_container", "boot_systemd", "boot_systemd_generations", "boot_grub", "boot_grub_generations"]),
+                Check::new(
+                    "state_version_set",
+                    "`system.stateVersion` should be set explicitly, so that nixpkgs updates don't silently change stateful defaults",
+                    "Set `system.stateVersion` to the NixOS release the system was first installed with",
+                    |config, _user_info| {
+                        if config.state_version_explicit == Some(false) {
+                            Err(CheckError {
+                                check_name: "State Version".to_string(),
+                                message: format!(
+                                    "`system.stateVersion` is not set and defaults to {}",
+                                    config.state_version.as_deref().unwrap_or("the nixpkgs release")
+                                ),
+                                finding: None,
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["state_version_explicit", "state_version"]),
+                Check::new(
+                    "tmp_cleanup",
+                    "/tmp should be cleaned on boot or be a tmpfs, as leftover files slowly fill the root disk",
+                    "Set `boot.tmp.cleanOnBoot = true`, or `boot.tmp.useTmpfs = true` if the system has enough memory",
+                    |config, _user_info| {
+                        if !config.tmp_clean_on_boot && !config.tmp_on_tmpfs {
+                            Err(CheckError {
+                                check_name: "Tmp Cleanup".to_string(),
+                                message: "/tmp is neither cleaned on boot nor a tmpfs. Consider setting `boot.tmp.cleanOnBoot = true`".to_string(),
+                                finding: Some(Finding::new("boot.tmp.cleanOnBoot", false, true)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["tmp_clean_on_boot", "tmp_on_tmpfs"]),
+                Check::new(
+                    "journald_space",
+                    "The space that the journal may use should be limited, so that logs don't fill the disk",
+                    "Set `SystemMaxUse=`, `SystemMaxFileSize=` and `SystemKeepFree=` in `services.journald.extraConfig`",
+                    |config, _user_info| {
+                        let missing: Vec<&str> = JOURNALD_SPACE_SETTINGS
+                            .into_iter()
+                            .filter(|setting| !config.journald_extra_config.contains(&format!("{setting}=")))
+                            .collect();
+                        if !missing.is_empty() {
+                            Err(CheckError {
+                                check_name: "Journald Space Management".to_string(),
+                                message: format!(
+                                    "The journal has no space limits for {}",
+                                    missing.join(", ")
+                                ),
+                                finding: None,
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["journald_extra_config"]),
+                Check::new(
+                    "journald_storage",
+                    "Servers should keep their journal on disk, as logs in volatile storage are lost on reboot, right when they are needed to find out what happened",
+                    "Remove `Storage=volatile` from `services.journald.extraConfig`. If the logs must not touch the disk, e.g. to spare an SD card, forward them with `services.journald.upload` or `services.journald.forwardToSyslog` instead",
+                    |config, _user_info| {
+                        let storage = journald_setting(&config.journald_extra_config, "Storage");
+                        if config.fqdn.is_some() && storage == Some("volatile") {
+                            Err(CheckError {
+                                check_name: "Journald Storage".to_string(),
+                                message: "The journal is only kept in memory (`Storage=volatile`), so logs don't survive a reboot".to_string(),
+                                finding: None,
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["fqdn", "journald_extra_config"])
+                .warning(),
+                Check::new(
+                    "swap_configured",
+                    "Systems should have swap, so that memory pressure on small machines doesn't immediately lead to OOM kills",
+                    "Set `zramSwap.enable = true`, or add a swap device to `swapDevices`",
+                    |config, _user_info| {
+                        // Containers share the memory management of their host
+                        if !config.swap_configured && !config.boot_is_container {
+                            Err(CheckError {
+                                check_name: "Swap".to_string(),
+                                message: "Neither swap devices nor zram swap are configured. Consider setting `zramSwap.enable = true`".to_string(),
+                                finding: Some(Finding::new("zramSwap.enable", false, true)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["swap_configured", "boot_is_container"]),
+                Check::new(
+                    "time_sync",
+                    "Time synchronization should be enabled, as clock drift breaks TLS certificate validation and makes logs hard to correlate",
+                    "Set `services.timesyncd.enable = true`, which is the NixOS default, or use `services.chrony.enable` or `services.ntp.enable`",
+                    |config, _user_info| {
+                        // Containers use the clock of their host
+                        if !config.time_sync_enabled && !config.boot_is_container {
+                            Err(CheckError {
+                                check_name: "Time Sync".to_string(),
+                                message: "Neither timesyncd, chrony nor ntp is enabled. Consider setting `services.timesyncd.enable = true`".to_string(),
+                                finding: Some(Finding::new("services.timesyncd.enable", false, true)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["time_sync_enabled", "boot_is_container"]),
+                Check::new(
+                    "auto_upgrade_disabled",
+                    "Servers that are deployed with nxbd should not upgrade themselves, as automatic upgrades replace the deployed system with whatever the upgrade channel or flake provides",
+                    "Set `system.autoUpgrade.enable = false` when using nxbd for deployment",
+                    |config, _user_info| {
+                        if config.fqdn.is_some() && config.auto_upgrade_enabled {
+                            Err(CheckError {
+                                check_name: "Auto Upgrade".to_string(),
+                                message: "Automatic upgrades are enabled and may override deployments. Consider setting `system.autoUpgrade.enable = false`".to_string(),
+                                finding: Some(Finding::new("system.autoUpgrade.enable", true, false)),
+                            })
+                        } else {
+                            Ok(())
+                        }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "auto_upgrade_enabled"]),
                 Check::new(
                     "nix_gc",
                     "Regular Nix Garbage Collection should be enabled",
@@ -423,12 +973,14 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Garbage Collection".to_string(),
                                 message: "Garbage Collection is not enabled. Consider setting  `nix.gc.automatic = true`".to_string(),
+                                finding: Some(Finding::new("nix.gc.automatic", false, true)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["nix_gc"]),
                 Check::new(
                     "nix_optimise_automatic",
                     "Nix store optimisation should be enabled",
@@ -440,12 +992,14 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Nix store optimisation".to_string(),
                                 message: "Nix store optimisation is disabled. Set either `nix.settings.auto-optimise-store` or `nix.optimise.automatic`".to_string(),
+                                finding: Some(Finding::new("nix.optimise.automatic", false, true)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["boot_is_container", "nix_optimise_automatic", "nix_auto_optimise_store"]),
             ],
         },
         CheckGroup {
@@ -467,18 +1021,47 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Nix Features".to_string(),
                                 message: "Missing required nix feature 'nix-command'. Add it to experimental-features in nix.extraOptions".to_string(),
+                                finding: None,
                             })
                         } else if !features_line.contains("flakes")
                             && !config.nix_settings_experimental_features.contains("flakes") {
                             Err(CheckError {
                                 check_name: "Nix Features".to_string(),
                                 message: "Missing required nix feature 'flakes'. Add it to experimental-features in nix.extraOptions".to_string(),
+                                finding: None,
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["nix_extra_options", "nix_settings_experimental_features"]),
+                Check::new(
+                    "build_host_parallelism",
+                    "Build hosts should build in parallel. Only applies to systems with `nix.sshServe.enable` or the `build-host` tag in `system.nixos.tags`",
+                    "Set `nix.settings.max-jobs` to `auto` and `nix.settings.cores` to 0, or to the number of CPUs",
+                    |config, _user_info| {
+                        if !config.nix_build_host {
+                            Ok(())
+                        } else if matches!(config.nix_max_jobs.as_str(), "0" | "1") {
+                            Err(CheckError {
+                                check_name: "Build Parallelism".to_string(),
+                                message: format!("nix.settings.max-jobs is {}, so the build host runs at most one build at a time", config.nix_max_jobs),
+                                finding: Some(Finding::new("nix.settings.max-jobs", &config.nix_max_jobs, "auto")),
+                            })
+                        } else if config.nix_cores == 1 {
+                            Err(CheckError {
+                                check_name: "Build Parallelism".to_string(),
+                                message: "nix.settings.cores is 1, so every build on the build host uses a single core".to_string(),
+                                finding: Some(Finding::new("nix.settings.cores", 1, 0)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["nix_build_host", "nix_max_jobs", "nix_cores"])
+                .warning(),
             ],
         },
         CheckGroup {
@@ -496,6 +1079,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Documentation".to_string(),
                                     message: "NixOS documentation enabled. Consider setting  `documentation.nixos.enable = false`".to_string(),
+                                    finding: Some(Finding::new("documentation.nixos.enable", true, false)),
                                 })
                             } else {
                                 Ok(())
@@ -504,7 +1088,9 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "doc_nixos_enabled"])
+                .warning(),
                 Check::new(
                     "documentation",
                     "General documentation should be disabled to reduce system closure size",
@@ -515,6 +1101,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Documentation".to_string(),
                                     message: "General documentation enabled. Consider setting  `documentation.enable = false`".to_string(),
+                                    finding: Some(Finding::new("documentation.enable", true, false)),
                                 })
                             } else {
                                 Ok(())
@@ -523,7 +1110,9 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "doc_enable"])
+                .warning(),
                 Check::new(
                     "doc_dev",
                     "Development documentation should be disabled to reduce system closure size",
@@ -534,6 +1123,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Documentation".to_string(),
                                     message: "Development documentation enabled. Consider setting  `documentation.dev.enable = false`".to_string(),
+                                    finding: Some(Finding::new("documentation.dev.enable", true, false)),
                                 })
                             } else {
                                 Ok(())
@@ -542,7 +1132,9 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "doc_dev_enable"])
+                .warning(),
                 Check::new(
                     "doc_doc",
                     "Doc documentation should be disabled to reduce system closure size",
@@ -553,6 +1145,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Documentation".to_string(),
                                     message: "Doc documentation enabled. Consider setting  `documentation.doc.enable = false`".to_string(),
+                                    finding: Some(Finding::new("documentation.doc.enable", true, false)),
                                 })
                             } else {
                                 Ok(())
@@ -561,7 +1154,9 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "doc_doc_enable"])
+                .warning(),
                 Check::new(
                     "doc_info",
                     "Info documentation should be disabled to reduce system closure size",
@@ -572,6 +1167,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Documentation".to_string(),
                                     message: "Info documentation enabled. Consider setting  `documentation.info.enable = false`".to_string(),
+                                    finding: Some(Finding::new("documentation.info.enable", true, false)),
                                 })
                             } else {
                                 Ok(())
@@ -580,7 +1176,9 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "doc_info_enable"])
+                .warning(),
                 Check::new(
                     "doc_man",
                     "Man pages should be disabled to reduce system closure size",
@@ -591,6 +1189,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Documentation".to_string(),
                                     message: "Man pages enabled. Consider setting  `documentation.man.enable = false`".to_string(),
+                                    finding: Some(Finding::new("documentation.man.enable", true, false)),
                                 })
                             } else {
                                 Ok(())
@@ -599,7 +1198,27 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "doc_man_enable"])
+                .warning(),
+                Check::new(
+                    "man_generate_caches",
+                    "The man page index cache should not be generated on servers, as it bloats the system closure and slows down builds",
+                    "Set  `documentation.man.generateCaches = false`",
+                    |config, _user_info| {
+                        if config.man_generate_caches && (config.fqdn.is_some() || !config.doc_man_enable) {
+                            Err(CheckError {
+                                check_name: "Documentation".to_string(),
+                                message: "The man page index cache is generated. Consider setting  `documentation.man.generateCaches = false`".to_string(),
+                                finding: Some(Finding::new("documentation.man.generateCaches", true, false)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["man_generate_caches", "fqdn", "doc_man_enable"])
+                .warning(),
                 Check::new(
                     "fontconfig",
                     "Font configuration should be disabled on servers to reduce system closure size",
@@ -609,12 +1228,33 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Font Configuration".to_string(),
                                 message: "Font configuration is enabled. Consider setting  `fonts.fontconfig.enable = false` on servers".to_string(),
+                                finding: Some(Finding::new("fonts.fontconfig.enable", true, false)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["fqdn", "font_fontconfig_enable"])
+                .warning(),
+                Check::new(
+                    "supported_locales",
+                    "Servers should not support all locales, which adds the complete glibc locale archive to the system closure",
+                    "Set `i18n.supportedLocales = [ \"en_US.UTF-8/UTF-8\" ]`, plus any other locales the server needs",
+                    |config, _user_info| {
+                        if config.fqdn.is_some() && config.i18n_supported_locales.iter().any(|locale| locale == "all") {
+                            Err(CheckError {
+                                check_name: "Supported Locales".to_string(),
+                                message: "All locales are supported. Consider restricting `i18n.supportedLocales` to the locales in use on servers".to_string(),
+                                finding: Some(Finding::new("i18n.supportedLocales", "[ \"all\" ]", "[ \"en_US.UTF-8/UTF-8\" ]")),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "i18n_supported_locales"])
+                .warning(),
                 Check::new(
                     "stub_ld",
                     "Stub-ld is typically not needed on servers and increases system closure size",
@@ -624,12 +1264,15 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Stub LD".to_string(),
                                 message: "Stub-ld is enabled but typically not needed on servers. Consider setting  `environment.stub-ld.enable = false` to reduce system closure size".to_string(),
+                                finding: Some(Finding::new("environment.stub-ld.enable", true, false)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "stub_ld"])
+                .warning(),
                 Check::new(
                     "command_not_found",
                     "The command-not-found program is typically not needed on servers and increases system closure size",
@@ -639,12 +1282,15 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Err(CheckError {
                                 check_name: "Command Not Found".to_string(),
                                 message: "The command-not-found program is enabled but typically not needed on servers. Consider setting  `programs.command-not-found.enable = false` to reduce system closure size".to_string(),
+                                finding: Some(Finding::new("programs.command-not-found.enable", true, false)),
                             })
                         } else {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["fqdn", "command_not_found"])
+                .warning(),
                 Check::new(
                     "nginx_brotli",
                     "Brotli compression should be enabled",
@@ -655,6 +1301,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Nginx Settings".to_string(),
                                     message: "Brotli compression not enabled. Consider setting  `services.nginx.recommendedBrotliSettings = true`".to_string(),
+                                    finding: Some(Finding::new("services.nginx.recommendedBrotliSettings", false, true)),
                                 })
                             } else {
                                 Ok(())
@@ -663,7 +1310,9 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["nginx_enabled", "nginx_brotli"])
+                .warning(),
                 Check::new(
                     "nginx_gzip",
                     "Gzip compression should be enabled",
@@ -674,6 +1323,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Nginx Settings".to_string(),
                                     message: "Gzip compression not enabled. Consider setting  `services.nginx.recommendedGzipSettings = true`".to_string(),
+                                    finding: Some(Finding::new("services.nginx.recommendedGzipSettings", false, true)),
                                 })
                             } else {
                                 Ok(())
@@ -682,7 +1332,9 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["nginx_enabled", "nginx_gzip"])
+                .warning(),
                 Check::new(
                     "nginx_optimisation",
                     "Optimisation settings should be enabled",
@@ -693,6 +1345,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Nginx Settings".to_string(),
                                     message: "Optimisation settings not enabled. Consider setting  `services.nginx.recommendedOptimisation = true`".to_string(),
+                                    finding: Some(Finding::new("services.nginx.recommendedOptimisation", false, true)),
                                 })
                             } else {
                                 Ok(())
@@ -701,7 +1354,9 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["nginx_enabled", "nginx_optimisation"])
+                .warning(),
                 Check::new(
                     "nginx_proxy",
                     "Proxy settings should be enabled",
@@ -712,6 +1367,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Nginx Settings".to_string(),
                                     message: "Proxy settings not enabled. Consider setting  `services.nginx.recommendedProxySettings = true`".to_string(),
+                                    finding: Some(Finding::new("services.nginx.recommendedProxySettings", false, true)),
                                 })
                             } else {
                                 Ok(())
@@ -720,7 +1376,9 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["nginx_enabled", "nginx_proxy"])
+                .warning(),
                 Check::new(
                     "nginx_tls",
                     "TLS settings should be enabled",
@@ -731,6 +1389,7 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                                 Err(CheckError {
                                     check_name: "Nginx Settings".to_string(),
                                     message: "TLS settings not enabled. Consider setting  `services.nginx.recommendedTlsSettings = true`".to_string(),
+                                    finding: Some(Finding::new("services.nginx.recommendedTlsSettings", false, true)),
                                 })
                             } else {
                                 Ok(())
@@ -739,7 +1398,34 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["nginx_enabled", "nginx_tls"])
+                .warning(),
+                Check::new(
+                    "boot_loader_timeout",
+                    "Servers should boot without waiting long in the boot menu, so unattended reboots are fast",
+                    "Set `boot.loader.timeout` to a few seconds, e.g. `boot.loader.timeout = 2`",
+                    |config, _user_info| {
+                        if config.fqdn.is_none() || config.boot_is_container {
+                            return Ok(());
+                        }
+                        match config.boot_loader_timeout {
+                            None => Err(CheckError {
+                                check_name: "Boot Loader Timeout".to_string(),
+                                message: "boot.loader.timeout is null, so the boot menu waits until someone selects an entry".to_string(),
+                                finding: Some(Finding::new("boot.loader.timeout", "null", 2)),
+                            }),
+                            Some(timeout) if timeout > MAX_SERVER_BOOT_LOADER_TIMEOUT => Err(CheckError {
+                                check_name: "Boot Loader Timeout".to_string(),
+                                message: format!("The boot menu waits {timeout} seconds on every reboot"),
+                                finding: Some(Finding::new("boot.loader.timeout", timeout, 2)),
+                            }),
+                            Some(_) => Ok(()),
+                        }
+                    },
+                )
+                .inspects(&["fqdn", "boot_is_container", "boot_loader_timeout"])
+                .warning(),
             ],
         },
         CheckGroup {
@@ -752,11 +1438,13 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                     "CPU microcode updates should be enabled on Intel architecture",
                     "Set either `hardware.cpu.intel.updateMicrocode` or `hardware.cpu.amd.updateMicrocode`",
                     |config, _user_info| {
-                        if config.is_x86 {
+                        // Containers run on the kernel and microcode of their host
+                        if config.is_x86 && !config.boot_is_container {
                             if !config.intel_microcode && !config.amd_microcode {
                                 Err(CheckError {
                                     check_name: "Microcode".to_string(),
                                     message: "No CPU microcode updates enabled. Set either `hardware.cpu.intel.updateMicrocode` or `hardware.cpu.amd.updateMicrocode` to `true`".to_string(),
+                                    finding: None,
                                 })
                             } else {
                                 Ok(())
@@ -765,7 +1453,49 @@ pub fn get_standard_checks() -> Vec<CheckGroup> {
                             Ok(())
                         }
                     },
-                ),
+                )
+                .inspects(&["is_x86", "boot_is_container", "intel_microcode", "amd_microcode"]),
+                Check::new(
+                    "firmware_appropriate",
+                    "Firmware blobs should match the machine: virtual machines and containers don't need them, while bare metal often needs redistributable firmware for wifi, GPUs and other devices",
+                    "On virtual machines set `hardware.enableAllFirmware = false`, on bare metal set `hardware.enableRedistributableFirmware = true`",
+                    |config, _user_info| {
+                        if config.is_virtual && config.firmware_all {
+                            Err(CheckError {
+                                check_name: "Firmware".to_string(),
+                                message: "`hardware.enableAllFirmware` is enabled on a virtual machine or container, which adds firmware blobs to the closure that it can't use".to_string(),
+                                finding: Some(Finding::new("hardware.enableAllFirmware", true, false)),
+                            })
+                        } else if !config.is_virtual && !config.firmware_redistributable && !config.firmware_all {
+                            Err(CheckError {
+                                check_name: "Firmware".to_string(),
+                                message: "Redistributable firmware is disabled on bare metal, which may break wifi, GPUs or other devices. Consider setting `hardware.enableRedistributableFirmware = true`".to_string(),
+                                finding: Some(Finding::new("hardware.enableRedistributableFirmware", false, true)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["is_virtual", "firmware_all", "firmware_redistributable"]),
+                Check::new(
+                    "predictable_interface_names",
+                    "Servers should use predictable network interface names, so that interface names and the firewall rules that refer to them survive NIC changes",
+                    "Remove `networking.usePredictableInterfaceNames = false` or set it to `true`",
+                    |config, _user_info| {
+                        if config.fqdn.is_some() && !config.predictable_interface_names {
+                            Err(CheckError {
+                                check_name: "Interface Names".to_string(),
+                                message: "Predictable interface names are disabled, so interfaces may be renamed when NICs are added or replaced".to_string(),
+                                finding: Some(Finding::new("networking.usePredictableInterfaceNames", false, true)),
+                            })
+                        } else {
+                            Ok(())
+                        }
+                    },
+                )
+                .inspects(&["fqdn", "predictable_interface_names"])
+                .warning(),
             ],
         },
     ]
@@ -807,24 +1537,11 @@ pub fn save_failed_checks_to_ignore_file(
 
     // Update map with new results
     for (system, results) in system_results {
-        let mut system_map_inner = HashMap::new();
-
-        for group in results {
-            let failed_checks: Vec<String> = group
-                .checks
-                .iter()
-                .filter(|check| !check.passed)
-                .map(|check| check.id.clone())
-                .collect();
-
-            if !failed_checks.is_empty() {
-                system_map_inner.insert(group.id.clone(), failed_checks);
-            }
-        }
+        let system_map = failed_checks_ignore_map(ignore_map.get(&system.attribute), results);
 
-        if !system_map_inner.is_empty() {
+        if !system_map.is_empty() {
             // Replace or insert the system's ignored checks
-            ignore_map.insert(system.attribute.clone(), IgnoreMap(system_map_inner));
+            ignore_map.insert(system.attribute.clone(), system_map);
         } else {
             // If no failures for this system, remove it from ignored checks
             ignore_map.remove(&system.attribute);
@@ -839,6 +1556,36 @@ pub fn save_failed_checks_to_ignore_file(
     Ok(())
 }
 
+/// The ignore map of a system that ignores its failed checks. Allowed values
+/// like `open_ports:8080` are kept from the previous ignore map, as they
+/// don't show up as failures.
+fn failed_checks_ignore_map(
+    previous: Option<&IgnoreMap>,
+    results: &[CheckGroupResult],
+) -> IgnoreMap {
+    let mut system_map = IgnoreMap::new();
+    for group in results {
+        let allowed = previous
+            .and_then(|map| map.get(&group.id))
+            .into_iter()
+            .flatten()
+            .filter(|entry| entry.contains(':'))
+            .cloned();
+        let entries: Vec<String> = group
+            .checks
+            .iter()
+            .filter(|check| check.is_failure())
+            .map(|check| check.id.clone())
+            .chain(allowed)
+            .collect();
+
+        if !entries.is_empty() {
+            system_map.insert(group.id.clone(), entries);
+        }
+    }
+    system_map
+}
+
 pub fn load_ignored_checks(path: &str) -> Option<HashMap<String, IgnoreMap>> {
     match fs::read_to_string(path) {
         Ok(contents) => serde_yaml::from_str(&contents).ok(),
@@ -846,6 +1593,112 @@ pub fn load_ignored_checks(path: &str) -> Option<HashMap<String, IgnoreMap>> {
     }
 }
 
+/// Key in ignore files whose ignored checks apply to all systems
+pub const ALL_SYSTEMS_KEY: &str = "*";
+
+/// The ignored checks of a system: its own entry merged with the `"*"` entry
+/// for all systems. Like all merges, this is a union, so a system's entry can
+/// add checks to the global ones but can't re-enable them.
+pub fn ignore_map_for_system(map: &HashMap<String, IgnoreMap>, system: &str) -> Option<IgnoreMap> {
+    match (map.get(ALL_SYSTEMS_KEY), map.get(system)) {
+        (Some(global), Some(own)) => Some(merge_ignore_maps(global, own)),
+        (global, own) => global.or(own).cloned(),
+    }
+}
+
+/// Loads several ignore files and merges them in order, see
+/// `merge_system_ignore_maps`. Missing or unreadable files are skipped.
+pub fn load_ignore_files(paths: &[String]) -> Option<HashMap<String, IgnoreMap>> {
+    let maps: Vec<_> = paths
+        .iter()
+        .filter_map(|path| load_ignored_checks(path))
+        .collect();
+    if maps.is_empty() {
+        None
+    } else {
+        Some(merge_system_ignore_maps(maps))
+    }
+}
+
+/// Merges per-system ignore maps from left to right.
+///
+/// Systems that appear in several maps get their ignore maps combined with
+/// `merge_ignore_maps`, so wildcards win and specific checks are united.
+pub fn merge_system_ignore_maps(
+    maps: impl IntoIterator<Item = HashMap<String, IgnoreMap>>,
+) -> HashMap<String, IgnoreMap> {
+    let mut result: HashMap<String, IgnoreMap> = HashMap::new();
+    for map in maps {
+        for (system, ignore_map) in map {
+            let merged = match result.get(&system) {
+                Some(existing) => merge_ignore_maps(existing, &ignore_map),
+                None => ignore_map,
+            };
+            result.insert(system, merged);
+        }
+    }
+    result
+}
+
+/// Failing checks per system attribute as `(group, check)` pairs, as printed
+/// by `check --format json`
+pub type CheckBaseline = BTreeMap<String, Vec<(String, String)>>;
+
+/// Parses a baseline saved from `check --format json`. That prints nothing
+/// if all checks pass, so an empty baseline has no failures.
+pub fn parse_check_baseline(contents: &str) -> Result<CheckBaseline, String> {
+    if contents.trim().is_empty() {
+        return Ok(CheckBaseline::new());
+    }
+    serde_json::from_str(contents).map_err(|e| format!("Invalid JSON: {e}"))
+}
+
+/// How a system's check results changed since a baseline
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BaselineDiff {
+    /// Checks that fail now, but didn't in the baseline
+    pub newly_failing: Vec<(String, String)>,
+    /// Checks that failed in the baseline, but don't anymore
+    pub newly_passing: Vec<(String, String)>,
+}
+
+impl BaselineDiff {
+    pub fn is_empty(&self) -> bool {
+        self.newly_failing.is_empty() && self.newly_passing.is_empty()
+    }
+}
+
+/// Compares check results with the failures of the same system in a
+/// baseline. Like the baseline itself, only failures that fail the run count.
+pub fn diff_against_baseline(
+    baseline_failures: &[(String, String)],
+    results: &[CheckGroupResult],
+    fail_on_warn: bool,
+) -> BaselineDiff {
+    let failures: Vec<(String, String)> = results
+        .iter()
+        .flat_map(|group| {
+            group
+                .checks
+                .iter()
+                .filter(|check| check.blocks(fail_on_warn))
+                .map(|check| (group.id.clone(), check.id.clone()))
+        })
+        .collect();
+    BaselineDiff {
+        newly_failing: failures
+            .iter()
+            .filter(|failure| !baseline_failures.contains(failure))
+            .cloned()
+            .collect(),
+        newly_passing: baseline_failures
+            .iter()
+            .filter(|failure| !failures.contains(failure))
+            .cloned()
+            .collect(),
+    }
+}
+
 /// Error type for parse_ignore_string
 #[derive(Debug)]
 pub enum ParseIgnoreError {
@@ -985,7 +1838,13 @@ pub fn merge_ignore_maps(map1: &IgnoreMap, map2: &IgnoreMap) -> IgnoreMap {
 
 #[cfg(test)]
 mod tests {
-    use super::{merge_ignore_maps, parse_ignore_string, IgnoreMap, ParseIgnoreError};
+    use super::{
+        diff_against_baseline, failed_checks_ignore_map, ignore_map_for_system, merge_ignore_maps,
+        merge_system_ignore_maps, parse_check_baseline, parse_ignore_string, run_all_checks,
+        BaselineDiff, CheckGroupResult, CheckResult, Finding, IgnoreMap, ParseIgnoreError,
+        Severity,
+    };
+    use crate::libnxbd::nixcommands::PrivilegeEscalation;
     use crate::libnxbd::nixosattributes::ConfigInfo;
     use crate::libnxbd::nixosattributes::NixUser;
     use crate::libnxbd::sshkeys::SshKeyInfo;
@@ -993,13 +1852,14 @@ mod tests {
     use crate::run_system_checks;
     use std::collections::HashMap;
 
-    #[test]
-    fn test_run_system_checks_with_ignores() {
-        // Create a minimal ConfigInfo that will fail some checks
-        let config_info = ConfigInfo {
+    /// A configuration that fails the `ssh_enabled` and `cpu_microcode` checks
+    fn config_info() -> ConfigInfo {
+        ConfigInfo {
             ssh_enabled: false, // This will fail ssh_enabled check
             sudo_enabled: true,
             wheel_needs_password: false,
+            doas_enabled: false,
+            doas_wheel_needs_password: true,
             nix_trusts_wheel: true,
             users: vec![NixUser {
                 name: "testuser".to_string(),
@@ -1012,9 +1872,23 @@ mod tests {
             }],
             sudo_wheel_only: true,
             ssh_password_authentication: false,
+            ssh_permit_root_login: "prohibit-password".to_string(),
+            state_version: Some("24.11".to_string()),
+            state_version_explicit: Some(true),
+            swap_configured: true,
+            time_sync_enabled: true,
             users_mutable: false,
             networking_firewall_enabled: true,
+            fail2ban_enabled: false,
+            firewall_allow_ping: true,
+            firewall_allowed_tcp_ports: vec![22, 8080],
+            firewall_allowed_udp_ports: vec![],
+            firmware_all: false,
+            firmware_redistributable: true,
+            is_virtual: false,
             log_refused_connections: false,
+            man_generate_caches: false,
+            predictable_interface_names: false,
             boot_systemd: false,
             boot_grub: false,
             boot_systemd_generations: None,
@@ -1022,6 +1896,9 @@ mod tests {
             nix_gc: true,
             nix_optimise_automatic: true,
             nix_auto_optimise_store: false,
+            nix_build_host: false,
+            nix_cores: 0,
+            nix_max_jobs: "auto".to_string(),
             nix_extra_options: "".to_string(),
             nix_settings_experimental_features: "nix-command flakes".to_string(),
             fqdn: None,
@@ -1032,8 +1909,10 @@ mod tests {
             doc_info_enable: false,
             doc_man_enable: false,
             font_fontconfig_enable: false,
+            i18n_supported_locales: vec!["en_US.UTF-8/UTF-8".to_string()],
             stub_ld: false,
             command_not_found: false,
+            configuration_revision: None,
             nginx_enabled: false,
             nginx_brotli: false,
             nginx_gzip: false,
@@ -1041,19 +1920,38 @@ mod tests {
             nginx_proxy: false,
             nginx_tls: false,
             is_x86: true,
+            kernel: "/nix/store/test-kernel/bzImage".to_string(),
+            journald_extra_config: "SystemMaxUse=1G\nSystemMaxFileSize=100M\nSystemKeepFree=2G\n"
+                .to_string(),
             intel_microcode: false, // This will fail cpu_microcode check
             amd_microcode: false,
+            auto_upgrade_enabled: false,
             boot_is_container: false,
+            boot_loader_timeout: Some(5),
             host_name: "testhost".to_string(),
             system: "x86_64-linux".to_string(),
+            tmp_clean_on_boot: true,
+            tmp_on_tmpfs: false,
             toplevel_out: "/nix/store/test-path".to_string(),
             toplevel_drv: "/nix/store/test-drv.drv".to_string(),
             fqdn_or_host_name: "testhost".to_string(),
-        };
+        }
+    }
 
-        // Create a minimal UserInfo
-        let user_info = UserInfo {
+    /// The configuration of a server, which checks treat as reachable from
+    /// the internet
+    fn server_config_info() -> ConfigInfo {
+        ConfigInfo {
+            fqdn: Some("testhost.example.com".to_string()),
+            ..config_info()
+        }
+    }
+
+    fn user_info() -> UserInfo {
+        UserInfo {
             username: "testuser".to_string(),
+            remote_user: None,
+            privilege_escalation: PrivilegeEscalation::Sudo,
             ssh_keys: vec![SshKeyInfo {
                 key_type: "ssh-rsa".to_string(),
                 key_data: "AAAAB3NzaC1yc2EAAAADAQABAAABAQC".to_string(),
@@ -1062,10 +1960,21 @@ mod tests {
             system: "x86_64-linux".to_string(),
             extra_platforms: vec![],
             remote_builders: vec![],
-        };
+            binfmt_platforms: vec![],
+        }
+    }
+
+    fn check(group: &str, check: &str) -> (String, String) {
+        (group.to_string(), check.to_string())
+    }
+
+    #[test]
+    fn test_run_system_checks_with_ignores() {
+        let config_info = config_info();
+        let user_info = user_info();
 
         // Test 1: Without any ignores, we should have failures
-        let failures = run_system_checks(&config_info, &user_info, None).unwrap();
+        let failures = run_system_checks(&config_info, &user_info, None, false).unwrap();
         assert!(!failures.is_empty(), "Expected failures without ignores");
 
         // Verify specific failures: ssh_enabled and cpu_microcode
@@ -1095,7 +2004,7 @@ mod tests {
         );
 
         let failures_with_ignores =
-            run_system_checks(&config_info, &user_info, Some(&ignore_map)).unwrap();
+            run_system_checks(&config_info, &user_info, Some(&ignore_map), false).unwrap();
         assert!(
             failures_with_ignores.is_empty(),
             "Expected no failures with ignores, got: {:?}",
@@ -1112,7 +2021,7 @@ mod tests {
         );
 
         let failures_with_partial_ignores =
-            run_system_checks(&config_info, &user_info, Some(&partial_ignore_map)).unwrap();
+            run_system_checks(&config_info, &user_info, Some(&partial_ignore_map), false).unwrap();
         assert_eq!(
             failures_with_partial_ignores.len(),
             1,
@@ -1133,8 +2042,13 @@ mod tests {
         // Use empty vector to ignore all checks in hardware_configuration
         empty_vector_ignore_map.insert("hardware_configuration".to_string(), vec![]);
 
-        let failures_with_empty_vector =
-            run_system_checks(&config_info, &user_info, Some(&empty_vector_ignore_map)).unwrap();
+        let failures_with_empty_vector = run_system_checks(
+            &config_info,
+            &user_info,
+            Some(&empty_vector_ignore_map),
+            false,
+        )
+        .unwrap();
         assert_eq!(
             failures_with_empty_vector.len(),
             1,
@@ -1150,6 +2064,390 @@ mod tests {
             "Expected hardware_configuration.cpu_microcode to be ignored with empty vector in ignore map");
     }
 
+    #[test]
+    fn test_failing_checks_report_findings() {
+        let results = run_all_checks(&config_info(), &user_info(), None);
+        let ssh_enabled = results
+            .iter()
+            .flat_map(|group| &group.checks)
+            .find(|check| check.id == "ssh_enabled")
+            .unwrap();
+        assert_eq!(
+            ssh_enabled.finding,
+            Some(Finding::new("services.openssh.enable", false, true))
+        );
+    }
+
+    #[test]
+    fn test_tmp_cleanup() {
+        let tmp_cleanup = check("system_maintenance", "tmp_cleanup");
+        let failures = run_system_checks(&config_info(), &user_info(), None, false).unwrap();
+        assert!(!failures.contains(&tmp_cleanup));
+        let config_info = ConfigInfo {
+            tmp_clean_on_boot: false,
+            tmp_on_tmpfs: false,
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        assert!(failures.contains(&tmp_cleanup));
+    }
+
+    #[test]
+    fn test_firewall_icmp_is_informational() {
+        let firewall_icmp = |config_info: &ConfigInfo| {
+            run_all_checks(config_info, &user_info(), None)
+                .into_iter()
+                .flat_map(|group| group.checks)
+                .find(|check| check.id == "firewall_icmp")
+                .unwrap()
+        };
+
+        // The value is reported in both states, but never blocks
+        for allow_ping in [true, false] {
+            let config_info = ConfigInfo {
+                firewall_allow_ping: allow_ping,
+                ..config_info()
+            };
+            let result = firewall_icmp(&config_info);
+            assert_eq!(result.severity, Severity::Info);
+            assert!(result
+                .message
+                .as_deref()
+                .unwrap()
+                .contains(&format!("allowPing = {allow_ping}")));
+            assert!(!result.blocks(true));
+        }
+
+        let config_info = ConfigInfo {
+            networking_firewall_enabled: false,
+            ..config_info()
+        };
+        assert!(firewall_icmp(&config_info).passed);
+    }
+
+    #[test]
+    fn test_info_checks_count_as_passed() {
+        let ed25519_key = SshKeyInfo {
+            key_type: "ssh-ed25519".to_string(),
+            key_data: "AAAAC3NzaC1lZDI1NTE5AAAAI".to_string(),
+            comment: "test@example.com".to_string(),
+        };
+        let config_info = ConfigInfo {
+            ssh_enabled: true,
+            intel_microcode: true,
+            users: vec![NixUser {
+                name: "testuser".to_string(),
+                ssh_keys: vec![ed25519_key.clone()],
+                extra_groups: vec!["wheel".to_string()],
+            }],
+            ..config_info()
+        };
+        let user_info = UserInfo {
+            ssh_keys: vec![ed25519_key],
+            ..user_info()
+        };
+        let results = run_all_checks(&config_info, &user_info, None);
+        let not_passed: Vec<&str> = results
+            .iter()
+            .flat_map(|group| &group.checks)
+            .filter(|check| !check.passed)
+            .map(|check| check.id.as_str())
+            .collect();
+        assert_eq!(not_passed, ["firewall_icmp"]);
+        assert!(results.iter().all(CheckGroupResult::all_passed_or_ignored));
+    }
+
+    #[test]
+    fn test_root_login_with_password() {
+        let root_login = check("system_security", "ssh_permit_root_login");
+        let config_info = ConfigInfo {
+            ssh_enabled: true,
+            ssh_permit_root_login: "yes".to_string(),
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        assert!(failures.contains(&root_login));
+
+        // Deploying as root needs the password login
+        let root_user_info = UserInfo {
+            remote_user: Some("root".to_string()),
+            ..user_info()
+        };
+        let failures = run_system_checks(&config_info, &root_user_info, None, false).unwrap();
+        assert!(!failures.contains(&root_login));
+    }
+
+    #[test]
+    fn test_passwordless_sudo_mutable_users() {
+        let passwordless_sudo = check("system_security", "passwordless_sudo_mutable_users");
+        let failures = run_system_checks(&config_info(), &user_info(), None, false).unwrap();
+        assert!(!failures.contains(&passwordless_sudo));
+        let config_info = ConfigInfo {
+            users_mutable: true,
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        assert!(failures.contains(&passwordless_sudo));
+    }
+
+    #[test]
+    fn test_user_key_type() {
+        // RSA keys only warn
+        let key_type = check("remote_deployment", "user_key_type");
+        let failures = run_system_checks(&config_info(), &user_info(), None, false).unwrap();
+        assert!(!failures.contains(&key_type));
+        let failures = run_system_checks(&config_info(), &user_info(), None, true).unwrap();
+        assert!(failures.contains(&key_type));
+
+        // DSA keys block
+        let dsa_user_info = UserInfo {
+            ssh_keys: vec![SshKeyInfo {
+                key_type: "ssh-dss".to_string(),
+                key_data: "AAAAB3NzaC1kc3MAAACBAP".to_string(),
+                comment: "test@example.com".to_string(),
+            }],
+            ..user_info()
+        };
+        let failures = run_system_checks(&config_info(), &dsa_user_info, None, false).unwrap();
+        assert!(failures.contains(&key_type));
+    }
+
+    #[test]
+    fn test_open_ports() {
+        // Only public hosts are checked
+        let open_ports = check("system_security", "open_ports");
+        let failures = run_system_checks(&config_info(), &user_info(), None, true).unwrap();
+        assert!(!failures.contains(&open_ports));
+        let config_info = server_config_info();
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(failures.contains(&open_ports));
+
+        // Unless the unexpected ports are allowed
+        let allow_map = parse_ignore_string("system_security.open_ports:8080").unwrap();
+        let failures =
+            run_system_checks(&config_info, &user_info(), Some(&allow_map), true).unwrap();
+        assert!(!failures.contains(&open_ports));
+    }
+
+    #[test]
+    fn test_wheel_user_has_key() {
+        // Without password authentication, some wheel user needs a key
+        let wheel_key = check("remote_deployment", "wheel_user_has_key");
+        let failures = run_system_checks(&config_info(), &user_info(), None, false).unwrap();
+        assert!(!failures.contains(&wheel_key));
+        let config_info = ConfigInfo {
+            users: vec![NixUser {
+                name: "testuser".to_string(),
+                ssh_keys: vec![],
+                extra_groups: vec!["wheel".to_string()],
+            }],
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        assert!(failures.contains(&wheel_key));
+    }
+
+    #[test]
+    fn test_journald_space() {
+        // All journald space limits have to be set
+        let journald_space = check("system_maintenance", "journald_space");
+        let failures = run_system_checks(&config_info(), &user_info(), None, false).unwrap();
+        assert!(!failures.contains(&journald_space));
+        let config_info = ConfigInfo {
+            journald_extra_config: "SystemMaxUse=1G\n".to_string(),
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        assert!(failures.contains(&journald_space));
+    }
+
+    #[test]
+    fn test_predictable_interface_names() {
+        // Unpredictable interface names only matter on servers
+        let interface_names = check("hardware_configuration", "predictable_interface_names");
+        let failures = run_system_checks(&server_config_info(), &user_info(), None, true).unwrap();
+        assert!(failures.contains(&interface_names));
+        let failures = run_system_checks(&config_info(), &user_info(), None, true).unwrap();
+        assert!(!failures.contains(&interface_names));
+        let config_info = ConfigInfo {
+            predictable_interface_names: true,
+            ..server_config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(!failures.contains(&interface_names));
+    }
+
+    #[test]
+    fn test_time_sync() {
+        // Time synchronization is required, except in containers
+        let time_sync = check("system_maintenance", "time_sync");
+        let failures = run_system_checks(&config_info(), &user_info(), None, false).unwrap();
+        assert!(!failures.contains(&time_sync));
+        let config_info = ConfigInfo {
+            time_sync_enabled: false,
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        assert!(failures.contains(&time_sync));
+        let config_info = ConfigInfo {
+            boot_is_container: true,
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        assert!(!failures.contains(&time_sync));
+    }
+
+    #[test]
+    fn test_container_skips_boot_checks() {
+        // Bootloader and microcode checks don't apply to containers
+        let config_info = ConfigInfo {
+            boot_systemd: true,
+            boot_grub: true,
+            boot_grub_generations: None,
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        for check in ["cpu_microcode", "system_generations_limit"] {
+            assert!(failures.iter().any(|(_, id)| id == check));
+        }
+        let config_info = ConfigInfo {
+            boot_is_container: true,
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        for check in ["cpu_microcode", "system_generations_limit"] {
+            assert!(!failures.iter().any(|(_, id)| id == check));
+        }
+    }
+
+    #[test]
+    fn test_build_host_parallelism() {
+        // Build parallelism is only checked on build hosts
+        let parallelism = check("nix_configuration", "build_host_parallelism");
+        let config_info = ConfigInfo {
+            nix_max_jobs: "1".to_string(),
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(!failures.contains(&parallelism));
+        let config_info = ConfigInfo {
+            nix_build_host: true,
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(failures.contains(&parallelism));
+        let config_info = ConfigInfo {
+            nix_max_jobs: "8".to_string(),
+            nix_cores: 1,
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(failures.contains(&parallelism));
+        let config_info = ConfigInfo {
+            nix_cores: 0,
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(!failures.contains(&parallelism));
+    }
+
+    #[test]
+    fn test_boot_loader_timeout() {
+        // Long boot menu timeouts only matter on servers
+        let boot_timeout = check("server_optimization", "boot_loader_timeout");
+        let failures = run_system_checks(&server_config_info(), &user_info(), None, true).unwrap();
+        assert!(!failures.contains(&boot_timeout));
+        let config_info = ConfigInfo {
+            boot_loader_timeout: None,
+            ..server_config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(failures.contains(&boot_timeout));
+        let config_info = ConfigInfo {
+            boot_loader_timeout: Some(30),
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(failures.contains(&boot_timeout));
+        let config_info = ConfigInfo {
+            fqdn: None,
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(!failures.contains(&boot_timeout));
+    }
+
+    #[test]
+    fn test_supported_locales() {
+        // Supporting all locales only matters on servers
+        let locales = check("server_optimization", "supported_locales");
+        let config_info = ConfigInfo {
+            i18n_supported_locales: vec!["all".to_string()],
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(!failures.contains(&locales));
+        let config_info = ConfigInfo {
+            fqdn: Some("testhost.example.com".to_string()),
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(failures.contains(&locales));
+    }
+
+    #[test]
+    fn test_privilege_escalation_checks() {
+        // With doas, doas has to be set up instead of sudo
+        let sudo_enabled = check("remote_deployment", "sudo_enabled");
+        let doas_enabled = check("remote_deployment", "doas_enabled");
+        let wheel_passwordless = check("remote_deployment", "wheel_passwordless");
+        let config_info = ConfigInfo {
+            sudo_enabled: false,
+            ..config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        assert!(failures.contains(&sudo_enabled));
+        assert!(!failures.contains(&doas_enabled));
+        assert!(!failures.contains(&wheel_passwordless));
+        let doas_user_info = UserInfo {
+            privilege_escalation: PrivilegeEscalation::Doas,
+            ..user_info()
+        };
+        let failures = run_system_checks(&config_info, &doas_user_info, None, false).unwrap();
+        assert!(!failures.contains(&sudo_enabled));
+        assert!(failures.contains(&doas_enabled));
+        assert!(failures.contains(&wheel_passwordless));
+        let config_info = ConfigInfo {
+            doas_enabled: true,
+            doas_wheel_needs_password: false,
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &doas_user_info, None, false).unwrap();
+        assert!(!failures.contains(&doas_enabled));
+        assert!(!failures.contains(&wheel_passwordless));
+    }
+
+    #[test]
+    fn test_journald_storage() {
+        // Volatile journal storage is only a warning on servers
+        let journald_storage = check("system_maintenance", "journald_storage");
+        let config_info = ConfigInfo {
+            journald_extra_config: "Storage=persistent\n# Storage=auto\nStorage = volatile\n"
+                .to_string(),
+            ..server_config_info()
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, false).unwrap();
+        assert!(!failures.contains(&journald_storage));
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(failures.contains(&journald_storage));
+        let config_info = ConfigInfo {
+            fqdn: None,
+            ..config_info
+        };
+        let failures = run_system_checks(&config_info, &user_info(), None, true).unwrap();
+        assert!(!failures.contains(&journald_storage));
+    }
+
     #[test]
     fn test_parse_ignore_string() {
         // Test parsing a single group and check
@@ -1310,4 +2608,187 @@ mod tests {
         assert_eq!(merged.len(), 1);
         assert_eq!(merged.get("group1").unwrap().len(), 1);
     }
+
+    #[test]
+    fn test_ignore_map_for_system() {
+        let map: HashMap<String, IgnoreMap> = [
+            ("*", "documentation.*,system_security.fail2ban"),
+            ("host1", "system_security.ssh_password_auth,nix_config.*"),
+            ("host2", "documentation.doc_enable"),
+        ]
+        .iter()
+        .map(|(system, checks)| (system.to_string(), parse_ignore_string(checks).unwrap()))
+        .collect();
+
+        // Global and per-system entries are united
+        let host1 = ignore_map_for_system(&map, "host1").unwrap();
+        assert_eq!(host1.len(), 3);
+        assert_eq!(host1.get("documentation").unwrap().len(), 0);
+        let security = host1.get("system_security").unwrap();
+        assert_eq!(security.len(), 2);
+        assert!(security.contains(&"fail2ban".to_string()));
+        assert!(security.contains(&"ssh_password_auth".to_string()));
+        assert_eq!(host1.get("nix_config").unwrap().len(), 0);
+
+        // A global wildcard wins over a specific check of the system
+        let host2 = ignore_map_for_system(&map, "host2").unwrap();
+        assert_eq!(host2.get("documentation").unwrap().len(), 0);
+
+        // Systems without an entry get the global one
+        let host3 = ignore_map_for_system(&map, "host3").unwrap();
+        assert_eq!(host3.len(), 2);
+        assert_eq!(
+            host3.get("system_security").unwrap(),
+            &vec!["fail2ban".to_string()]
+        );
+
+        // Without a global entry, only the system's own entry applies
+        let mut map = map;
+        map.remove("*");
+        let host2 = ignore_map_for_system(&map, "host2").unwrap();
+        assert_eq!(
+            host2.get("documentation").unwrap(),
+            &vec!["doc_enable".to_string()]
+        );
+        assert!(ignore_map_for_system(&map, "host3").is_none());
+    }
+
+    #[test]
+    fn test_merge_system_ignore_maps() {
+        let system_map = |entries: &[(&str, &str)]| -> HashMap<String, IgnoreMap> {
+            entries
+                .iter()
+                .map(|(system, checks)| (system.to_string(), parse_ignore_string(checks).unwrap()))
+                .collect()
+        };
+
+        let base = system_map(&[
+            ("host1", "group1.check1,group2.check2"),
+            ("host2", "group1.check1"),
+        ]);
+        let team = system_map(&[
+            ("host1", "group1.check3,group2.*"),
+            ("host3", "group3.check1"),
+        ]);
+        let repo = system_map(&[
+            ("host1", "group1.check1,group3.check4"),
+            ("host2", "group1.*"),
+        ]);
+
+        let merged = merge_system_ignore_maps([base, team, repo]);
+        assert_eq!(merged.len(), 3);
+
+        // Union of specific checks across all three files
+        let host1 = merged.get("host1").unwrap();
+        assert_eq!(host1.len(), 3);
+        let group1 = host1.get("group1").unwrap();
+        assert_eq!(group1.len(), 2);
+        assert!(group1.contains(&"check1".to_string()));
+        assert!(group1.contains(&"check3".to_string()));
+        // Wildcard from the middle file wins over the specific check from the first
+        assert_eq!(host1.get("group2").unwrap().len(), 0);
+        assert_eq!(host1.get("group3").unwrap(), &vec!["check4".to_string()]);
+
+        // Wildcard from the last file wins as well
+        assert_eq!(merged.get("host2").unwrap().get("group1").unwrap().len(), 0);
+
+        // Systems only present in one file are copied
+        assert_eq!(
+            merged.get("host3").unwrap().get("group3").unwrap(),
+            &vec!["check1".to_string()]
+        );
+
+        assert!(merge_system_ignore_maps(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_failed_checks_ignore_map_keeps_allowed_values() {
+        let check = |id: &str, passed: bool| CheckResult {
+            id: id.to_string(),
+            description: String::new(),
+            advice: String::new(),
+            message: None,
+            passed,
+            ignored: false,
+            severity: Severity::Error,
+            finding: None,
+        };
+        let group = |id: &str, checks| CheckGroupResult {
+            id: id.to_string(),
+            name: String::new(),
+            description: String::new(),
+            checks,
+        };
+        let results = vec![
+            group(
+                "system_security",
+                vec![check("open_ports", true), check("firewall", false)],
+            ),
+            group("system_maintenance", vec![check("time_sync", true)]),
+        ];
+        let previous = parse_ignore_string(
+            "system_security.open_ports:8080,system_security.fail2ban,system_maintenance.journald_storage",
+        )
+        .unwrap();
+
+        // Failures replace the ignored checks, allowed values stay
+        let system_map = failed_checks_ignore_map(Some(&previous), &results);
+        assert_eq!(
+            system_map.get("system_security").unwrap(),
+            &vec!["firewall".to_string(), "open_ports:8080".to_string()]
+        );
+        assert!(system_map.get("system_maintenance").is_none());
+
+        let results = vec![group("system_security", vec![check("open_ports", true)])];
+        let system_map = failed_checks_ignore_map(Some(&previous), &results);
+        assert_eq!(
+            system_map.get("system_security").unwrap(),
+            &vec!["open_ports:8080".to_string()]
+        );
+        assert!(failed_checks_ignore_map(None, &results).is_empty());
+    }
+
+    #[test]
+    fn test_diff_against_baseline() {
+        let check = |id: &str, passed: bool, severity: Severity| CheckResult {
+            id: id.to_string(),
+            description: String::new(),
+            advice: String::new(),
+            message: None,
+            passed,
+            ignored: false,
+            severity,
+            finding: None,
+        };
+        let results = vec![CheckGroupResult {
+            id: "remote_deployment".to_string(),
+            name: String::new(),
+            description: String::new(),
+            checks: vec![
+                check("ssh_enabled", false, Severity::Error),
+                check("sudo_enabled", true, Severity::Error),
+                check("firewall", false, Severity::Warning),
+            ],
+        }];
+        let pair = |check: &str| ("remote_deployment".to_string(), check.to_string());
+
+        let baseline =
+            parse_check_baseline(r#"{"web1": [["remote_deployment", "sudo_enabled"]]}"#).unwrap();
+        assert_eq!(
+            diff_against_baseline(&baseline["web1"], &results, false),
+            BaselineDiff {
+                newly_failing: vec![pair("ssh_enabled")],
+                newly_passing: vec![pair("sudo_enabled")],
+            }
+        );
+        // Warnings only count with fail_on_warn, like in the baseline
+        assert_eq!(
+            diff_against_baseline(&[pair("ssh_enabled")], &results, true).newly_failing,
+            vec![pair("firewall")]
+        );
+        assert!(diff_against_baseline(&[pair("ssh_enabled")], &results, false).is_empty());
+
+        assert!(parse_check_baseline("\n").unwrap().is_empty());
+        assert!(parse_check_baseline("[]").is_err());
+    }
 }
@@ -0,0 +1,170 @@
+use super::nixcommands::{check_system_status, ActivationMode, ConnectionOptions, SystemStatus};
+use super::nixosattributes::ConfigInfo;
+use super::userinfo::UserInfo;
+use super::FlakeReference;
+use rayon::prelude::*;
+use serde::{Serialize, Serializer};
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildStrategy {
+    /// Built on this machine, natively or via emulation
+    Local,
+    /// Built by one of the configured remote builders
+    Builder,
+    /// The derivation is copied to the target host and built there
+    TargetHost,
+    /// The derivation is copied to the `--build-host` and built there
+    BuildHost,
+}
+
+impl BuildStrategy {
+    /// Whether the result is built from here and copied to the target host
+    pub fn is_local(self) -> bool {
+        matches!(self, Self::Local | Self::Builder)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedDeployment<'a> {
+    #[serde(serialize_with = "serialize_display")]
+    pub system: &'a FlakeReference,
+    pub host: &'a str,
+    pub platform: &'a str,
+    pub toplevel_out: &'a str,
+    pub build: BuildStrategy,
+    pub action: &'static str,
+    /// Whether the host already runs this system, `None` if it wasn't
+    /// queried or isn't reachable
+    pub up_to_date: Option<bool>,
+    #[serde(skip)]
+    pub info: &'a ConfigInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeployPlan<'a> {
+    pub deployments: Vec<PlannedDeployment<'a>>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PlanOptions {
+    pub local_build_then_copy: bool,
+    /// Query the target hosts for their running generation
+    pub query_hosts: bool,
+    pub connection: ConnectionOptions,
+    pub activation_mode: ActivationMode,
+    /// Builds all systems on this host instead
+    pub build_host: Option<String>,
+}
+
+fn serialize_display<S: Serializer>(
+    value: &impl Display,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn build_strategy(platform: &str, user_info: &UserInfo, options: &PlanOptions) -> BuildStrategy {
+    if options.build_host.is_some() {
+        BuildStrategy::BuildHost
+    } else if options.local_build_then_copy || user_info.can_build_locally(platform) {
+        BuildStrategy::Local
+    } else if user_info.can_build_natively(platform) {
+        BuildStrategy::Builder
+    } else {
+        BuildStrategy::TargetHost
+    }
+}
+
+/// Decides how every system is built and deployed, without running anything
+/// on the target hosts unless `options.query_hosts` is set. Systems that are
+/// built from here come first, as they are deployed first.
+pub fn plan<'a>(
+    systems: &[(&'a FlakeReference, &'a ConfigInfo)],
+    user_info: &UserInfo,
+    options: &PlanOptions,
+) -> DeployPlan<'a> {
+    let mut deployments: Vec<PlannedDeployment> = systems
+        .iter()
+        .map(|(system, info)| PlannedDeployment {
+            system,
+            host: &info.fqdn_or_host_name,
+            platform: &info.system,
+            toplevel_out: &info.toplevel_out,
+            build: build_strategy(&info.system, user_info, options),
+            action: options.activation_mode.as_str(),
+            up_to_date: None,
+            info,
+        })
+        .collect();
+    deployments.sort_by_key(|deployment| !deployment.build.is_local());
+
+    if options.query_hosts {
+        deployments.par_iter_mut().for_each(|deployment| {
+            let remote_host = options.connection.remote_host(deployment.host);
+            deployment.up_to_date = match check_system_status(Some(&remote_host), None) {
+                Ok(SystemStatus::Reachable {
+                    current_generation, ..
+                }) => Some(current_generation == deployment.toplevel_out),
+                _ => None,
+            };
+        });
+    }
+
+    DeployPlan { deployments }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libnxbd::nixcommands::{PrivilegeEscalation, RemoteBuilder};
+
+    #[test]
+    fn test_build_strategy() {
+        let user_info = UserInfo {
+            username: "user".to_string(),
+            remote_user: None,
+            privilege_escalation: PrivilegeEscalation::Sudo,
+            ssh_keys: vec![],
+            system: "x86_64-linux".to_string(),
+            extra_platforms: vec![],
+            remote_builders: vec![RemoteBuilder {
+                ssh_host: "builder".to_string(),
+                system: "aarch64-linux".to_string(),
+            }],
+            binfmt_platforms: vec![],
+        };
+        let default = PlanOptions::default();
+        let copy = PlanOptions {
+            local_build_then_copy: true,
+            ..PlanOptions::default()
+        };
+
+        assert_eq!(
+            build_strategy("x86_64-linux", &user_info, &default),
+            BuildStrategy::Local
+        );
+        assert_eq!(
+            build_strategy("aarch64-linux", &user_info, &default),
+            BuildStrategy::Builder
+        );
+        assert_eq!(
+            build_strategy("riscv64-linux", &user_info, &default),
+            BuildStrategy::TargetHost
+        );
+        assert_eq!(
+            build_strategy("riscv64-linux", &user_info, &copy),
+            BuildStrategy::Local
+        );
+        let build_host = PlanOptions {
+            build_host: Some("builder".to_string()),
+            ..PlanOptions::default()
+        };
+        assert_eq!(
+            build_strategy("x86_64-linux", &user_info, &build_host),
+            BuildStrategy::BuildHost
+        );
+    }
+}
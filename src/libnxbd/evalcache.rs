@@ -0,0 +1,75 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+/// On-disk cache of evaluation results in `$XDG_CACHE_HOME/nxbd`. Entries are
+/// keyed by a fingerprint of everything the evaluation depends on, so they
+/// never need to be invalidated explicitly.
+pub struct EvalCache {
+    dir: PathBuf,
+}
+
+impl EvalCache {
+    pub fn new(dir: PathBuf) -> Self {
+        EvalCache { dir }
+    }
+
+    /// The cache in the user's cache directory, `None` if there is none
+    pub fn from_env() -> Option<Self> {
+        let cache_home = env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(Self::new(cache_home.join("nxbd")))
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        fs::read_to_string(self.entry_path(key)).ok()
+    }
+
+    /// Stores an entry. Failing to write the cache only costs a future
+    /// evaluation, so errors are ignored.
+    pub fn put(&self, key: &str, contents: &str) {
+        let path = self.entry_path(key);
+        // Write to a temporary file first, so concurrent runs never read
+        // partially written entries
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        let _ = fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(&tmp_path, contents))
+            .and_then(|()| fs::rename(&tmp_path, &path));
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+}
+
+/// Combines the given parts into a cache key
+pub fn cache_key(parts: &[&str]) -> String {
+    let mut hasher = DefaultHasher::new();
+    parts.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let dir = env::temp_dir().join(format!("nxbd-evalcache-test-{}", std::process::id()));
+        let cache = EvalCache::new(dir.clone());
+        let key = cache_key(&[".", "web1", "sha256-abc"]);
+
+        assert_eq!(cache.get(&key), None);
+        cache.put(&key, "{\"hostName\":\"web1\"}");
+        assert_eq!(cache.get(&key).as_deref(), Some("{\"hostName\":\"web1\"}"));
+
+        assert_ne!(key, cache_key(&[".", "web1", "sha256-def"]));
+        assert_ne!(key, cache_key(&[".", "web", "1sha256-abc"]));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
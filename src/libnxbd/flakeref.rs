@@ -16,24 +16,18 @@ pub enum ParseError {
 impl FromStr for FlakeReference {
     type Err = ParseError;
 
+    /// Without a flake URL, as in `myhost` or `#myhost`, the flake in the
+    /// current directory is meant, like with `.#myhost`
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = input.split('#').collect();
-
-        match parts.len() {
-            0 => Ok(FlakeReference {
-                url: String::new(),
-                attribute: String::new(),
-            }),
-            1 => Ok(FlakeReference {
-                url: String::new(),
-                attribute: parts[0].to_string(),
-            }),
-            2 => Ok(FlakeReference {
-                url: parts[0].to_string(),
-                attribute: parts[1].to_string(),
-            }),
-            _ => Err(ParseError::MultipleHashSigns),
-        }
+        let (url, attribute) = match input.split('#').collect::<Vec<_>>()[..] {
+            [attribute] => ("", attribute),
+            [url, attribute] => (url, attribute),
+            _ => return Err(ParseError::MultipleHashSigns),
+        };
+        Ok(FlakeReference {
+            url: if url.is_empty() { "." } else { url }.to_string(),
+            attribute: attribute.to_string(),
+        })
     }
 }
 
@@ -55,6 +49,65 @@ pub fn parse_flake_reference(s: &str) -> Result<FlakeReference, String> {
     FlakeReference::from_str(s).map_err(|e| e.to_string())
 }
 
+/// The number of single character insertions, deletions and substitutions
+/// that turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The candidate that is closest to `name`, if it is close enough to assume
+/// that `name` is a typo of it
+pub fn closest_match<'a>(name: &str, candidates: &'a [String]) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+/// Matches a shell-style glob, where `*` matches any number of characters
+/// and `?` exactly one, against the whole of `text`
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was and how much of the text it consumes so far
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, consumed)) => {
+                    p = star + 1;
+                    t = consumed + 1;
+                    backtrack = Some((star, consumed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,7 +117,7 @@ mod tests {
     fn test_empty_string() {
         let parsed = FlakeReference::from_str("");
         let expected = Ok(FlakeReference {
-            url: "".to_string(),
+            url: ".".to_string(),
             attribute: "".to_string(),
         });
         assert_eq!(parsed, expected);
@@ -74,7 +127,7 @@ mod tests {
     fn test_no_flake_path() {
         let parsed = FlakeReference::from_str("bla");
         let expected = Ok(FlakeReference {
-            url: "".to_string(),
+            url: ".".to_string(),
             attribute: "bla".to_string(),
         });
         assert_eq!(parsed, expected);
@@ -94,12 +147,24 @@ mod tests {
     fn test_empty_flake_path_with_attribute() {
         let parsed = FlakeReference::from_str("#bar");
         let expected = Ok(FlakeReference {
-            url: "".to_string(),
+            url: ".".to_string(),
             attribute: "bar".to_string(),
         });
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn test_bare_attribute_equals_current_flake() {
+        assert_eq!(
+            FlakeReference::from_str("myhost"),
+            FlakeReference::from_str(".#myhost")
+        );
+        assert_eq!(
+            FlakeReference::from_str("myhost").unwrap().to_string(),
+            ".#myhost"
+        );
+    }
+
     #[test]
     fn test_flake_path_with_empty_attribute() {
         let parsed = FlakeReference::from_str("foo#");
@@ -116,4 +181,32 @@ mod tests {
         let expected = Err(ParseError::MultipleHashSigns);
         assert_eq!(parsed, expected);
     }
+
+    #[test]
+    fn test_closest_match() {
+        let candidates = vec![
+            "web1".to_string(),
+            "web2".to_string(),
+            "database".to_string(),
+        ];
+        assert_eq!(edit_distance("databse", "database"), 1);
+        assert_eq!(edit_distance("", "web"), 3);
+        assert_eq!(closest_match("databse", &candidates), Some("database"));
+        assert_eq!(closest_match("web3", &candidates), Some("web1"));
+        assert_eq!(closest_match("mail", &candidates), None);
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("web-*", "web-1"));
+        assert!(glob_matches("web-*", "web-"));
+        assert!(!glob_matches("web-*", "db-1"));
+        assert!(glob_matches("*-prod", "web-1-prod"));
+        assert!(glob_matches("web-?", "web-1"));
+        assert!(!glob_matches("web-?", "web-12"));
+        assert!(glob_matches("*b*-*", "web-1"));
+        assert!(glob_matches("web1", "web1"));
+        assert!(!glob_matches("web", "web1"));
+        assert!(glob_matches("*", ""));
+    }
 }
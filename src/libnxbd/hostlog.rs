@@ -0,0 +1,265 @@
+use super::runlog;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogMode {
+    /// Collect the lines of a host and print them as one block when the host
+    /// is done
+    Buffered,
+    /// Print every line immediately, prefixed with the host name
+    Prefixed,
+}
+
+/// Keeps the output of operations that run for several hosts in parallel
+/// readable. Every host gets its own `HostLog`, whose lines are either
+/// flushed as a contiguous block or streamed with a host prefix.
+pub struct HostLogger {
+    mode: LogMode,
+    /// Whether a status line can be updated in place
+    terminal: bool,
+    output: Mutex<Output>,
+}
+
+struct Output {
+    writer: Box<dyn Write + Send>,
+    /// The host and text of the status line below the log lines, which is
+    /// redrawn after every write
+    status: Option<(String, String)>,
+}
+
+impl HostLogger {
+    pub fn new(mode: LogMode) -> Self {
+        Self {
+            terminal: io::stderr().is_terminal(),
+            ..Self::with_writer(mode, io::stderr())
+        }
+    }
+
+    pub fn with_writer(mode: LogMode, writer: impl Write + Send + 'static) -> Self {
+        Self {
+            mode,
+            terminal: false,
+            output: Mutex::new(Output {
+                writer: Box::new(writer),
+                status: None,
+            }),
+        }
+    }
+
+    /// Prefixed streaming is meant for `--verbose`, where seeing progress
+    /// immediately matters more than grouping.
+    pub fn from_verbose(verbose: bool) -> Self {
+        Self::new(if verbose {
+            LogMode::Prefixed
+        } else {
+            LogMode::Buffered
+        })
+    }
+
+    pub fn for_host(&self, host: &str) -> HostLog<'_> {
+        HostLog {
+            logger: self,
+            host: host.to_string(),
+            lines: RefCell::default(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Output> {
+        self.output.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_lines(&self, lines: &[String]) {
+        let mut output = self.lock();
+        let Output { writer, status } = &mut *output;
+        // Losing log output is not worth aborting a deployment for
+        if status.is_some() {
+            let _ = write!(writer, "\r\x1b[K");
+        }
+        for line in lines {
+            let _ = writeln!(writer, "{line}");
+            runlog::log(line);
+        }
+        if let Some((_, status)) = status {
+            let _ = write!(writer, "{status}");
+        }
+        let _ = writer.flush();
+    }
+}
+
+/// The log of one host. Buffered lines are flushed when it is dropped, so a
+/// host's output also shows up if its operation fails early.
+pub struct HostLog<'a> {
+    logger: &'a HostLogger,
+    host: String,
+    lines: RefCell<Vec<String>>,
+}
+
+impl HostLog<'_> {
+    pub fn log(&self, message: impl fmt::Display) {
+        match self.logger.mode {
+            LogMode::Buffered => self.lines.borrow_mut().push(format!("  {message}")),
+            LogMode::Prefixed => self
+                .logger
+                .write_lines(&[format!("[{}] {message}", self.host)]),
+        }
+    }
+
+    /// Reports that a long running step starts. Unlike `log`, this is printed
+    /// immediately in every mode, so there is a sign of life while the
+    /// buffered lines are held back.
+    pub fn phase(&self, phase: impl fmt::Display) {
+        self.logger
+            .write_lines(&[format!("{}: {phase}...", self.host)]);
+    }
+
+    /// Whether `status` can update a line in place
+    pub fn shows_status(&self) -> bool {
+        self.logger.terminal
+    }
+
+    /// Shows a status line that is updated in place until `clear_status`.
+    /// Lines written meanwhile, also of other hosts, are printed above it.
+    pub fn status(&self, status: impl fmt::Display) {
+        let mut output = self.logger.lock();
+        let status = format!("{}: {status}", self.host);
+        let _ = write!(output.writer, "\r\x1b[K{status}");
+        let _ = output.writer.flush();
+        output.status = Some((self.host.clone(), status));
+    }
+
+    /// Removes the status line if it is this host's
+    pub fn clear_status(&self) {
+        let mut output = self.logger.lock();
+        if output
+            .status
+            .as_ref()
+            .is_some_and(|(host, _)| *host == self.host)
+        {
+            output.status = None;
+            let _ = write!(output.writer, "\r\x1b[K");
+            let _ = output.writer.flush();
+        }
+    }
+}
+
+impl Drop for HostLog<'_> {
+    fn drop(&mut self) {
+        let lines = self.lines.get_mut();
+        if lines.is_empty() {
+            return;
+        }
+        let mut block = Vec::with_capacity(lines.len() + 1);
+        block.push(format!("=== {} ===", self.host));
+        block.append(lines);
+        self.logger.write_lines(&block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_buffered_blocks_are_contiguous() {
+        let buffer = SharedBuffer::default();
+        let logger = HostLogger::with_writer(LogMode::Buffered, buffer.clone());
+
+        thread::scope(|scope| {
+            for host in ["web1", "web2", "web3"] {
+                let logger = &logger;
+                scope.spawn(move || {
+                    let log = logger.for_host(host);
+                    for step in 0..50 {
+                        log.log(format!("{host} step {step}"));
+                        thread::yield_now();
+                    }
+                });
+            }
+        });
+
+        let output = buffer.contents();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3 * 51);
+        for block in lines.chunks(51) {
+            let host = block[0].trim_matches(|c| c == '=' || c == ' ');
+            assert!(block[1..]
+                .iter()
+                .all(|line| line.starts_with(&format!("  {host} step"))));
+        }
+    }
+
+    #[test]
+    fn test_phases_are_not_buffered() {
+        let buffer = SharedBuffer::default();
+        let logger = HostLogger::with_writer(LogMode::Buffered, buffer.clone());
+
+        let log = logger.for_host("web1");
+        log.phase("copying");
+        log.log("copied closure");
+        assert_eq!(buffer.contents(), "web1: copying...\n");
+        drop(log);
+        assert_eq!(
+            buffer.contents(),
+            "web1: copying...\n=== web1 ===\n  copied closure\n"
+        );
+    }
+
+    #[test]
+    fn test_lines_are_printed_above_the_status_line() {
+        let buffer = SharedBuffer::default();
+        let logger = HostLogger::with_writer(LogMode::Prefixed, buffer.clone());
+
+        let web1 = logger.for_host("web1");
+        let web2 = logger.for_host("web2");
+        web1.status("copied 1/2 paths");
+        web2.log("switching");
+        assert_eq!(
+            buffer.contents(),
+            "\r\x1b[Kweb1: copied 1/2 paths\r\x1b[K[web2] switching\nweb1: copied 1/2 paths"
+        );
+
+        // Only the host that shows the status line removes it
+        web2.clear_status();
+        web1.clear_status();
+        web2.phase("checking");
+        assert!(buffer
+            .contents()
+            .ends_with("web1: copied 1/2 paths\r\x1b[Kweb2: checking...\n"));
+    }
+
+    #[test]
+    fn test_prefixed_streams_immediately() {
+        let buffer = SharedBuffer::default();
+        let logger = HostLogger::with_writer(LogMode::Prefixed, buffer.clone());
+
+        let log = logger.for_host("web1");
+        log.log("copied closure");
+        assert_eq!(buffer.contents(), "[web1] copied closure\n");
+        drop(log);
+        assert_eq!(buffer.contents(), "[web1] copied closure\n");
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Serializes operations on the same target host when systems are processed
+/// in parallel. Several system attributes can resolve to the same host, and
+/// two activations must never run on one machine at the same time.
+#[derive(Debug, Default)]
+pub struct HostMutexes {
+    mutexes: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl HostMutexes {
+    fn get(&self, host: &str) -> Arc<Mutex<()>> {
+        self.mutexes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(host.to_lowercase())
+            .or_default()
+            .clone()
+    }
+
+    /// Runs `f` while holding the mutex of `host`
+    pub fn with_lock<T>(&self, host: &str, f: impl FnOnce() -> T) -> T {
+        let mutex = self.get(host);
+        let _guard = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+        f()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    fn max_concurrency(hosts: &[&str]) -> usize {
+        let mutexes = HostMutexes::default();
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for host in hosts {
+                scope.spawn(|| {
+                    mutexes.with_lock(host, || {
+                        let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_active.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(50));
+                        active.fetch_sub(1, Ordering::SeqCst);
+                    });
+                });
+            }
+        });
+
+        max_active.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_same_host_is_serialized() {
+        assert_eq!(max_concurrency(&["web1", "WEB1", "web1"]), 1);
+    }
+
+    #[test]
+    fn test_different_hosts_run_concurrently() {
+        assert_eq!(max_concurrency(&["web1", "web2"]), 2);
+    }
+}
@@ -0,0 +1,182 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const BYTE_UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// Formats a byte count with binary unit prefixes, e.g. `1.5 GiB`.
+#[allow(clippy::cast_precision_loss)]
+pub fn format_bytes(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < BYTE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} {}", BYTE_UNITS[0])
+    } else {
+        format!("{value:.1} {}", BYTE_UNITS[unit])
+    }
+}
+
+/// Parses a duration like `90d`, `2w` or `1d12h` into seconds.
+///
+/// Supported units are `s`, `m`, `h`, `d` and `w`.
+pub fn parse_duration(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("empty duration".to_string());
+    }
+
+    let mut total: u64 = 0;
+    let mut rest = input;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!("invalid duration '{input}': expected a number"));
+        }
+        let value: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("invalid duration '{input}': number too large"))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let multiplier = match &rest[..unit_len] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 7 * 86400,
+            "" => return Err(format!("invalid duration '{input}': missing unit")),
+            unit => return Err(format!("invalid duration '{input}': unknown unit '{unit}'")),
+        };
+        rest = &rest[unit_len..];
+
+        total = value
+            .checked_mul(multiplier)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(|| format!("invalid duration '{input}': too large"))?;
+    }
+    Ok(total)
+}
+
+/// Parses a duration like `parse_duration` into a duration for nix'
+/// `--delete-older-than`, e.g. `2w` into `14d`. nix only understands whole
+/// days.
+pub fn parse_nix_days(input: &str) -> Result<String, String> {
+    let seconds = parse_duration(input)?;
+    if seconds == 0 || seconds % 86400 != 0 {
+        return Err(format!(
+            "invalid duration '{input}': must be a whole number of days, e.g. `30d`"
+        ));
+    }
+    Ok(format!("{}d", seconds / 86400))
+}
+
+/// Formats seconds as days, hours and minutes, e.g. `3d 4h 5m`.
+pub fn format_duration(seconds: u64) -> String {
+    let days = seconds / 86400;
+    let hours = (seconds % 86400) / 3600;
+    let minutes = (seconds % 3600) / 60;
+    format!("{days}d {hours}h {minutes}m")
+}
+
+/// Formats a short duration with at most two units, e.g. `42s` or `3m 5s`.
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, (seconds % 3600) / 60),
+    }
+}
+
+/// Formats a point in time as UTC in RFC 3339 format, e.g.
+/// `2024-03-01T12:00:00Z`.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // Converts days since the epoch to a date in the proleptic Gregorian
+    // calendar, see http://howardhinnant.github.io/date_algorithms.html
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90d"), Ok(90 * 86400));
+        assert_eq!(parse_duration("2w"), Ok(14 * 86400));
+        assert_eq!(parse_duration("1d12h"), Ok(86400 + 12 * 3600));
+        assert_eq!(parse_duration("30m"), Ok(1800));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("90").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("3y").is_err());
+    }
+
+    #[test]
+    fn test_parse_nix_days() {
+        assert_eq!(parse_nix_days("30d"), Ok("30d".to_string()));
+        assert_eq!(parse_nix_days("2w"), Ok("14d".to_string()));
+        assert!(parse_nix_days("12h").is_err());
+        assert!(parse_nix_days("0d").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0d 0h 0m");
+        assert_eq!(format_duration(90061), "1d 1h 1m");
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_millis(42_700)), "42s");
+        assert_eq!(format_elapsed(Duration::from_secs(185)), "3m 5s");
+        assert_eq!(format_elapsed(Duration::from_secs(7385)), "2h 3m");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let at = |seconds| format_timestamp(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_827_696), "2000-02-29T12:34:56Z");
+        assert_eq!(at(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+}
@@ -1,7 +1,16 @@
 pub mod configcheck;
+pub mod deployplan;
+pub mod evalcache;
 pub mod flakeref;
+pub mod hostlog;
+pub mod hostmutex;
+pub mod humanize;
 pub mod nixcommands;
+pub mod nixlog;
 pub mod nixosattributes;
+pub mod projectconfig;
+pub mod runlog;
+pub mod sharedresults;
 pub mod sshkeys;
 pub mod userinfo;
 
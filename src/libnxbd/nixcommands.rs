@@ -1,13 +1,25 @@
 use serde_json;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fmt;
 use std::fs;
+use std::io::{BufRead, BufReader};
 use std::process;
 use std::str;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
 use which::which;
 
+use super::hostlog::HostLog;
+use super::nixlog;
+use super::runlog;
+use super::sshkeys::SshKeyInfo;
 use super::FlakeReference;
 
+/// How many lines of a failed command's error output are kept in its error
+const STDERR_TAIL_LINES: usize = 10;
+
 #[derive(Debug, Clone)]
 pub enum NixError {
     Eval(String),
@@ -16,6 +28,71 @@ pub enum NixError {
     ProfileSet,
     Deserialization,
     Copy,
+    GcRoot,
+    Lock,
+    Locked {
+        host: String,
+        holder: String,
+    },
+    /// The command ran but exited unsuccessfully, `exit_code` is `None` if it
+    /// was terminated by a signal. `stderr_tail` holds the last lines of its
+    /// error output, if they were captured.
+    CommandFailed {
+        error: Box<NixError>,
+        exit_code: Option<i32>,
+        stderr_tail: Vec<String>,
+    },
+    /// The host could not be reached, trying again later may succeed
+    ConnectionFailed(Box<NixError>),
+    /// The host was switched, but doesn't run properly afterwards
+    Unhealthy {
+        host: String,
+        problem: String,
+    },
+    /// The host's CPU can't run the system, e.g. an `aarch64-linux` system
+    /// on an `x86_64` machine
+    PlatformMismatch {
+        host: String,
+        platform: String,
+        machine: String,
+    },
+    /// sudo or doas on the host asks for a password, which nxbd can't provide
+    PasswordRequired {
+        host: String,
+        escalation: PrivilegeEscalation,
+    },
+}
+
+impl NixError {
+    /// Wraps the error with the exit status and the end of the error output
+    /// of the failed command
+    pub fn with_stderr(self, status: process::ExitStatus, stderr: &str) -> Self {
+        Self::CommandFailed {
+            error: Box::new(self),
+            exit_code: status.code(),
+            stderr_tail: stderr_tail(stderr),
+        }
+    }
+
+    pub fn with_output(self, output: &process::Output) -> Self {
+        self.with_stderr(output.status, &String::from_utf8_lossy(&output.stderr))
+    }
+
+    pub fn is_connection_error(&self) -> bool {
+        matches!(self, Self::ConnectionFailed(_))
+    }
+}
+
+/// The last non-empty lines of a command's error output
+fn stderr_tail(stderr: &str) -> Vec<String> {
+    let lines: Vec<&str> = stderr
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
 }
 
 impl fmt::Display for NixError {
@@ -27,12 +104,109 @@ impl fmt::Display for NixError {
             Self::ProfileSet => write!(f, "Failed to set profile"),
             Self::Deserialization => write!(f, "Failed to parse output"),
             Self::Copy => write!(f, "Failed to copy to host"),
+            Self::GcRoot => write!(f, "Failed to register garbage collector root"),
+            Self::Lock => write!(f, "Failed to take the deploy lock"),
+            Self::Locked { host, holder } => {
+                write!(f, "Deploy already in progress on {host} by {holder}")
+            }
+            Self::CommandFailed {
+                error,
+                exit_code,
+                stderr_tail,
+            } => {
+                match exit_code {
+                    Some(code) => write!(f, "{error} (exit code {code})")?,
+                    None => write!(f, "{error} (terminated by signal)")?,
+                }
+                for line in stderr_tail {
+                    write!(f, "\n    {line}")?;
+                }
+                Ok(())
+            }
+            Self::ConnectionFailed(error) => write!(f, "{error} (connection failed)"),
+            Self::Unhealthy { host, problem } => {
+                write!(f, "{host} is unhealthy after the switch: {problem}")
+            }
+            Self::PlatformMismatch {
+                host,
+                platform,
+                machine,
+            } => write!(
+                f,
+                "{host} is an {machine} machine, but its configuration is for {platform}. \
+                 Check `nixpkgs.hostPlatform` of the system"
+            ),
+            Self::PasswordRequired { host, escalation } => write!(
+                f,
+                "Remote {escalation} on {host} requires a password; nxbd cannot deploy non-interactively. \
+                 Allow passwordless {escalation} for the remote user (security.{escalation}.wheelNeedsPassword = false) \
+                 or deploy as root with --remote-user root"
+            ),
         }
     }
 }
 
 impl std::error::Error for NixError {}
 
+/// Options for every nix evaluation and local build of the run
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvalOptions {
+    /// Print the full trace of evaluation errors, and keep the build
+    /// directories of failed builds for inspection
+    pub show_trace: bool,
+    /// Allow evaluations to access the environment, e.g. `builtins.getEnv`
+    pub impure: bool,
+    /// Don't look anything up on the network, only use what is cached
+    pub offline: bool,
+    /// Don't resolve flake references via the flake registries
+    pub no_registries: bool,
+}
+
+impl EvalOptions {
+    fn eval_args(self) -> Vec<&'static str> {
+        [
+            (self.show_trace, "--show-trace"),
+            (self.impure, "--impure"),
+            (self.offline, "--offline"),
+            (self.no_registries, "--no-use-registries"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, arg)| enabled.then_some(arg))
+        .collect()
+    }
+
+    fn build_args(self) -> Vec<&'static str> {
+        let mut args = self.eval_args();
+        if self.show_trace {
+            args.push("--keep-failed");
+        }
+        args
+    }
+}
+
+/// Set once at startup from the global command line options
+static EVAL_OPTIONS: OnceLock<EvalOptions> = OnceLock::new();
+
+pub fn set_eval_options(options: EvalOptions) {
+    // Only the first call counts, like the options of a single run
+    let _ = EVAL_OPTIONS.set(options);
+}
+
+fn eval_options() -> EvalOptions {
+    EVAL_OPTIONS.get().copied().unwrap_or_default()
+}
+
+/// Extra arguments for `nix eval` and other commands that evaluate flakes
+pub fn eval_args() -> Vec<&'static str> {
+    eval_options().eval_args()
+}
+
+/// Extra arguments for `nix build`
+fn build_args() -> Vec<&'static str> {
+    eval_options().build_args()
+}
+
 pub fn nixos_configuration_attributes(flake_url: &str) -> Result<Vec<String>, NixError> {
     let build_output = process::Command::new("nix")
         .args([
@@ -42,6 +216,7 @@ pub fn nixos_configuration_attributes(flake_url: &str) -> Result<Vec<String>, Ni
             "--apply",
             "builtins.attrNames",
         ])
+        .args(eval_args())
         .stderr(process::Stdio::inherit())
         .output()
         .map_err(|_| NixError::Eval("Failed to execute nix eval".to_string()))?;
@@ -53,6 +228,30 @@ pub fn nixos_configuration_attributes(flake_url: &str) -> Result<Vec<String>, Ni
     Ok(attributes)
 }
 
+/// Evaluates the system's derivation path without building anything or
+/// evaluating more of the configuration than the system itself needs
+pub fn toplevel_drv_path(flake_reference: &FlakeReference) -> Result<String, NixError> {
+    let output = process::Command::new("nix")
+        .args([
+            "eval",
+            "--raw",
+            &format!(
+                "{}#nixosConfigurations.\"{}\".config.system.build.toplevel.drvPath",
+                flake_reference.url, flake_reference.attribute
+            ),
+        ])
+        .args(eval_args())
+        .output()
+        .map_err(|_| NixError::Eval("Failed to execute nix eval".to_string()))?;
+
+    if !output.status.success() {
+        return Err(NixError::Eval(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
 pub fn nixos_configuration_flakerefs(flake_url: &str) -> Result<Vec<FlakeReference>, NixError> {
     let discovered_attrs = nixos_configuration_attributes(flake_url)?;
     let flakerefs = discovered_attrs
@@ -65,49 +264,428 @@ pub fn nixos_configuration_flakerefs(flake_url: &str) -> Result<Vec<FlakeReferen
     Ok(flakerefs)
 }
 
+/// Identifies the contents of a flake: the hash of its source tree, which
+/// includes its `flake.lock`. `None` if nix can't tell, e.g. for flakes
+/// that can't be fetched.
+pub fn flake_fingerprint(flake_url: &str) -> Option<String> {
+    let output = process::Command::new("nix")
+        .args(["flake", "metadata", "--json", flake_url])
+        .args(eval_args())
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let metadata = json::parse_nix_json_output(&output.stdout).ok()?;
+    metadata["locked"]["narHash"].as_str().map(String::from)
+}
+
+/// Options for connecting to deployment targets
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionOptions {
+    /// User to log in as on the target, defaults to the local user
+    pub user: Option<String>,
+    /// Extra options for every ssh invocation, e.g. `-p 2222 -i key`,
+    /// including those from `NIX_SSHOPTS`
+    pub ssh_opts: Vec<String>,
+    /// Seconds to wait for ssh connections to be established, so unreachable
+    /// hosts don't stall everything until the TCP timeout
+    pub connect_timeout: Option<u64>,
+    /// How often copies and remote builds are retried after connection errors
+    pub retries: u32,
+    /// Push whole closures from here instead of letting the target fetch
+    /// what it can from its own substituters, e.g. for air-gapped targets
+    pub no_substitute_on_destination: bool,
+    /// Substituters for `nix copy` instead of those from `nix.conf`
+    pub substituters: Option<String>,
+    /// How commands that need root privileges are run on the target
+    pub privilege_escalation: PrivilegeEscalation,
+}
+
+impl ConnectionOptions {
+    /// Splits options given as one string, like `NIX_SSHOPTS`, into words
+    pub fn parse_ssh_opts(opts: &str) -> Vec<String> {
+        opts.split_whitespace().map(String::from).collect()
+    }
+
+    /// The ssh options from `NIX_SSHOPTS`, which nix uses for its own ssh
+    /// connections
+    pub fn env_ssh_opts() -> Vec<String> {
+        std::env::var("NIX_SSHOPTS")
+            .map(|opts| Self::parse_ssh_opts(&opts))
+            .unwrap_or_default()
+    }
+
+    /// Default options, except for the ssh options from `NIX_SSHOPTS`
+    pub fn from_env() -> Self {
+        ConnectionOptions {
+            ssh_opts: Self::env_ssh_opts(),
+            ..Self::default()
+        }
+    }
+
+    pub fn remote_host(&self, host: &str) -> RemoteHost {
+        RemoteHost {
+            host: host.to_string(),
+            options: self.clone(),
+        }
+    }
+}
+
+/// Addresses to reach systems at instead of their `networking.fqdnOrHostName`,
+/// e.g. behind NAT or with split-horizon DNS
+#[derive(Debug, Clone, Default)]
+pub struct TargetHosts {
+    /// Address for any system, only meaningful if there is a single one
+    pub default: Option<String>,
+    pub by_attribute: HashMap<String, String>,
+}
+
+impl TargetHosts {
+    /// Parses `attr=addr` mappings and plain addresses, which apply to any
+    /// system
+    pub fn parse(values: &[String]) -> Self {
+        let mut target_hosts = Self::default();
+        for value in values {
+            match value.split_once('=') {
+                Some((attribute, address)) => {
+                    target_hosts
+                        .by_attribute
+                        .insert(attribute.to_string(), address.to_string());
+                }
+                None => target_hosts.default = Some(value.clone()),
+            }
+        }
+        target_hosts
+    }
+
+    pub fn for_attribute(&self, attribute: &str) -> Option<&str> {
+        self.by_attribute
+            .get(attribute)
+            .or(self.default.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// A deployment target and how to connect to it
+#[derive(Debug, Clone)]
+pub struct RemoteHost {
+    pub host: String,
+    pub options: ConnectionOptions,
+}
+
+impl RemoteHost {
+    /// The ssh destination, `user@host` if a remote user is set
+    pub fn destination(&self) -> String {
+        match &self.options.user {
+            Some(user) => format!("{user}@{}", self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// How commands that need root privileges are run, they run directly if
+    /// we log in as root
+    pub fn escalation(&self) -> PrivilegeEscalation {
+        if self.options.user.as_deref() == Some("root") {
+            PrivilegeEscalation::None
+        } else {
+            self.options.privilege_escalation
+        }
+    }
+
+    /// Arguments for `ssh` up to and including the destination
+    pub fn ssh_args(&self) -> Vec<String> {
+        let mut args = self.options.ssh_opts.clone();
+        args.extend(self.timeout_opts());
+        args.push(self.destination());
+        args
+    }
+
+    fn timeout_opts(&self) -> Vec<String> {
+        self.options
+            .connect_timeout
+            .map(|seconds| vec!["-o".to_string(), format!("ConnectTimeout={seconds}")])
+            .unwrap_or_default()
+    }
+
+    /// The port from a `-p` ssh option, if any
+    fn port(&self) -> Option<&str> {
+        let mut opts = self.options.ssh_opts.iter();
+        while let Some(opt) = opts.next() {
+            if opt == "-p" {
+                return opts.next().map(String::as_str);
+            }
+            if let Some(port) = opt.strip_prefix("-p") {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    /// Store URL for `nix copy`. nix passes its own `-p` from the URL to ssh,
+    /// so the port has to go there instead of into `NIX_SSHOPTS`.
+    fn store_url(&self) -> String {
+        match self.port() {
+            Some(port) => format!("ssh://{}:{port}", self.destination()),
+            None => format!("ssh://{}", self.destination()),
+        }
+    }
+
+    /// Arguments for `nix copy` to copy paths to this host
+    fn copy_to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.options.no_substitute_on_destination {
+            args.push("--substitute-on-destination".to_string());
+        }
+        if let Some(substituters) = &self.options.substituters {
+            args.extend(["--option", "substituters", substituters].map(String::from));
+        }
+        args.extend(["--to".to_string(), self.store_url()]);
+        args
+    }
+
+    /// The ssh options without the port, for `NIX_SSHOPTS`
+    fn nix_ssh_opts(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        let mut opts = self.options.ssh_opts.iter();
+        while let Some(opt) = opts.next() {
+            if opt == "-p" {
+                opts.next();
+            } else if !opt.starts_with("-p") {
+                result.push(opt.clone());
+            }
+        }
+        result.extend(self.timeout_opts());
+        result
+    }
+}
+
+impl fmt::Display for RemoteHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.destination())
+    }
+}
+
 // New helper module for command execution
 mod command {
-    use super::NixError;
-    use std::process::{Command, Output};
+    use super::{runlog, NixError, PrivilegeEscalation, RemoteHost};
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::process::{Command, Output, Stdio};
+    use std::thread;
+
+    /// ssh error messages that indicate a stale or broken connection
+    /// multiplexing control socket, e.g. left behind by a crashed master
+    const CONTROL_SOCKET_ERRORS: &[&str] =
+        &["mux_client_", "Control socket connect", "ControlSocket"];
+
+    /// ssh options that disable connection multiplexing for one invocation
+    pub const NO_MULTIPLEXING_OPTS: [&str; 4] =
+        ["-o", "ControlMaster=no", "-o", "ControlPath=none"];
+
+    /// Messages of ssh and nix that indicate the host could not be reached
+    const CONNECTION_ERRORS: &[&str] = &[
+        "Connection refused",
+        "Connection timed out",
+        "Connection reset",
+        "Connection closed",
+        "Could not resolve hostname",
+        "No route to host",
+        "Network is unreachable",
+        "Broken pipe",
+        "failed to start SSH connection",
+        "cannot connect to",
+    ];
+
+    /// Messages of sudo and doas when they need a password but can't ask for
+    /// one
+    const PASSWORD_ERRORS: &[&str] = &[
+        "sudo: a password is required",
+        "sudo: a terminal is required to read the password",
+        "doas: Authorization required",
+        "doas: Authentication required",
+    ];
+
+    /// ssh exits with this code on connection errors
+    pub const SSH_ERROR_EXIT_CODE: i32 = 255;
+
+    pub fn is_control_socket_error<S: AsRef<str>>(messages: &[S]) -> bool {
+        messages.iter().any(|message| {
+            CONTROL_SOCKET_ERRORS
+                .iter()
+                .any(|signature| message.as_ref().contains(signature))
+        })
+    }
+
+    pub fn is_password_error<S: AsRef<str>>(messages: &[S]) -> bool {
+        messages.iter().any(|message| {
+            PASSWORD_ERRORS
+                .iter()
+                .any(|signature| message.as_ref().contains(signature))
+        })
+    }
+
+    pub fn is_connection_error<S: AsRef<str>>(messages: &[S]) -> bool {
+        messages.iter().any(|message| {
+            CONNECTION_ERRORS
+                .iter()
+                .any(|signature| message.as_ref().contains(signature))
+        })
+    }
+
+    /// Quotes a string for use as a single word in a POSIX shell command line
+    pub fn shell_quote(s: &str) -> String {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
 
-    pub fn build_remote_command(remote_host: Option<&str>, use_sudo: bool) -> Vec<String> {
+    /// Prefixes the command with `sudo -n` or `doas -n`. Nobody can answer a
+    /// password prompt on the remote host, so they fail instead of asking.
+    pub fn build_remote_command(cmd: &[&str], escalation: PrivilegeEscalation) -> Vec<String> {
         let mut command_vec = Vec::new();
-        if let Some(host) = remote_host {
-            command_vec.extend(["ssh", host].map(String::from));
-        }
-        if use_sudo {
-            command_vec.push("sudo".to_string());
+        if let Some(program) = escalation.program() {
+            command_vec.extend([program.to_string(), "-n".to_string()]);
         }
+        command_vec.extend(cmd.iter().map(|s| s.to_string()));
         command_vec
     }
 
+    /// Runs the command and returns its output regardless of its exit status.
+    /// stderr is shown as it comes and also captured.
+    pub fn command_output(cmd: &str, args: &[&str], error: NixError) -> Result<Output, NixError> {
+        output_tee_stderr(Command::new(cmd).args(args), None).map_err(|_| error)
+    }
+
+    fn check_status(output: Output, error: NixError) -> Result<Output, NixError> {
+        if output.status.success() {
+            Ok(output)
+        } else {
+            Err(error.with_output(&output))
+        }
+    }
+
+    /// Runs the command and fails if it can't be started or exits unsuccessfully
     pub fn run_command(cmd: &str, args: &[&str], error: NixError) -> Result<Output, NixError> {
-        Command::new(cmd)
+        check_status(command_output(cmd, args, error.clone())?, error)
+    }
+
+    /// Like `run_command`, but leaves stderr attached to the terminal, for
+    /// commands like `nom` that draw their own terminal UI
+    pub fn run_command_on_terminal(
+        cmd: &str,
+        args: &[&str],
+        error: NixError,
+    ) -> Result<Output, NixError> {
+        let output = Command::new(cmd)
             .args(args)
-            .stderr(std::process::Stdio::inherit())
+            .stderr(Stdio::inherit())
             .output()
-            .map_err(|_| error)
+            .map_err(|_| error.clone())?;
+        check_status(output, error)
+    }
+
+    /// Runs the command like `Command::output`, but passes stderr through to
+    /// our own stderr while also capturing it in the returned output. Like
+    /// `Command::output`, the child gets no stdin unless input is given.
+    fn output_tee_stderr(command: &mut Command, stdin: Option<&[u8]>) -> io::Result<Output> {
+        let mut child = command
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stderr = child.stderr.take();
+        let stderr_reader = thread::spawn(move || {
+            let mut captured = Vec::new();
+            if let Some(stderr) = stderr {
+                let mut reader = BufReader::new(stderr);
+                let mut line = Vec::new();
+                while reader.read_until(b'\n', &mut line).is_ok_and(|n| n > 0) {
+                    let _ = io::stderr().write_all(&line);
+                    captured.append(&mut line);
+                }
+            }
+            captured
+        });
+
+        if let (Some(input), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+            child_stdin.write_all(input)?;
+        }
+
+        let mut output = child.wait_with_output()?;
+        output.stderr = stderr_reader.join().unwrap_or_default();
+        Ok(output)
+    }
+
+    /// Runs ssh with the given arguments. If the connection fails because of a
+    /// broken multiplexing control socket, it is retried once with multiplexing
+    /// disabled.
+    pub fn run_ssh(
+        args: &[String],
+        stdin: Option<&[u8]>,
+        error: NixError,
+    ) -> Result<Output, NixError> {
+        let output =
+            output_tee_stderr(Command::new("ssh").args(args), stdin).map_err(|_| error.clone())?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.code() == Some(SSH_ERROR_EXIT_CODE)
+            && is_control_socket_error(&stderr.lines().collect::<Vec<_>>())
+        {
+            let warning =
+                "Warning: ssh connection multiplexing failed, retrying with a fresh connection";
+            eprintln!("{warning}");
+            runlog::log(warning);
+            return output_tee_stderr(
+                Command::new("ssh").args(NO_MULTIPLEXING_OPTS).args(args),
+                stdin,
+            )
+            .map_err(|_| error);
+        }
+
+        Ok(output)
     }
 
     pub fn run_remote_command(
         cmd: &[&str],
-        remote_host: Option<&str>,
+        remote_host: Option<&RemoteHost>,
         use_sudo: bool,
         error: NixError,
     ) -> Result<Output, NixError> {
-        let mut command = build_remote_command(remote_host, use_sudo);
-        command.extend(cmd.iter().map(|s| s.to_string()));
+        if let Some(host) = remote_host {
+            let escalation = if use_sudo {
+                host.escalation()
+            } else {
+                PrivilegeEscalation::None
+            };
+            let mut args = host.ssh_args();
+            args.extend(build_remote_command(cmd, escalation));
+            let output = run_ssh(&args, None, error.clone())?;
+            if escalation != PrivilegeEscalation::None
+                && !output.status.success()
+                && is_password_error(
+                    &String::from_utf8_lossy(&output.stderr)
+                        .lines()
+                        .collect::<Vec<_>>(),
+                )
+            {
+                return Err(NixError::PasswordRequired {
+                    host: host.host.clone(),
+                    escalation,
+                });
+            }
+            return check_status(output, error);
+        }
 
-        let (cmd, args) = command.split_first().ok_or_else(|| error.clone())?;
+        // Locally, sudo may ask for the password on the terminal
+        let (cmd, args) = match (use_sudo, cmd) {
+            (true, _) => ("sudo", cmd),
+            (false, [cmd, args @ ..]) => (*cmd, args),
+            (false, []) => return Err(error),
+        };
 
-        run_command(
-            cmd,
-            args.iter()
-                .map(String::as_str)
-                .collect::<Vec<_>>()
-                .as_slice(),
-            error,
-        )
+        run_command(cmd, args, error)
     }
 }
 
@@ -126,10 +704,80 @@ mod json {
     }
 }
 
+/// The modes of `switch-to-configuration`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ActivationMode {
+    /// Activate the system and make it the boot default
+    #[default]
+    Switch,
+    /// Make the system the boot default, it is activated on the next reboot
+    Boot,
+    /// Activate the system without making it the boot default
+    Test,
+    /// Only print what activating the system would do
+    DryActivate,
+}
+
+impl ActivationMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Switch => "switch",
+            Self::Boot => "boot",
+            Self::Test => "test",
+            Self::DryActivate => "dry-activate",
+        }
+    }
+
+    /// Whether the system profile has to point to the new system
+    pub fn sets_boot_default(self) -> bool {
+        matches!(self, Self::Switch | Self::Boot)
+    }
+
+    /// Whether the new system runs after the activation
+    pub fn activates(self) -> bool {
+        matches!(self, Self::Switch | Self::Test)
+    }
+}
+
+impl fmt::Display for ActivationMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How commands that need root privileges are run on target hosts that we
+/// don't log in to as root
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrivilegeEscalation {
+    #[default]
+    Sudo,
+    Doas,
+    /// Run the commands as the remote user, e.g. if it is allowed to switch
+    /// the system by other means
+    None,
+}
+
+impl PrivilegeEscalation {
+    /// The program that runs a command as root
+    pub fn program(self) -> Option<&'static str> {
+        match self {
+            Self::Sudo => Some("sudo"),
+            Self::Doas => Some("doas"),
+            Self::None => None,
+        }
+    }
+}
+
+impl fmt::Display for PrivilegeEscalation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.program().unwrap_or("none"))
+    }
+}
+
 pub fn activate_profile(
     toplevel_path: &str,
     use_sudo: bool,
-    remote_host: Option<&str>,
+    remote_host: Option<&RemoteHost>,
 ) -> Result<(), NixError> {
     command::run_remote_command(
         &[
@@ -150,7 +798,7 @@ pub fn switch_to_configuration(
     toplevel_path: &str,
     command: &str,
     use_sudo: bool,
-    remote_host: Option<&str>,
+    remote_host: Option<&RemoteHost>,
 ) -> Result<(), NixError> {
     let switch_path = format!("{toplevel_path}/bin/switch-to-configuration");
     command::run_remote_command(
@@ -162,14 +810,284 @@ pub fn switch_to_configuration(
     Ok(())
 }
 
-pub fn copy_to_host(path: &str, host: &str) -> Result<(), NixError> {
-    let target = format!("ssh://{}", host);
-    process::Command::new("nix")
-        .args(["copy", "--substitute-on-destination", "--to", &target, path])
-        .stderr(process::Stdio::inherit())
-        .output()
-        .map_err(|_| NixError::Copy)
-        .map(|_| ())
+/// Switches back to the previous generation of the system profile
+pub fn rollback_system(use_sudo: bool, remote_host: Option<&RemoteHost>) -> Result<(), NixError> {
+    command::run_remote_command(
+        &[
+            "nix-env",
+            "-p",
+            "/nix/var/nix/profiles/system",
+            "--rollback",
+        ],
+        remote_host,
+        use_sudo,
+        NixError::ProfileSet,
+    )?;
+    switch_to_configuration(
+        "/nix/var/nix/profiles/system",
+        "switch",
+        use_sudo,
+        remote_host,
+    )
+}
+
+/// The number of the generation the system profile points to
+pub fn system_generation(remote_host: Option<&RemoteHost>) -> Result<u64, NixError> {
+    let output = command::run_remote_command(
+        &["readlink", "/nix/var/nix/profiles/system"],
+        remote_host,
+        false,
+        NixError::Eval("Failed to read the system profile".to_string()),
+    )?;
+    parse_generation_link(&String::from_utf8_lossy(&output.stdout)).ok_or(NixError::Deserialization)
+}
+
+/// Pairs of (platform CPU, machine) where the machine runs the platform's
+/// binaries although `uname -m` reports another architecture
+const COMPATIBLE_MACHINES: &[(&str, &str)] = &[
+    ("i686", "x86_64"),
+    ("armv7l", "aarch64"),
+    ("armv6l", "aarch64"),
+    ("armv6l", "armv7l"),
+];
+
+/// The machine hardware name of the host, as reported by `uname -m`
+pub fn machine_architecture(remote_host: Option<&RemoteHost>) -> Result<String, NixError> {
+    let output = command::run_remote_command(
+        &["uname", "-m"],
+        remote_host,
+        false,
+        NixError::Eval("Failed to read the machine architecture".to_string()),
+    )?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Whether a machine with the `uname -m` name `machine` runs systems of
+/// `platform`, like `x86_64-linux`
+pub fn platform_runs_on(platform: &str, machine: &str) -> bool {
+    let cpu = platform.split('-').next().unwrap_or_default();
+    cpu == machine || COMPATIBLE_MACHINES.contains(&(cpu, machine))
+}
+
+/// Parses profile generation links like `system-42-link`
+fn parse_generation_link(link: &str) -> Option<u64> {
+    link.trim()
+        .strip_prefix("system-")?
+        .strip_suffix("-link")?
+        .parse()
+        .ok()
+}
+
+/// A generation of the system profile
+#[derive(Debug, PartialEq, Eq)]
+pub struct Generation {
+    pub number: u64,
+    pub date: String,
+    /// Whether the system profile points to this generation
+    pub current: bool,
+    pub store_path: Option<String>,
+}
+
+/// Lists the generations of the system profile, oldest first
+pub fn list_generations(remote_host: Option<&RemoteHost>) -> Result<Vec<Generation>, NixError> {
+    let script = r#"
+        set -euo pipefail
+        nix-env -p /nix/var/nix/profiles/system --list-generations
+        echo ---
+        for link in /nix/var/nix/profiles/system-*-link; do
+            echo "$(basename "$link") $(readlink -f "$link")"
+        done
+    "#;
+    let output = run_script(script, remote_host)?;
+    if !output.status.success() {
+        return Err(NixError::Eval("Failed to list generations".to_string()).with_output(&output));
+    }
+    Ok(parse_generations(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses the output of the script in `list_generations`
+fn parse_generations(output: &str) -> Vec<Generation> {
+    let (list, links) = output.split_once("---").unwrap_or((output, ""));
+    let store_paths: Vec<(u64, &str)> = links
+        .lines()
+        .filter_map(|line| {
+            let (link, path) = line.trim().split_once(' ')?;
+            Some((parse_generation_link(link)?, path))
+        })
+        .collect();
+
+    list.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let number: u64 = fields.next()?.parse().ok()?;
+            let date = format!("{} {}", fields.next()?, fields.next()?);
+            let current = fields.next() == Some("(current)");
+            let store_path = store_paths
+                .iter()
+                .find(|(n, _)| *n == number)
+                .map(|(_, path)| (*path).to_string());
+            Some(Generation {
+                number,
+                date,
+                current,
+                store_path,
+            })
+        })
+        .collect()
+}
+
+/// What a garbage collection run removed
+#[derive(Debug, PartialEq, Eq)]
+pub struct GcResult {
+    pub paths_deleted: u64,
+    /// As reported by nix, e.g. `123.45 MiB`
+    pub freed: String,
+}
+
+/// Collects garbage. With `older_than`, a nix duration like `14d`,
+/// generations of all profiles older than that are deleted first.
+pub fn collect_garbage(
+    older_than: Option<&str>,
+    remote_host: Option<&RemoteHost>,
+) -> Result<GcResult, NixError> {
+    let mut cmd = vec!["nix-collect-garbage"];
+    if let Some(older_than) = older_than {
+        cmd.extend(["--delete-older-than", older_than]);
+    }
+    let output = command::run_remote_command(
+        &cmd,
+        remote_host,
+        true,
+        NixError::Eval("Failed to collect garbage".to_string()),
+    )?;
+
+    // nix prints the summary on stderr
+    [&output.stderr, &output.stdout]
+        .iter()
+        .find_map(|output| parse_gc_summary(&String::from_utf8_lossy(output)))
+        .ok_or(NixError::Deserialization)
+}
+
+/// Parses the `N store paths deleted, X MiB freed` line of nix-collect-garbage
+fn parse_gc_summary(output: &str) -> Option<GcResult> {
+    output.lines().rev().find_map(|line| {
+        let (paths, freed) = line.trim().split_once(" store paths deleted, ")?;
+        Some(GcResult {
+            paths_deleted: paths.parse().ok()?,
+            freed: freed.strip_suffix(" freed")?.to_string(),
+        })
+    })
+}
+
+/// GC root on deployment targets that protects the last copied system closure
+/// between copying and activation. As it is overwritten on every deployment and
+/// points to the active system afterwards, it never needs to be cleaned up.
+pub const STAGED_GC_ROOT: &str = "/nix/var/nix/gcroots/nxbd-staged";
+
+pub fn add_gc_root(
+    path: &str,
+    link: &str,
+    use_sudo: bool,
+    remote_host: Option<&RemoteHost>,
+) -> Result<(), NixError> {
+    command::run_remote_command(
+        &["nix-store", "--realise", path, "--add-root", link],
+        remote_host,
+        use_sudo,
+        NixError::GcRoot,
+    )?;
+    Ok(())
+}
+
+pub fn copy_to_host(path: &str, host: &RemoteHost, log: &HostLog) -> Result<(), NixError> {
+    let args = host.copy_to_args();
+    nix_copy(
+        path,
+        &args.iter().map(String::as_str).collect::<Vec<_>>(),
+        host,
+        log,
+    )
+}
+
+/// Copies a path from the host into the local store
+pub fn copy_from_host(path: &str, host: &RemoteHost, log: &HostLog) -> Result<(), NixError> {
+    nix_copy(path, &["--from", &host.store_url()], host, log)
+}
+
+/// Copies a path from one host to another, without adding it to the local
+/// store. The ssh and copy options of `to` apply to both connections.
+pub fn copy_between_hosts(
+    path: &str,
+    from: &RemoteHost,
+    to: &RemoteHost,
+    log: &HostLog,
+) -> Result<(), NixError> {
+    let mut args = vec!["--from".to_string(), from.store_url()];
+    args.extend(to.copy_to_args());
+    nix_copy(
+        path,
+        &args.iter().map(String::as_str).collect::<Vec<_>>(),
+        to,
+        log,
+    )
+}
+
+/// Runs `nix copy` with the host's ssh options and reports its progress to
+/// the host's log
+fn nix_copy(
+    path: &str,
+    store_args: &[&str],
+    host: &RemoteHost,
+    log: &HostLog,
+) -> Result<(), NixError> {
+    // nix copy only sees our NIX_SSHOPTS, which already include the user's
+    let mut ssh_opts = host.nix_ssh_opts();
+
+    let (mut status, mut messages) = run_nix_copy(path, store_args, &ssh_opts, log)?;
+
+    if !status.success() && command::is_control_socket_error(&messages) {
+        log.log("Warning: ssh connection multiplexing failed, retrying with a fresh connection");
+        ssh_opts.extend(command::NO_MULTIPLEXING_OPTS.map(String::from));
+        (status, messages) = run_nix_copy(path, store_args, &ssh_opts, log)?;
+    }
+
+    if !status.success() {
+        let error = NixError::Copy.with_stderr(status, &messages.join("\n"));
+        if command::is_connection_error(&messages) {
+            return Err(NixError::ConnectionFailed(Box::new(error)));
+        }
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn run_nix_copy(
+    path: &str,
+    store_args: &[&str],
+    ssh_opts: &[String],
+    log: &HostLog,
+) -> Result<(process::ExitStatus, Vec<String>), NixError> {
+    let mut command = process::Command::new("nix");
+    if !ssh_opts.is_empty() {
+        command.env("NIX_SSHOPTS", ssh_opts.join(" "));
+    }
+    let mut child = command
+        .args(["copy", "--log-format", "internal-json"])
+        .args(store_args)
+        .arg(path)
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .map_err(|_| NixError::Copy)?;
+
+    let messages = child
+        .stderr
+        .take()
+        .map(|stderr| nixlog::report_copy_progress(log, stderr).messages)
+        .unwrap_or_default();
+
+    let status = child.wait().map_err(|_| NixError::Copy)?;
+    Ok((status, messages))
 }
 
 #[derive(Debug)]
@@ -246,6 +1164,12 @@ pub fn get_remote_builders() -> Result<Vec<RemoteBuilder>, NixError> {
         .ok_or_else(|| NixError::Eval("builders value is not a string".to_string()))?
         .to_string();
 
+    remote_builders_from_spec(&builders_value)
+}
+
+/// Parses a value of nix' `builders` setting, which is either a list of
+/// machines or `@` followed by the path of a machines file.
+pub fn remote_builders_from_spec(builders_value: &str) -> Result<Vec<RemoteBuilder>, NixError> {
     let builders_str = if builders_value.starts_with('@') {
         fs::read_to_string(&builders_value[1..]).map_err(|_| {
             NixError::Eval(format!(
@@ -254,21 +1178,24 @@ pub fn get_remote_builders() -> Result<Vec<RemoteBuilder>, NixError> {
             ))
         })?
     } else {
-        builders_value
+        builders_value.to_string()
     };
 
     Ok(parse_builders(&builders_str))
 }
 
-pub fn realise_drv_remotely(drv_path: &str, host: &str) -> Result<String, NixError> {
-    let output = process::Command::new("ssh")
-        .args([host, "nix-store", "--realise", drv_path])
-        .stderr(process::Stdio::inherit())
-        .output()
-        .map_err(|_| NixError::Build)?;
+pub fn realise_drv_remotely(drv_path: &str, host: &RemoteHost) -> Result<String, NixError> {
+    let mut args = host.ssh_args();
+    args.extend(["nix-store", "--realise", drv_path].map(String::from));
+    let output = command::run_ssh(&args, None, NixError::Build)?;
 
+    if output.status.code() == Some(command::SSH_ERROR_EXIT_CODE) {
+        return Err(NixError::ConnectionFailed(Box::new(
+            NixError::Build.with_output(&output),
+        )));
+    }
     if !output.status.success() {
-        return Err(NixError::Build);
+        return Err(NixError::Build.with_output(&output));
     }
 
     let path = String::from_utf8(output.stdout)
@@ -283,7 +1210,68 @@ pub fn realise_drv_remotely(drv_path: &str, host: &str) -> Result<String, NixErr
     Ok(path)
 }
 
-pub fn realise_toplevel_output_paths(flake_references: &[FlakeReference]) -> Result<(), NixError> {
+/// Runs `operation` and retries it up to `retries` times while it fails with
+/// a connection error, waiting `base_delay` before the first retry and twice
+/// as long before every further one. `on_retry` is told about every retry.
+pub fn retry_on_connection_error<T>(
+    retries: u32,
+    base_delay: Duration,
+    mut on_retry: impl FnMut(u32, Duration, &NixError),
+    mut operation: impl FnMut() -> Result<T, NixError>,
+) -> Result<T, NixError> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(error) if error.is_connection_error() && attempt < retries => {
+                let delay = base_delay * 2u32.saturating_pow(attempt);
+                attempt += 1;
+                on_retry(attempt, delay, &error);
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Options for the local `nix build` of system closures
+#[derive(Debug, Default, Clone)]
+pub struct BuildOptions {
+    /// Overrides nix' `builders` setting. An empty string disables remote builders.
+    pub builders: Option<String>,
+    /// Builds everything from source instead of using substituters
+    pub no_substitute: bool,
+    /// nix' `--max-jobs`, a number or `auto`
+    pub max_jobs: Option<String>,
+    /// nix' `--cores`, 0 means all cores
+    pub cores: Option<u32>,
+    /// Passed to `nix build` as they are
+    pub extra_args: Vec<String>,
+}
+
+impl BuildOptions {
+    fn nix_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(builders) = &self.builders {
+            args.extend(["--builders".to_string(), builders.clone()]);
+        }
+        if self.no_substitute {
+            args.extend(["--option", "substitute", "false"].map(String::from));
+        }
+        if let Some(max_jobs) = &self.max_jobs {
+            args.extend(["--max-jobs".to_string(), max_jobs.clone()]);
+        }
+        if let Some(cores) = self.cores {
+            args.extend(["--cores".to_string(), cores.to_string()]);
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+}
+
+pub fn realise_toplevel_output_paths(
+    flake_references: &[FlakeReference],
+    options: &BuildOptions,
+) -> Result<(), NixError> {
     let (cmd, mut args) = match which("nom") {
         Ok(_) => ("nom", vec!["build"]),
         Err(_) => ("nix", vec!["build", "--no-link"]),
@@ -300,45 +1288,118 @@ pub fn realise_toplevel_output_paths(flake_references: &[FlakeReference]) -> Res
         })
         .collect();
 
+    let option_args = options.nix_args();
     args.extend(["--json"]);
+    args.extend(option_args.iter().map(String::as_str));
+    args.extend(build_args());
     args.extend(targets.iter().map(String::as_str));
 
-    command::run_command(cmd, &args, NixError::Build).map(|_| ())
+    if cmd == "nom" {
+        command::run_command_on_terminal(cmd, &args, NixError::Build).map(|_| ())
+    } else {
+        command::run_command(cmd, &args, NixError::Build).map(|_| ())
+    }
 }
 
-pub fn reboot_host(host: &str) -> Result<(), NixError> {
+/// Reboots the host, or the local machine if `host` is `None`
+pub fn reboot_host(host: Option<&RemoteHost>) -> Result<(), NixError> {
     // Use systemctl to reboot, which will gracefully terminate the SSH connection
-    let mut cmd = command::build_remote_command(Some(host), true);
-    cmd.extend(["systemctl", "reboot"].iter().map(|&s| s.to_string()));
-
-    let output = command::run_command(
-        &cmd[0],
-        &cmd[1..].iter().map(String::as_str).collect::<Vec<_>>(),
+    command::run_remote_command(
+        &["systemctl", "reboot"],
+        host,
+        true,
         NixError::Eval("Failed to initiate reboot".to_string()),
     )?;
 
     // If we get here, the command was sent successfully
     // The SSH connection will be terminated by the reboot
     // We consider this a success
-    if !output.status.success() {
-        return Err(NixError::Eval("Reboot command failed".to_string()));
-    }
-
     Ok(())
 }
 
 #[derive(Debug)]
 pub enum SystemStatus {
     Unreachable,
+    /// The host answered, but the status script failed or printed something
+    /// unexpected, e.g. because a command it uses is missing. `stderr_tail`
+    /// holds the end of the script's error output.
+    StatusUnavailable {
+        stderr_tail: Vec<String>,
+    },
     Reachable {
         current_generation: String,
         needs_reboot: bool,
         uptime_seconds: u64,
         failed_units: usize,
+        failed_unit_names: Vec<String>,
+        /// Whether the system profile has another kernel than the expected
+        /// one, i.e. deploying would bring a kernel update
+        update_available: bool,
+        /// `None` if the system has no `/boot` or it couldn't be measured
+        boot_space: Option<BootSpace>,
+        /// `system.configurationRevision` of the system profile, `None` if
+        /// the deployed configuration doesn't set it
+        deployed_revision: Option<String>,
     },
 }
 
-pub fn check_system_status(host: Option<&str>) -> Result<SystemStatus, NixError> {
+/// Space on the boot partition, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootSpace {
+    pub size: u64,
+    pub available: u64,
+    /// Size of the current generation's kernel and initrd, which is about
+    /// what every generation on the boot partition takes
+    pub generation_size: u64,
+}
+
+impl BootSpace {
+    /// Whether the given number of generations might not fit on the boot
+    /// partition. Without a limit, whether one more generation might not fit.
+    pub fn too_small_for(&self, generations: Option<u32>) -> bool {
+        match generations {
+            Some(generations) => u64::from(generations) * self.generation_size > self.size,
+            None => self.generation_size > self.available,
+        }
+    }
+
+    /// Parses the `<size> <available> <generation size>` line of the status
+    /// script
+    fn parse(line: &str) -> Option<Self> {
+        let numbers: Vec<u64> = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        match numbers[..] {
+            [size, available, generation_size] => Some(BootSpace {
+                size,
+                available,
+                generation_size,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl SystemStatus {
+    /// Whether the system was booted after the given point in time
+    fn booted_after(&self, instant: Instant) -> bool {
+        match self {
+            Self::Reachable { uptime_seconds, .. } => {
+                Duration::from_secs(*uptime_seconds) < instant.elapsed()
+            }
+            Self::Unreachable | Self::StatusUnavailable { .. } => false,
+        }
+    }
+}
+
+/// Queries the state of a system. `expected_kernel` is the kernel of the
+/// system that would be deployed, as in `ConfigInfo::kernel`.
+pub fn check_system_status(
+    host: Option<&RemoteHost>,
+    expected_kernel: Option<&str>,
+) -> Result<SystemStatus, NixError> {
     let status_script = r#"
         set -euo pipefail
 
@@ -360,21 +1421,41 @@ pub fn check_system_status(host: Option<&str>) -> Result<SystemStatus, NixError>
         echo "$uptime_sec"
         echo "$failed_units"
         echo "$needs_reboot"
+        readlink -f "$currentgen/kernel"
+        boot_space=$(df -B1 --output=size,avail /boot 2>/dev/null | tail -n1 || true)
+        generation_size=$(stat -L -c %s "$currentgen/kernel" "$currentgen/initrd" 2>/dev/null \
+            | awk '{ sum += $1 } END { print sum }' || true)
+        echo "$boot_space $generation_size"
+        revision=$("$currentgen/sw/bin/nixos-version" --configuration-revision 2>/dev/null || true)
+        echo "$revision"
+        systemctl list-units --state=failed --no-legend --plain | cut -d' ' -f1
     "#;
 
     let output = run_script(status_script, host)?;
 
-    if !output.status.success() {
+    // Any other exit code comes from the script, so the host was reached
+    if host.is_some() && output.status.code() == Some(command::SSH_ERROR_EXIT_CODE) {
         return Ok(SystemStatus::Unreachable);
     }
+    Ok(parse_system_status(&output, expected_kernel))
+}
+
+/// Parses the output of the status script of `check_system_status`
+fn parse_system_status(output: &process::Output, expected_kernel: Option<&str>) -> SystemStatus {
+    let unavailable = || SystemStatus::StatusUnavailable {
+        stderr_tail: stderr_tail(&String::from_utf8_lossy(&output.stderr)),
+    };
+    if !output.status.success() {
+        return unavailable();
+    }
 
     let output_str = String::from_utf8_lossy(&output.stdout);
     let mut lines = output_str.lines();
 
-    // If any of these fail to parse, the system is considered unreachable
+    // If any of these fail to parse, the status can't be trusted
     let current_generation = match lines.next().map(|s| s.trim().to_string()) {
         Some(gen) if !gen.is_empty() => gen,
-        _ => return Ok(SystemStatus::Unreachable),
+        _ => return unavailable(),
     };
 
     let uptime_seconds = match lines.next().and_then(|s| {
@@ -383,54 +1464,216 @@ pub fn check_system_status(host: Option<&str>) -> Result<SystemStatus, NixError>
             .and_then(|n| n.parse::<f64>().ok().map(|f| f as u64))
     }) {
         Some(uptime) => uptime,
-        _ => return Ok(SystemStatus::Unreachable),
+        _ => return unavailable(),
     };
 
-    let failed_units = match lines.next().and_then(|s| s.parse::<usize>().ok()) {
-        Some(units) => units,
-        _ => return Ok(SystemStatus::Unreachable),
+    let Some(failed_units) = lines.next().and_then(|s| s.parse::<usize>().ok()) else {
+        return unavailable();
     };
 
     let needs_reboot = match lines.next().and_then(|s| s.parse::<u8>().ok()) {
         Some(1) => true,
         Some(0) => false,
-        _ => return Ok(SystemStatus::Unreachable),
+        _ => return unavailable(),
     };
 
-    Ok(SystemStatus::Reachable {
+    let profile_kernel = lines.next().unwrap_or_default().trim();
+    let boot_space = lines.next().and_then(BootSpace::parse);
+    let deployed_revision = lines
+        .next()
+        .map(str::trim)
+        .filter(|revision| !revision.is_empty())
+        .map(String::from);
+
+    SystemStatus::Reachable {
         current_generation,
         needs_reboot,
         uptime_seconds,
         failed_units,
-    })
+        update_available: expected_kernel.is_some_and(|kernel| kernel != profile_kernel),
+        boot_space,
+        deployed_revision,
+        // The remaining lines are the names of the failed units
+        failed_unit_names: lines
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect(),
+    }
 }
 
-pub fn run_script(script: &str, host: Option<&str>) -> Result<process::Output, NixError> {
-    match host {
-        Some(h) => {
-            let mut cmd = std::process::Command::new("ssh");
-            cmd.arg(h)
-                .arg("bash")
-                .stdin(std::process::Stdio::piped())
-                .stdout(std::process::Stdio::piped())
-                .stderr(std::process::Stdio::inherit());
-
-            let mut child = cmd
-                .spawn()
-                .map_err(|_| NixError::Eval("Failed to spawn SSH".to_string()))?;
-
-            if let Some(mut stdin) = child.stdin.take() {
-                use std::io::Write;
-                stdin
-                    .write_all(script.as_bytes())
-                    .map_err(|_| NixError::Eval("Failed to write to SSH stdin".to_string()))?;
+const REBOOT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Polls a host after a reboot that was initiated at `rebooted_at`, until it
+/// is reachable again. The host only counts as back once its uptime is
+/// shorter than the time since the reboot, so it isn't mistaken as back
+/// while it is still shutting down. `None` if it doesn't come back within
+/// `timeout`.
+pub fn wait_until_rebooted(
+    host: &RemoteHost,
+    rebooted_at: Instant,
+    timeout: Duration,
+) -> Option<SystemStatus> {
+    while rebooted_at.elapsed() < timeout {
+        thread::sleep(REBOOT_POLL_INTERVAL);
+        match check_system_status(Some(host), None) {
+            Ok(status @ SystemStatus::Reachable { .. }) if status.booted_after(rebooted_at) => {
+                return Some(status);
             }
-
-            child
-                .wait_with_output()
-                .map_err(|_| NixError::Eval("Failed to get SSH output".to_string()))
+            _ => {}
         }
-        None => command::run_command("bash", &["-c", script], NixError::Build),
+    }
+    None
+}
+
+/// Lists the package version and closure size changes between two system
+/// closures, as reported by `nix store diff-closures`. Both closures must be
+/// in the store of the machine that runs the command.
+pub fn diff_closures(
+    from: &str,
+    to: &str,
+    remote_host: Option<&RemoteHost>,
+) -> Result<String, NixError> {
+    let output = command::run_remote_command(
+        &[
+            "nix",
+            "--extra-experimental-features",
+            "nix-command",
+            "store",
+            "diff-closures",
+            from,
+            to,
+        ],
+        remote_host,
+        false,
+        NixError::Eval("Failed to diff closures".to_string()),
+    )?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The size of a store path and everything it references, in bytes. The
+/// path must be in the store of the machine that runs the command.
+pub fn closure_size(path: &str, remote_host: Option<&RemoteHost>) -> Result<u64, NixError> {
+    let output = command::run_remote_command(
+        &[
+            "nix",
+            "--extra-experimental-features",
+            "nix-command",
+            "path-info",
+            "--json",
+            "--closure-size",
+            path,
+        ],
+        remote_host,
+        false,
+        NixError::Eval("Failed to query closure size".to_string()),
+    )?;
+    let info = json::parse_nix_json_output(&output.stdout)?;
+    parse_closure_size(&info).ok_or(NixError::Deserialization)
+}
+
+/// Reads the closure size from `nix path-info --json --closure-size`, which
+/// is an object keyed by store path since nix 2.19 and an array before
+fn parse_closure_size(info: &Value) -> Option<u64> {
+    let entry = match info {
+        Value::Object(entries) => entries.values().next()?,
+        Value::Array(entries) => entries.first()?,
+        _ => return None,
+    };
+    entry["closureSize"].as_u64()
+}
+
+/// Advisory lock file on deployment targets, see `lock_host`
+const DEPLOY_LOCK_FILE: &str = "/run/nxbd.lock";
+
+/// The deploy lock of a host, which is released when this is dropped
+pub struct HostLock {
+    child: process::Child,
+}
+
+impl Drop for HostLock {
+    fn drop(&mut self) {
+        // Closing stdin ends the remote shell, which releases the lock
+        drop(self.child.stdin.take());
+        let _ = self.child.wait();
+    }
+}
+
+/// Takes an advisory lock on the host, so concurrent nxbd runs don't deploy
+/// to it at the same time. The lock is held by a remote shell that keeps
+/// running until its stdin is closed, so it is also released if nxbd or the
+/// ssh connection dies. The lock file contains `owner`, which is reported to
+/// others trying to take the lock.
+pub fn lock_host(
+    host: &RemoteHost,
+    owner: &str,
+    timeout_seconds: u64,
+) -> Result<HostLock, NixError> {
+    let script = format!(
+        "exec 9>>{DEPLOY_LOCK_FILE}\n\
+         if ! flock -w {timeout_seconds} 9; then echo \"busy $(cat {DEPLOY_LOCK_FILE})\"; exit 1; fi\n\
+         echo {owner} since \"$(date -Is)\" > {DEPLOY_LOCK_FILE}\n\
+         echo locked\n\
+         cat > /dev/null\n",
+        owner = command::shell_quote(owner),
+    );
+
+    let mut remote_command = command::build_remote_command(&["bash", "-c"], host.escalation());
+    remote_command.push(command::shell_quote(&script));
+
+    let mut child = process::Command::new("ssh")
+        .args(host.ssh_args())
+        .args(remote_command)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::inherit())
+        .spawn()
+        .map_err(|_| NixError::Lock)?;
+
+    let mut status_line = String::new();
+    if let Some(stdout) = child.stdout.take() {
+        let _ = BufReader::new(stdout).read_line(&mut status_line);
+    }
+
+    if status_line.trim() == "locked" {
+        return Ok(HostLock { child });
+    }
+
+    let _ = child.wait();
+    match status_line.trim().strip_prefix("busy") {
+        Some(holder) => Err(NixError::Locked {
+            host: host.host.clone(),
+            holder: match holder.trim() {
+                "" => "an unknown user".to_string(),
+                holder => holder.to_string(),
+            },
+        }),
+        None => Err(NixError::Lock),
+    }
+}
+
+/// The public SSH host keys of a system
+pub fn ssh_host_keys(host: Option<&RemoteHost>) -> Result<Vec<SshKeyInfo>, NixError> {
+    let output = run_script("cat /etc/ssh/ssh_host_*_key.pub", host)?;
+    if !output.status.success() {
+        return Err(
+            NixError::Eval("Failed to read the SSH host keys".to_string()).with_output(&output),
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(SshKeyInfo::from_authorized_key)
+        .collect())
+}
+
+pub fn run_script(script: &str, host: Option<&RemoteHost>) -> Result<process::Output, NixError> {
+    match host {
+        Some(h) => command::run_ssh(
+            &[h.ssh_args(), vec!["bash".to_string()]].concat(),
+            Some(script.as_bytes()),
+            NixError::Eval("Failed to run script via SSH".to_string()),
+        ),
+        None => command::command_output("bash", &["-c", script], NixError::Build),
     }
 }
 
@@ -468,4 +1711,460 @@ mod tests {
         assert_eq!(builders[1].ssh_host, "ssh://beastie");
         assert_eq!(builders[1].system, "x86_64-freebsd");
     }
+
+    #[test]
+    fn test_is_control_socket_error() {
+        assert!(command::is_control_socket_error(&[
+            "mux_client_request_session: read from master failed: Broken pipe"
+        ]));
+        assert!(command::is_control_socket_error(&[
+            "Warning: Permanently added 'host' to the list of known hosts.",
+            "Control socket connect(/tmp/ssh-host): Connection refused",
+        ]));
+        assert!(!command::is_control_socket_error(&[
+            "ssh: connect to host example.com port 22: Connection refused"
+        ]));
+    }
+
+    #[test]
+    fn test_is_connection_error() {
+        assert!(command::is_connection_error(&[
+            "ssh: connect to host example.com port 22: Connection refused"
+        ]));
+        assert!(!command::is_connection_error(&[
+            "error: builder for '/nix/store/abc-foo.drv' failed with exit code 1"
+        ]));
+    }
+
+    #[test]
+    fn test_is_password_error() {
+        assert!(command::is_password_error(&[
+            "sudo: a password is required"
+        ]));
+        assert!(command::is_password_error(&[
+            "doas: Authorization required"
+        ]));
+        assert!(command::is_password_error(&[
+            "sudo: a terminal is required to read the password; either use the -S option to read from standard input or configure an askpass helper"
+        ]));
+        assert!(!command::is_password_error(&[
+            "error: builder for '/nix/store/abc.drv' failed with exit code 1"
+        ]));
+    }
+
+    #[test]
+    fn test_build_remote_command() {
+        assert_eq!(
+            command::build_remote_command(&["nix-env", "--profile"], PrivilegeEscalation::Sudo),
+            ["sudo", "-n", "nix-env", "--profile"]
+        );
+        assert_eq!(
+            command::build_remote_command(&["nix-env", "--profile"], PrivilegeEscalation::Doas),
+            ["doas", "-n", "nix-env", "--profile"]
+        );
+        assert_eq!(
+            command::build_remote_command(&["nix-env", "--profile"], PrivilegeEscalation::None),
+            ["nix-env", "--profile"]
+        );
+    }
+
+    #[test]
+    fn test_parse_closure_size() {
+        let current = serde_json::json!({
+            "/nix/store/abc-nixos-system": { "closureSize": 1_234_567, "narSize": 1000 }
+        });
+        assert_eq!(parse_closure_size(&current), Some(1_234_567));
+        let legacy = serde_json::json!([
+            { "path": "/nix/store/abc-nixos-system", "closureSize": 42, "narSize": 1000 }
+        ]);
+        assert_eq!(parse_closure_size(&legacy), Some(42));
+        assert_eq!(parse_closure_size(&serde_json::json!({})), None);
+    }
+
+    fn connection_error() -> NixError {
+        NixError::ConnectionFailed(Box::new(NixError::Copy))
+    }
+
+    #[test]
+    fn test_retry_on_connection_error() {
+        let mut retried = Vec::new();
+        let mut attempts = 0;
+        let result = retry_on_connection_error(
+            3,
+            Duration::ZERO,
+            |attempt, _, _| retried.push(attempt),
+            || {
+                attempts += 1;
+                if attempts < 3 {
+                    Err(connection_error())
+                } else {
+                    Ok(attempts)
+                }
+            },
+        );
+        assert_eq!(result.ok(), Some(3));
+        assert_eq!(retried, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_retry_gives_up_after_retries() {
+        let mut attempts = 0;
+        let result: Result<(), _> = retry_on_connection_error(
+            1,
+            Duration::ZERO,
+            |_, _, _| {},
+            || {
+                attempts += 1;
+                Err(connection_error())
+            },
+        );
+        assert!(result.is_err_and(|error| error.is_connection_error()));
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn test_no_retry_on_build_failure() {
+        let mut attempts = 0;
+        let result: Result<(), _> = retry_on_connection_error(
+            3,
+            Duration::ZERO,
+            |_, _, _| {},
+            || {
+                attempts += 1;
+                Err(NixError::Build)
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_run_command_checks_exit_status() {
+        assert!(command::run_command("true", &[], NixError::Build).is_ok());
+        match command::run_command(
+            "sh",
+            &["-c", "echo oops >&2; exit 3"],
+            NixError::ConfigSwitch,
+        ) {
+            Err(NixError::CommandFailed {
+                error,
+                exit_code,
+                stderr_tail,
+            }) => {
+                assert!(matches!(*error, NixError::ConfigSwitch));
+                assert_eq!(exit_code, Some(3));
+                assert_eq!(stderr_tail, vec!["oops"]);
+            }
+            other => panic!("expected a failed command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_system_status() {
+        let parse = |script| parse_system_status(&run_script(script, None).unwrap(), None);
+
+        match parse(
+            "printf '/nix/store/abc-system\\n42.5 10.0\\n1\\n0\\n\\n\\n\\nnginx.service\\n'",
+        ) {
+            SystemStatus::Reachable {
+                current_generation,
+                uptime_seconds,
+                failed_unit_names,
+                ..
+            } => {
+                assert_eq!(current_generation, "/nix/store/abc-system");
+                assert_eq!(uptime_seconds, 42);
+                assert_eq!(failed_unit_names, ["nginx.service"]);
+            }
+            other => panic!("expected a reachable system, got {other:?}"),
+        }
+        match parse("echo /nix/store/abc-system; echo 'systemctl: command not found' >&2; exit 127")
+        {
+            SystemStatus::StatusUnavailable { stderr_tail } => {
+                assert_eq!(stderr_tail, ["systemctl: command not found"]);
+            }
+            other => panic!("expected an unavailable status, got {other:?}"),
+        }
+        assert!(matches!(
+            parse("echo /nix/store/abc-system; echo not-a-number"),
+            SystemStatus::StatusUnavailable { .. }
+        ));
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(command::shell_quote("alice@laptop"), "'alice@laptop'");
+        assert_eq!(command::shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_target_hosts() {
+        let target_hosts = TargetHosts::parse(&["web1=10.0.0.1".to_string()]);
+        assert_eq!(target_hosts.for_attribute("web1"), Some("10.0.0.1"));
+        assert_eq!(target_hosts.for_attribute("web2"), None);
+
+        let target_hosts =
+            TargetHosts::parse(&["10.0.0.2".to_string(), "web1=10.0.0.1".to_string()]);
+        assert_eq!(target_hosts.for_attribute("web1"), Some("10.0.0.1"));
+        assert_eq!(target_hosts.for_attribute("web2"), Some("10.0.0.2"));
+    }
+
+    #[test]
+    fn test_remote_host_destination() {
+        let default = ConnectionOptions::default().remote_host("web1");
+        assert_eq!(default.destination(), "web1");
+        assert_eq!(default.escalation(), PrivilegeEscalation::Sudo);
+
+        let root = ConnectionOptions {
+            user: Some("root".to_string()),
+            ..ConnectionOptions::default()
+        }
+        .remote_host("web1");
+        assert_eq!(root.destination(), "root@web1");
+        assert_eq!(root.escalation(), PrivilegeEscalation::None);
+
+        let doas = ConnectionOptions {
+            privilege_escalation: PrivilegeEscalation::Doas,
+            ..ConnectionOptions::default()
+        }
+        .remote_host("web1");
+        assert_eq!(doas.escalation(), PrivilegeEscalation::Doas);
+    }
+
+    #[test]
+    fn test_remote_host_ssh_opts() {
+        let options = ConnectionOptions {
+            user: None,
+            ssh_opts: ConnectionOptions::parse_ssh_opts(
+                "-p 2222 -i /key -o StrictHostKeyChecking=accept-new",
+            ),
+            connect_timeout: None,
+            retries: 0,
+            ..ConnectionOptions::default()
+        };
+        let host = options.remote_host("web1");
+        assert_eq!(
+            host.ssh_args(),
+            vec![
+                "-p",
+                "2222",
+                "-i",
+                "/key",
+                "-o",
+                "StrictHostKeyChecking=accept-new",
+                "web1"
+            ]
+        );
+        assert_eq!(host.store_url(), "ssh://web1:2222");
+        assert_eq!(
+            host.nix_ssh_opts(),
+            vec!["-i", "/key", "-o", "StrictHostKeyChecking=accept-new"]
+        );
+
+        let options = ConnectionOptions {
+            user: Some("deploy".to_string()),
+            ssh_opts: vec!["-p2222".to_string()],
+            connect_timeout: Some(10),
+            retries: 0,
+            ..ConnectionOptions::default()
+        };
+        let host = options.remote_host("web1");
+        assert_eq!(host.store_url(), "ssh://deploy@web1:2222");
+        assert_eq!(host.nix_ssh_opts(), vec!["-o", "ConnectTimeout=10"]);
+        assert_eq!(
+            host.ssh_args(),
+            vec!["-p2222", "-o", "ConnectTimeout=10", "deploy@web1"]
+        );
+        assert_eq!(
+            ConnectionOptions::default().remote_host("web1").store_url(),
+            "ssh://web1"
+        );
+    }
+
+    #[test]
+    fn test_remote_host_copy_to_args() {
+        let host = ConnectionOptions::default().remote_host("web1");
+        assert_eq!(
+            host.copy_to_args(),
+            vec!["--substitute-on-destination", "--to", "ssh://web1"]
+        );
+
+        let air_gapped = ConnectionOptions {
+            no_substitute_on_destination: true,
+            substituters: Some("https://cache.example.org".to_string()),
+            ..ConnectionOptions::default()
+        }
+        .remote_host("web1");
+        assert_eq!(
+            air_gapped.copy_to_args(),
+            vec![
+                "--option",
+                "substituters",
+                "https://cache.example.org",
+                "--to",
+                "ssh://web1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_platform_runs_on() {
+        assert!(platform_runs_on("x86_64-linux", "x86_64"));
+        assert!(platform_runs_on("aarch64-linux", "aarch64"));
+        assert!(platform_runs_on("i686-linux", "x86_64"));
+        assert!(!platform_runs_on("aarch64-linux", "x86_64"));
+        assert!(!platform_runs_on("x86_64-linux", "aarch64"));
+        assert!(!platform_runs_on("x86_64-linux", "i686"));
+    }
+
+    #[test]
+    fn test_parse_generation_link() {
+        assert_eq!(parse_generation_link("system-42-link\n"), Some(42));
+        assert_eq!(parse_generation_link("/nix/store/abc-nixos-system"), None);
+        assert_eq!(parse_generation_link("system-x-link"), None);
+    }
+
+    #[test]
+    fn test_boot_space() {
+        assert_eq!(BootSpace::parse(""), None);
+        assert_eq!(BootSpace::parse(" 62914560"), None);
+        let boot_space = BootSpace::parse("536870912 268435456 67108864").unwrap();
+        assert_eq!(
+            boot_space,
+            BootSpace {
+                size: 512 << 20,
+                available: 256 << 20,
+                generation_size: 64 << 20,
+            }
+        );
+
+        assert!(!boot_space.too_small_for(Some(8)));
+        assert!(boot_space.too_small_for(Some(10)));
+        assert!(!boot_space.too_small_for(None));
+        let full = BootSpace {
+            available: 32 << 20,
+            ..boot_space
+        };
+        assert!(full.too_small_for(None));
+    }
+
+    #[test]
+    fn test_booted_after() {
+        let status = |uptime_seconds| SystemStatus::Reachable {
+            current_generation: String::new(),
+            needs_reboot: false,
+            uptime_seconds,
+            failed_units: 0,
+            failed_unit_names: vec![],
+            update_available: false,
+            boot_space: None,
+            deployed_revision: None,
+        };
+        let Some(rebooted_at) = Instant::now().checked_sub(Duration::from_secs(90)) else {
+            return;
+        };
+
+        assert!(status(20).booted_after(rebooted_at));
+        assert!(!status(3600).booted_after(rebooted_at));
+        assert!(!SystemStatus::Unreachable.booted_after(rebooted_at));
+    }
+
+    #[test]
+    fn test_parse_generations() {
+        let output = "  41   2024-05-01 12:00:00   \n\
+                      \x20 42   2024-05-02 10:30:00   (current)\n\
+                      ---\n\
+                      system-41-link /nix/store/aaa-nixos-system\n\
+                      system-42-link /nix/store/bbb-nixos-system\n";
+        assert_eq!(
+            parse_generations(output),
+            vec![
+                Generation {
+                    number: 41,
+                    date: "2024-05-01 12:00:00".to_string(),
+                    current: false,
+                    store_path: Some("/nix/store/aaa-nixos-system".to_string()),
+                },
+                Generation {
+                    number: 42,
+                    date: "2024-05-02 10:30:00".to_string(),
+                    current: true,
+                    store_path: Some("/nix/store/bbb-nixos-system".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_gc_summary() {
+        let output = "removing old generations of profile /nix/var/nix/profiles/system\n\
+                      deleting '/nix/store/abc-foo'\n\
+                      1234 store paths deleted, 567.89 MiB freed\n";
+        assert_eq!(
+            parse_gc_summary(output),
+            Some(GcResult {
+                paths_deleted: 1234,
+                freed: "567.89 MiB".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_gc_summary("finding garbage collector roots...\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_build_options_nix_args() {
+        assert!(BuildOptions::default().nix_args().is_empty());
+        let local_only = BuildOptions {
+            builders: Some(String::new()),
+            ..BuildOptions::default()
+        };
+        assert_eq!(local_only.nix_args(), vec!["--builders", ""]);
+        let from_source = BuildOptions {
+            no_substitute: true,
+            ..BuildOptions::default()
+        };
+        assert_eq!(
+            from_source.nix_args(),
+            vec!["--option", "substitute", "false"]
+        );
+        let constrained = BuildOptions {
+            max_jobs: Some("2".to_string()),
+            cores: Some(4),
+            extra_args: vec!["--keep-going".to_string()],
+            ..BuildOptions::default()
+        };
+        assert_eq!(
+            constrained.nix_args(),
+            vec!["--max-jobs", "2", "--cores", "4", "--keep-going"]
+        );
+    }
+
+    #[test]
+    fn test_eval_options_args() {
+        let default = EvalOptions::default();
+        assert!(default.eval_args().is_empty());
+        assert!(default.build_args().is_empty());
+
+        let options = EvalOptions {
+            show_trace: true,
+            impure: true,
+            no_registries: true,
+            ..EvalOptions::default()
+        };
+        assert_eq!(
+            options.eval_args(),
+            vec!["--show-trace", "--impure", "--no-use-registries"]
+        );
+        assert_eq!(
+            options.build_args(),
+            vec![
+                "--show-trace",
+                "--impure",
+                "--no-use-registries",
+                "--keep-failed"
+            ]
+        );
+    }
 }
@@ -0,0 +1,227 @@
+use super::hostlog::HostLog;
+use super::humanize::format_bytes;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Activity and result type numbers of nix's `--log-format internal-json`
+const ACT_COPY_PATH: u64 = 100;
+const ACT_COPY_PATHS: u64 = 103;
+const RES_PROGRESS: u64 = 105;
+const RES_SET_EXPECTED: u64 = 106;
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum LogEntry {
+    Start {
+        id: u64,
+        #[serde(rename = "type")]
+        activity_type: u64,
+    },
+    Stop {},
+    Result {
+        id: u64,
+        #[serde(rename = "type")]
+        result_type: u64,
+        #[serde(default)]
+        fields: Vec<Value>,
+    },
+    Msg {
+        msg: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LogEvent {
+    Progress,
+    Message(String),
+    Other,
+}
+
+/// Progress of a `nix copy` invocation, accumulated from its log output
+#[derive(Debug, Default)]
+pub struct CopyProgress {
+    activity_types: HashMap<u64, u64>,
+    copied_bytes: HashMap<u64, u64>,
+    pub paths_done: u64,
+    pub paths_expected: u64,
+    pub bytes_expected: u64,
+    pub messages: Vec<String>,
+}
+
+impl CopyProgress {
+    /// Processes one line of nix's stderr. Lines that are not part of the
+    /// internal-json log are passed through as messages.
+    pub fn process_line(&mut self, line: &str) -> LogEvent {
+        let Some(json) = line.strip_prefix("@nix ") else {
+            return LogEvent::Message(line.to_string());
+        };
+        let Ok(entry) = serde_json::from_str::<LogEntry>(json) else {
+            return LogEvent::Other;
+        };
+
+        match entry {
+            LogEntry::Start { id, activity_type } => {
+                self.activity_types.insert(id, activity_type);
+                LogEvent::Other
+            }
+            LogEntry::Stop {} => LogEvent::Other,
+            LogEntry::Result {
+                id,
+                result_type,
+                fields,
+            } => {
+                let field = |i: usize| fields.get(i).and_then(Value::as_u64).unwrap_or(0);
+                match (self.activity_types.get(&id).copied(), result_type) {
+                    (Some(ACT_COPY_PATHS), RES_PROGRESS) => {
+                        self.paths_done = field(0);
+                        self.paths_expected = field(1);
+                        LogEvent::Progress
+                    }
+                    (Some(ACT_COPY_PATHS), RES_SET_EXPECTED) if field(0) == ACT_COPY_PATH => {
+                        self.bytes_expected = field(1);
+                        LogEvent::Progress
+                    }
+                    (Some(ACT_COPY_PATH), RES_PROGRESS) => {
+                        self.copied_bytes.insert(id, field(0));
+                        LogEvent::Progress
+                    }
+                    _ => LogEvent::Other,
+                }
+            }
+            LogEntry::Msg { msg } => LogEvent::Message(msg),
+        }
+    }
+
+    pub fn bytes_done(&self) -> u64 {
+        self.copied_bytes.values().sum()
+    }
+}
+
+impl fmt::Display for CopyProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "copied {}/{} paths ({} / {})",
+            self.paths_done,
+            self.paths_expected,
+            format_bytes(self.bytes_done()),
+            format_bytes(self.bytes_expected)
+        )
+    }
+}
+
+/// Number of `nix copy` invocations whose progress is being reported. A
+/// status line can only be updated in place while there is just one.
+static RUNNING_COPIES: AtomicUsize = AtomicUsize::new(0);
+
+struct RunningCopy;
+
+impl RunningCopy {
+    fn start() -> Self {
+        RUNNING_COPIES.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+
+    fn is_only_copy() -> bool {
+        RUNNING_COPIES.load(Ordering::SeqCst) == 1
+    }
+}
+
+impl Drop for RunningCopy {
+    fn drop(&mut self) {
+        RUNNING_COPIES.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Reads the internal-json log of `nix copy` and reports the progress to the
+/// host's log. While it is the only copy running on a terminal, the host's
+/// status line is updated, otherwise the progress is logged as a phase every
+/// few seconds. Messages are logged and collected.
+pub fn report_copy_progress(log: &HostLog, stderr: impl Read) -> CopyProgress {
+    let _running = RunningCopy::start();
+
+    let mut progress = CopyProgress::default();
+    let mut last_report: Option<Instant> = None;
+
+    for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+        match progress.process_line(&line) {
+            LogEvent::Message(msg) => {
+                log.log(&msg);
+                progress.messages.push(msg);
+            }
+            LogEvent::Progress => {
+                let in_place = log.shows_status() && RunningCopy::is_only_copy();
+                let interval = if in_place {
+                    Duration::from_millis(100)
+                } else {
+                    Duration::from_secs(5)
+                };
+                if last_report.is_some_and(|t| t.elapsed() < interval) {
+                    continue;
+                }
+                last_report = Some(Instant::now());
+                if in_place {
+                    log.status(&progress);
+                } else {
+                    log.clear_status();
+                    log.phase(&progress);
+                }
+            }
+            LogEvent::Other => {}
+        }
+    }
+
+    log.clear_status();
+    if progress.paths_expected > 0 {
+        log.log(&progress);
+    }
+
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_progress() {
+        let mut progress = CopyProgress::default();
+        let lines = [
+            r#"@nix {"action":"start","id":1,"level":3,"parent":0,"text":"copying 2 paths","type":103}"#,
+            r#"@nix {"action":"result","id":1,"type":106,"fields":[100,3072]}"#,
+            r#"@nix {"action":"start","id":2,"level":3,"parent":1,"text":"copying path","type":100}"#,
+            r#"@nix {"action":"result","id":2,"type":105,"fields":[1024,2048,0,0]}"#,
+            r#"@nix {"action":"result","id":1,"type":105,"fields":[1,2,1,0]}"#,
+        ];
+        for line in lines {
+            progress.process_line(line);
+        }
+        assert_eq!(progress.paths_done, 1);
+        assert_eq!(progress.paths_expected, 2);
+        assert_eq!(progress.bytes_expected, 3072);
+        assert_eq!(progress.bytes_done(), 1024);
+        assert_eq!(progress.to_string(), "copied 1/2 paths (1.0 KiB / 3.0 KiB)");
+    }
+
+    #[test]
+    fn test_copy_progress_messages() {
+        let mut progress = CopyProgress::default();
+        assert_eq!(
+            progress.process_line(r#"@nix {"action":"msg","level":0,"msg":"error: oops"}"#),
+            LogEvent::Message("error: oops".to_string())
+        );
+        assert_eq!(
+            progress.process_line("plain output"),
+            LogEvent::Message("plain output".to_string())
+        );
+        assert_eq!(
+            progress.process_line(r#"@nix {"action":"stop","id":1}"#),
+            LogEvent::Other
+        );
+    }
+}
@@ -1,33 +1,57 @@
+use super::evalcache::{cache_key, EvalCache};
+use super::nixcommands::{eval_args, flake_fingerprint};
 use super::sshkeys::SshKeyInfo;
 use super::{FlakeReference, NixError};
 
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::str;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 #[allow(clippy::module_name_repetitions)]
 pub struct ConfigInfo {
     pub amd_microcode: bool,
+    pub auto_upgrade_enabled: bool,
     pub boot_grub: bool,
     pub boot_grub_generations: Option<i32>,
     pub boot_is_container: bool,
+    /// `boot.loader.timeout` in seconds, `None` waits for a selection
+    pub boot_loader_timeout: Option<i32>,
     pub boot_systemd: bool,
     pub boot_systemd_generations: Option<i32>,
     pub command_not_found: bool,
+    /// `system.configurationRevision`, usually set to the flake's revision
+    pub configuration_revision: Option<String>,
+    pub doas_enabled: bool,
+    pub doas_wheel_needs_password: bool,
     pub doc_dev_enable: bool,
     pub doc_doc_enable: bool,
     pub doc_enable: bool,
     pub doc_info_enable: bool,
     pub doc_man_enable: bool,
     pub doc_nixos_enabled: bool,
+    pub fail2ban_enabled: bool,
+    pub firewall_allow_ping: bool,
+    pub firewall_allowed_tcp_ports: Vec<u16>,
+    pub firewall_allowed_udp_ports: Vec<u16>,
+    pub firmware_all: bool,
+    pub firmware_redistributable: bool,
     pub font_fontconfig_enable: bool,
     pub fqdn: Option<String>,
     pub fqdn_or_host_name: String,
     pub host_name: String,
+    pub i18n_supported_locales: Vec<String>,
     pub intel_microcode: bool,
+    /// Whether the system runs in a container or a virtual machine
+    pub is_virtual: bool,
     pub is_x86: bool,
+    /// The kernel image that the system's `kernel` symlink points to
+    pub kernel: String,
+    pub journald_extra_config: String,
     pub log_refused_connections: bool,
+    pub man_generate_caches: bool,
     pub networking_firewall_enabled: bool,
     pub nginx_brotli: bool,
     pub nginx_enabled: bool,
@@ -36,17 +60,36 @@ pub struct ConfigInfo {
     pub nginx_proxy: bool,
     pub nginx_tls: bool,
     pub nix_auto_optimise_store: bool,
+    /// Whether the system serves as a remote builder: it has
+    /// `nix.sshServe.enable` or the `build-host` tag in `system.nixos.tags`
+    pub nix_build_host: bool,
+    pub nix_cores: u32,
     pub nix_extra_options: String,
     pub nix_settings_experimental_features: String,
     pub nix_gc: bool,
+    /// `nix.settings.max-jobs`, a number or `auto`
+    pub nix_max_jobs: String,
     pub nix_optimise_automatic: bool,
     pub nix_trusts_wheel: bool,
+    pub predictable_interface_names: bool,
     pub ssh_enabled: bool,
     pub ssh_password_authentication: bool,
+    /// `services.openssh.settings.PermitRootLogin`, booleans as `yes`/`no`
+    pub ssh_permit_root_login: String,
+    pub state_version: Option<String>,
+    /// Whether the system has swap devices or zram swap
+    pub swap_configured: bool,
+    /// Whether `system.stateVersion` is set in the configuration rather than
+    /// defaulted to the nixpkgs release. `None` if this can't be determined.
+    pub state_version_explicit: Option<bool>,
     pub stub_ld: bool,
     pub sudo_enabled: bool,
     pub sudo_wheel_only: bool,
     pub system: String,
+    /// Whether timesyncd, chrony or ntp is enabled
+    pub time_sync_enabled: bool,
+    pub tmp_clean_on_boot: bool,
+    pub tmp_on_tmpfs: bool,
     pub toplevel_drv: String,
     pub toplevel_out: String,
     pub users: Vec<NixUser>,
@@ -54,13 +97,16 @@ pub struct ConfigInfo {
     pub wheel_needs_password: bool,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 #[allow(clippy::module_name_repetitions)]
 pub struct NixUser {
     pub name: String,
     pub extra_groups: Vec<String>,
-    #[serde(deserialize_with = "deserialize_ssh_keys")]
+    #[serde(
+        deserialize_with = "deserialize_ssh_keys",
+        serialize_with = "serialize_ssh_keys"
+    )]
     pub ssh_keys: Vec<SshKeyInfo>,
 }
 
@@ -75,11 +121,30 @@ where
         .collect())
 }
 
-pub fn nixos_deploy_info(flake_reference: &FlakeReference) -> Result<ConfigInfo, NixError> {
-    // At this point we're just mindlessly piling up all the attributes of a
-    // config that the checks would ever need. Maybe at some point in the future
-    // this should be modularized.
-    let nix_expr = r#"{ config, pkgs, ... }:
+/// Writes the keys back in `authorized_keys` format, so serialized
+/// configurations can be read again with `--from-json`
+fn serialize_ssh_keys<S: Serializer>(
+    keys: &[SshKeyInfo],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(keys.iter().map(ToString::to_string))
+}
+
+/// Whether `local_hostname`, as reported by the local machine, names the system
+/// with the given `networking.hostName` and FQDN. Host names are compared
+/// case-insensitively, and a short name matches its FQDN either way round.
+pub fn hostname_matches(local_hostname: &str, host_name: &str, fqdn_or_host_name: &str) -> bool {
+    let short_name = |name: &str| name.split('.').next().unwrap_or_default().to_string();
+    local_hostname.eq_ignore_ascii_case(fqdn_or_host_name)
+        || short_name(local_hostname).eq_ignore_ascii_case(&short_name(host_name))
+}
+
+/// Applied to a NixOS configuration, evaluates to its [`ConfigInfo`].
+///
+/// At this point we're just mindlessly piling up all the attributes of a
+/// config that the checks would ever need. Maybe at some point in the future
+/// this should be modularized.
+const DEPLOY_INFO_EXPR: &str = r#"{ config, options, pkgs, ... }:
         let
           tryOrNull = x:
             let r = builtins.tryEval x;
@@ -91,29 +156,49 @@ pub fn nixos_deploy_info(flake_reference: &FlakeReference) -> Result<ConfigInfo,
                 inherit (user) name extraGroups;
                 sshKeys = user.openssh.authorizedKeys.keys or [];
             }) (builtins.filter
-                (user: (user.isNormalUser or false))
+                (user: (user.isNormalUser or false) || user.name == "root")
                 (builtins.attrValues config.users.users));
 
             amdMicrocode = config.hardware.cpu.amd.updateMicrocode;
+            autoUpgradeEnabled = config.system.autoUpgrade.enable;
             bootGrub = config.boot.loader.grub.enable;
             bootGrubGenerations = config.boot.loader.grub.configurationLimit;
             bootIsContainer = config.boot.isContainer;
+            bootLoaderTimeout = config.boot.loader.timeout;
             bootSystemd = config.boot.loader.systemd-boot.enable;
             bootSystemdGenerations = config.boot.loader.systemd-boot.configurationLimit;
             commandNotFound = config.programs.command-not-found.enable;
+            configurationRevision = config.system.configurationRevision;
+            doasEnabled = config.security.doas.enable;
+            doasWheelNeedsPassword = config.security.doas.wheelNeedsPassword;
             docDevEnable = config.documentation.dev.enable;
             docDocEnable = config.documentation.doc.enable;
             docEnable = config.documentation.enable;
             docInfoEnable = config.documentation.info.enable;
             docManEnable = config.documentation.man.enable;
             docNixosEnabled = config.documentation.nixos.enable;
+            fail2banEnabled = config.services.fail2ban.enable;
+            firewallAllowPing = config.networking.firewall.allowPing;
+            firewallAllowedTcpPorts = config.networking.firewall.allowedTCPPorts;
+            firewallAllowedUdpPorts = config.networking.firewall.allowedUDPPorts;
+            firmwareAll = config.hardware.enableAllFirmware;
+            firmwareRedistributable = config.hardware.enableRedistributableFirmware;
             fontFontconfigEnable = config.fonts.fontconfig.enable;
             fqdn = tryOrNull config.networking.fqdn;
             fqdnOrHostName = config.networking.fqdnOrHostName;
             hostName = config.networking.hostName;
+            i18nSupportedLocales = config.i18n.supportedLocales;
             intelMicrocode = config.hardware.cpu.intel.updateMicrocode;
+            isVirtual = config.boot.isContainer
+                || (config.services.qemuGuest.enable or false)
+                || (config.virtualisation.hypervGuest.enable or false)
+                || (config.virtualisation.vmware.guest.enable or false)
+                || (config.virtualisation.virtualbox.guest.enable or false);
             isX86 = pkgs.stdenv.hostPlatform.isx86;
+            kernel = "${config.boot.kernelPackages.kernel}/${config.system.boot.loader.kernelFile}";
+            journaldExtraConfig = config.services.journald.extraConfig;
             logRefusedConnections = config.networking.firewall.logRefusedConnections;
+            manGenerateCaches = config.documentation.man.generateCaches;
             networkingFirewallEnabled = config.networking.firewall.enable;
             nginxBrotli = config.services.nginx.recommendedBrotliSettings;
             nginxEnabled = config.services.nginx.enable;
@@ -122,43 +207,216 @@ pub fn nixos_deploy_info(flake_reference: &FlakeReference) -> Result<ConfigInfo,
             nginxProxy = config.services.nginx.recommendedProxySettings;
             nginxTls = config.services.nginx.recommendedTlsSettings;
             nixAutoOptimiseStore = config.nix.settings.auto-optimise-store;
+            nixBuildHost = config.nix.sshServe.enable
+                || builtins.elem "build-host" config.system.nixos.tags;
+            nixCores = config.nix.settings.cores;
             nixExtraOptions = config.nix.extraOptions;
             nixSettingsExperimentalFeatures = config.nix.settings.experimental-features or "";
             nixGc = config.nix.gc.automatic;
+            nixMaxJobs = toString config.nix.settings.max-jobs;
             nixOptimiseAutomatic = config.nix.optimise.automatic;
             nixTrustsWheel = builtins.elem "@wheel" config.nix.settings.trusted-users;
+            predictableInterfaceNames = config.networking.usePredictableInterfaceNames;
             sshEnabled = config.services.openssh.enable;
             sshPasswordAuthentication = config.services.openssh.settings.PasswordAuthentication;
+            sshPermitRootLogin =
+              let permit = config.services.openssh.settings.PermitRootLogin;
+              in if builtins.isBool permit then (if permit then "yes" else "no") else permit;
+            stateVersion = tryOrNull config.system.stateVersion;
+            # Option defaults have priority 1500, anything set in a module has a lower value
+            stateVersionExplicit = tryOrNull (options.system.stateVersion.highestPrio < 1500);
             stubLd = config.environment.stub-ld.enable;
+            swapConfigured = (config.swapDevices != []) || config.zramSwap.enable;
             sudoEnabled = config.security.sudo.enable;
             sudoWheelOnly = config.security.sudo.execWheelOnly;
+            timeSyncEnabled = config.services.timesyncd.enable
+                || config.services.chrony.enable
+                || config.services.ntp.enable;
+            tmpCleanOnBoot = config.boot.tmp.cleanOnBoot;
+            tmpOnTmpfs = config.boot.tmp.useTmpfs;
             toplevelDrv = config.system.build.toplevel.drvPath;
             toplevelOut = config.system.build.toplevel;
             usersMutable = config.users.mutableUsers;
             wheelNeedsPassword = config.security.sudo.wheelNeedsPassword;
         }"#;
 
-    let output = std::process::Command::new("nix")
-        .args([
-            "eval",
-            "--json",
-            &format!(
-                "{}#nixosConfigurations.\"{}\"",
-                flake_reference.url, flake_reference.attribute
-            ),
-            "--apply",
-            nix_expr,
-        ])
-        .output()
-        .map_err(|_| NixError::Eval("Failed to execute nix eval".to_string()))?;
-
-    if !output.status.success() {
-        return Err(NixError::Eval(
-            String::from_utf8_lossy(&output.stderr).into_owned(),
+/// Evaluates the attributes of a system that deployments and checks need.
+/// With a cache, results are reused as long as the flake's contents don't
+/// change.
+pub fn nixos_deploy_info(
+    flake_reference: &FlakeReference,
+    cache: Option<&EvalCache>,
+) -> Result<ConfigInfo, NixError> {
+    // The expression is part of the key, so other nxbd versions don't pick up
+    // entries with different attributes
+    let key = cache.and_then(|_| {
+        let fingerprint = flake_fingerprint(&flake_reference.url)?;
+        Some(cache_key(&[
+            &flake_reference.url,
+            &flake_reference.attribute,
+            &fingerprint,
+            DEPLOY_INFO_EXPR,
+        ]))
+    });
+
+    cached_eval(cache, key.as_deref(), || {
+        let output = std::process::Command::new("nix")
+            .args([
+                "eval",
+                "--json",
+                &format!(
+                    "{}#nixosConfigurations.\"{}\"",
+                    flake_reference.url, flake_reference.attribute
+                ),
+                "--apply",
+                DEPLOY_INFO_EXPR,
+            ])
+            .args(eval_args())
+            .output()
+            .map_err(|_| NixError::Eval("Failed to execute nix eval".to_string()))?;
+
+        if !output.status.success() {
+            return Err(NixError::Eval(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+
+        String::from_utf8(output.stdout).map_err(|_| NixError::Deserialization)
+    })
+}
+
+/// Deserializes the JSON that `eval` produces. With a cache and a key, a
+/// cached result is used instead of calling `eval`, and new results are stored.
+fn cached_eval<T: DeserializeOwned>(
+    cache: Option<&EvalCache>,
+    key: Option<&str>,
+    eval: impl FnOnce() -> Result<String, NixError>,
+) -> Result<T, NixError> {
+    let cache = cache.zip(key);
+    if let Some((cache, key)) = cache {
+        if let Some(value) = cache
+            .get(key)
+            .and_then(|json| serde_json::from_str(&json).ok())
+        {
+            return Ok(value);
+        }
+    }
+
+    let json = eval()?;
+    let value = serde_json::from_str(&json).map_err(|_| NixError::Deserialization)?;
+    if let Some((cache, key)) = cache {
+        cache.put(key, &json);
+    }
+    Ok(value)
+}
+
+/// Reads already evaluated configurations from JSON, as produced by `nix eval`
+/// with [`DEPLOY_INFO_EXPR`].
+///
+/// The input is either a single configuration, which is then named after its
+/// `hostName`, or an object of configurations keyed by system attribute.
+pub fn config_infos_from_json(contents: &str) -> Result<Vec<(String, ConfigInfo)>, String> {
+    let value: Value = serde_json::from_str(contents).map_err(|e| format!("Invalid JSON: {e}"))?;
+
+    let Value::Object(object) = value else {
+        return Err("Expected a JSON object".to_string());
+    };
+
+    if object.contains_key("hostName") {
+        let info = ConfigInfo::deserialize(Value::Object(object))
+            .map_err(|e| format!("Invalid configuration: {e}"))?;
+        return Ok(vec![(info.host_name.clone(), info)]);
+    }
+
+    object
+        .into_iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(attribute, value)| {
+            ConfigInfo::deserialize(value)
+                .map(|info| (attribute.clone(), info))
+                .map_err(|e| format!("Invalid configuration for system '{attribute}': {e}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached_eval() {
+        let dir =
+            std::env::temp_dir().join(format!("nxbd-cached-eval-test-{}", std::process::id()));
+        let cache = EvalCache::new(dir.clone());
+        let key = cache_key(&["cached-eval"]);
+
+        let value: Vec<i32> =
+            cached_eval(Some(&cache), Some(&key), || Ok("[1]".to_string())).unwrap();
+        assert_eq!(value, [1]);
+        // The cached result is used from now on
+        let value: Vec<i32> =
+            cached_eval(Some(&cache), Some(&key), || Err(NixError::Deserialization)).unwrap();
+        assert_eq!(value, [1]);
+        // Without a key, the cache isn't consulted
+        let value: Vec<i32> = cached_eval(Some(&cache), None, || Ok("[2]".to_string())).unwrap();
+        assert_eq!(value, [2]);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_config_infos_from_json_rejects_non_objects() {
+        assert!(config_infos_from_json("[]").is_err());
+        assert!(config_infos_from_json("not json").is_err());
+    }
+
+    #[test]
+    fn test_config_infos_from_json_reports_missing_fields() {
+        let err = config_infos_from_json(r#"{"hostName": "foo"}"#).unwrap_err();
+        assert!(err.contains("missing field"), "unexpected error: {err}");
+    }
+
+    #[test]
+    fn test_config_infos_from_json_names_broken_system() {
+        let err = config_infos_from_json(r#"{"foo": {"system": "x86_64-linux"}}"#).unwrap_err();
+        assert!(err.contains("'foo'"), "unexpected error: {err}");
+        assert!(err.contains("missing field"), "unexpected error: {err}");
+    }
+
+    #[test]
+    fn test_hostname_matches() {
+        assert!(hostname_matches("web1", "web1", "web1"));
+        assert!(hostname_matches("WEB1", "web1", "web1"));
+        assert!(hostname_matches("web1", "web1", "web1.example.com"));
+        assert!(hostname_matches(
+            "web1.example.com",
+            "web1",
+            "web1.example.com"
+        ));
+        assert!(hostname_matches(
+            "Web1.Example.com",
+            "web1",
+            "web1.example.com"
+        ));
+        assert!(!hostname_matches("web2", "web1", "web1.example.com"));
+        assert!(!hostname_matches(
+            "web2.example.com",
+            "web1",
+            "web1.example.com"
         ));
     }
 
-    let stdout_str = str::from_utf8(&output.stdout).map_err(|_| NixError::Deserialization)?;
+    #[test]
+    fn test_nix_user_roundtrip() {
+        let json = r#"{"name":"alice","extraGroups":["wheel"],"sshKeys":["ssh-ed25519 AAAAC3 alice@laptop","invalid"]}"#;
+        let user: NixUser = serde_json::from_str(json).unwrap();
+        assert_eq!(user.ssh_keys.len(), 1);
 
-    serde_json::from_str(&stdout_str).map_err(|_| NixError::Deserialization)
+        let serialized = serde_json::to_string(&user).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"name":"alice","extraGroups":["wheel"],"sshKeys":["ssh-ed25519 AAAAC3 alice@laptop"]}"#
+        );
+    }
 }
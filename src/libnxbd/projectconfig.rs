@@ -0,0 +1,287 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the project config file in the flake root
+pub const PROJECT_CONFIG_FILE: &str = ".nxbd.toml";
+
+/// Where the project config of the flake at `flake_url` is. Only local flakes
+/// have a root to read it from, flakes that nix fetches have none.
+pub fn project_config_path(flake_url: &str) -> Option<PathBuf> {
+    let path = ["path:", "git+file://"]
+        .iter()
+        .find_map(|scheme| flake_url.strip_prefix(scheme))
+        .unwrap_or(flake_url);
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    if path.contains(':') {
+        return None;
+    }
+    Some(Path::new(path).join(PROJECT_CONFIG_FILE))
+}
+
+/// Defaults for command line options, from `.nxbd.toml` in the flake root.
+/// Options given on the command line always win. This only holds invocation
+/// defaults, nothing about how individual hosts are deployed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ProjectConfig {
+    pub remote_user: Option<String>,
+    pub ssh_opts: Option<String>,
+    pub ignore_file: Option<Vec<String>>,
+    pub max_parallel: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProjectConfigError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ProjectConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// The values of the TOML subset that `.nxbd.toml` supports
+#[derive(Debug, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "a string",
+            Self::Integer(_) => "an integer",
+            Self::Array(_) => "an array",
+        }
+    }
+}
+
+impl ProjectConfig {
+    /// Reads the config file at `path`. A missing file is an empty config.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Parses top-level `key = value` pairs with string, integer and array
+    /// values, which is all the options need. Tables are rejected, so
+    /// settings can't silently end up in a section nxbd doesn't read.
+    pub fn parse(contents: &str) -> Result<Self, ProjectConfigError> {
+        let mut config = Self::default();
+        for (index, line) in contents.lines().enumerate() {
+            let error = |message: String| ProjectConfigError {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(error("tables are not supported".to_string()));
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected `key = value`".to_string()));
+            };
+            let key = key.trim();
+            let (value, rest) = parse_value(value.trim_start()).map_err(error)?;
+            let rest = rest.trim_start();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(error(format!("unexpected `{rest}` after the value")));
+            }
+            config.set(key, value).map_err(error)?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        let expected = |expected: &str, value: &Value| {
+            format!("`{key}` must be {expected}, not {}", value.type_name())
+        };
+        match (key, value) {
+            ("remote-user", Value::String(user)) => self.remote_user = Some(user),
+            ("ssh-opts", Value::String(opts)) => self.ssh_opts = Some(opts),
+            ("ignore-file", Value::String(path)) => self.ignore_file = Some(vec![path]),
+            ("ignore-file", Value::Array(values)) => {
+                let paths = values
+                    .into_iter()
+                    .map(|value| match value {
+                        Value::String(path) => Ok(path),
+                        other => Err(expected("an array of strings", &other)),
+                    })
+                    .collect::<Result<_, _>>()?;
+                self.ignore_file = Some(paths);
+            }
+            ("max-parallel", Value::Integer(n)) => {
+                self.max_parallel = Some(
+                    usize::try_from(n)
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| format!("`{key}` must be at least 1"))?,
+                );
+            }
+            ("remote-user" | "ssh-opts", value) => return Err(expected("a string", &value)),
+            ("ignore-file", value) => {
+                return Err(expected("a string or an array of strings", &value))
+            }
+            ("max-parallel", value) => return Err(expected("an integer", &value)),
+            _ => return Err(format!("unknown key `{key}`")),
+        }
+        Ok(())
+    }
+}
+
+/// Parses the value at the start of `input`, returning it and the rest
+fn parse_value(input: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = input.strip_prefix('"') {
+        parse_basic_string(rest)
+    } else if let Some(rest) = input.strip_prefix('\'') {
+        let (literal, rest) = rest
+            .split_once('\'')
+            .ok_or_else(|| "unterminated string".to_string())?;
+        Ok((Value::String(literal.to_string()), rest))
+    } else if let Some(mut rest) = input.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            let (value, after) = parse_value(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                return Err("expected `,` or `]` in array".to_string());
+            }
+        }
+    } else {
+        let end = input
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '+' || c == '_'))
+            .unwrap_or(input.len());
+        let (token, rest) = input.split_at(end);
+        match token.replace('_', "").parse() {
+            Ok(n) => Ok((Value::Integer(n), rest)),
+            Err(_) if token.is_empty() => Err("missing value".to_string()),
+            Err(_) => Err(format!("unsupported value `{token}`")),
+        }
+    }
+}
+
+/// Parses a double-quoted string after its opening quote
+fn parse_basic_string(input: &str) -> Result<(Value, &str), String> {
+    let mut result = String::new();
+    let mut chars = input.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((Value::String(result), &input[index + 1..])),
+            '\\' => match chars.next().map(|(_, escaped)| escaped) {
+                Some('"') => result.push('"'),
+                Some('\\') => result.push('\\'),
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(other) => return Err(format!("unsupported escape `\\{other}`")),
+                None => break,
+            },
+            c => result.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_project_config() {
+        let config = ProjectConfig::parse(
+            r#"
+            # Defaults for this flake
+            remote-user = "deploy"
+            ssh-opts = '-i ~/.ssh/deploy_key'  # literal string
+            ignore-file = [".nxbd-ignore.yaml", "team.yaml",]
+            max-parallel = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            ProjectConfig {
+                remote_user: Some("deploy".to_string()),
+                ssh_opts: Some("-i ~/.ssh/deploy_key".to_string()),
+                ignore_file: Some(vec![
+                    ".nxbd-ignore.yaml".to_string(),
+                    "team.yaml".to_string()
+                ]),
+                max_parallel: Some(4),
+            }
+        );
+
+        assert_eq!(
+            ProjectConfig::parse(r#"ssh-opts = "-o \"ProxyJump=bastion\"""#)
+                .unwrap()
+                .ssh_opts
+                .as_deref(),
+            Some("-o \"ProxyJump=bastion\"")
+        );
+        assert_eq!(ProjectConfig::parse("").unwrap(), ProjectConfig::default());
+    }
+
+    #[test]
+    fn test_parse_project_config_errors() {
+        let error = |contents| ProjectConfig::parse(contents).unwrap_err();
+        assert_eq!(
+            error("remote-user = \"deploy\"\nmax-parallel = \"4\""),
+            ProjectConfigError {
+                line: 2,
+                message: "`max-parallel` must be an integer, not a string".to_string()
+            }
+        );
+        assert_eq!(
+            error("max-parallel = 0").message,
+            "`max-parallel` must be at least 1"
+        );
+        assert_eq!(
+            error("remote_user = \"x\"").message,
+            "unknown key `remote_user`"
+        );
+        assert_eq!(error("[hosts]").message, "tables are not supported");
+        assert_eq!(error("remote-user = \"x").message, "unterminated string");
+        assert_eq!(
+            error("remote-user = \"x\" y").message,
+            "unexpected `y` after the value"
+        );
+        assert_eq!(
+            error("remote-user = true").message,
+            "unsupported value `true`"
+        );
+        assert_eq!(error("remote-user").message, "expected `key = value`");
+    }
+
+    #[test]
+    fn test_project_config_path() {
+        let path = |url| project_config_path(url).map(|path| path.display().to_string());
+        assert_eq!(path(".").as_deref(), Some("./.nxbd.toml"));
+        assert_eq!(path("/srv/infra").as_deref(), Some("/srv/infra/.nxbd.toml"));
+        assert_eq!(
+            path("path:../infra").as_deref(),
+            Some("../infra/.nxbd.toml")
+        );
+        assert_eq!(
+            path("git+file:///srv/infra?ref=main").as_deref(),
+            Some("/srv/infra/.nxbd.toml")
+        );
+        assert_eq!(path("github:user/infra"), None);
+        assert_eq!(path("git+https://example.com/infra.git"), None);
+    }
+}
@@ -0,0 +1,66 @@
+use super::humanize::format_timestamp;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::SystemTime;
+
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Appends everything nxbd reports from now on to the file at `path`, as a
+/// record of what a run changed. Only the first call opens a file.
+pub fn open(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let _ = LOG_FILE.set(Mutex::new(file));
+    Ok(())
+}
+
+/// Writes every line of `text` to the log file, if one is open, prefixed with
+/// the current time. Colors are stripped, as the file isn't read on a
+/// terminal.
+pub fn log(text: &str) {
+    let Some(file) = LOG_FILE.get() else {
+        return;
+    };
+    let timestamp = format_timestamp(SystemTime::now());
+    let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+    for line in strip_colors(text).split('\n') {
+        // Losing log output is not worth aborting a deployment for
+        let _ = writeln!(file, "{timestamp} {line}");
+    }
+}
+
+/// Removes ANSI escape sequences like `\x1b[31m`
+fn strip_colors(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                // Parameters and intermediates end at the final byte
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_colors() {
+        assert_eq!(
+            strip_colors("  \x1b[31m✗\x1b[39m web1: \x1b[1;32mok\x1b[0m"),
+            "  ✗ web1: ok"
+        );
+        assert_eq!(strip_colors("plain text"), "plain text");
+    }
+}
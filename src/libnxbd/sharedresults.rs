@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+/// Runs an operation only once per key when systems are processed in
+/// parallel, e.g. building the same derivation for several systems on one
+/// build host. Callers with a key that is already running wait for it and
+/// share its result.
+#[derive(Debug)]
+pub struct SharedResults<T> {
+    cells: Mutex<HashMap<String, Arc<OnceLock<T>>>>,
+}
+
+impl<T> Default for SharedResults<T> {
+    fn default() -> Self {
+        Self {
+            cells: Mutex::default(),
+        }
+    }
+}
+
+impl<T: Clone> SharedResults<T> {
+    /// Returns the result of `f` for `key`, running it unless another caller
+    /// already did. The flag tells whether this call ran `f`.
+    pub fn get_or_run(&self, key: &str, f: impl FnOnce() -> T) -> (T, bool) {
+        let cell = self
+            .cells
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        let mut ran = false;
+        let result = cell.get_or_init(|| {
+            ran = true;
+            f()
+        });
+        (result.clone(), ran)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_same_key_runs_once() {
+        let results = SharedResults::default();
+        let runs = AtomicUsize::new(0);
+        let keys = [
+            "web1 /nix/store/a.drv",
+            "web1 /nix/store/a.drv",
+            "web1 /nix/store/b.drv",
+        ];
+
+        let ran: Vec<bool> = thread::scope(|scope| {
+            let handles: Vec<_> = keys
+                .iter()
+                .map(|key| {
+                    scope.spawn(|| {
+                        let (result, ran) = results.get_or_run(key, || {
+                            thread::sleep(Duration::from_millis(50));
+                            runs.fetch_add(1, Ordering::SeqCst)
+                        });
+                        assert!(result < 2);
+                        ran
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(ran.iter().filter(|ran| **ran).count(), 2);
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +36,30 @@ impl SshKeyInfo {
     }
 }
 
+/// Groups the hosts that have the same key, which happens with VM images
+/// that were cloned without regenerating their SSH host keys. Keys of a
+/// single host are left out.
+pub fn shared_host_keys<'a>(
+    host_keys: &'a [(&'a str, Vec<SshKeyInfo>)],
+) -> Vec<(&'a SshKeyInfo, Vec<&'a str>)> {
+    let mut hosts_by_key: BTreeMap<(&str, &str), (&SshKeyInfo, Vec<&str>)> = BTreeMap::new();
+    for (host, keys) in host_keys {
+        for key in keys {
+            let (_, hosts) = hosts_by_key
+                .entry((&key.key_type, &key.key_data))
+                .or_insert((key, Vec::new()));
+            // Several systems may be deployed to the same host
+            if !hosts.contains(host) {
+                hosts.push(host);
+            }
+        }
+    }
+    hosts_by_key
+        .into_values()
+        .filter(|(_, hosts)| hosts.len() > 1)
+        .collect()
+}
+
 impl fmt::Display for SshKeyInfo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.comment.is_empty() {
@@ -44,3 +69,36 @@ impl fmt::Display for SshKeyInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_host_keys() {
+        let key = |line| SshKeyInfo::from_authorized_key(line).unwrap();
+        let host_keys = [
+            (
+                "web1",
+                vec![
+                    key("ssh-ed25519 AAAAC3template root@template"),
+                    key("ssh-rsa AAAAB3web1 root@web1"),
+                ],
+            ),
+            (
+                "web2",
+                vec![key("ssh-ed25519 AAAAC3template root@template")],
+            ),
+            (
+                "web2",
+                vec![key("ssh-ed25519 AAAAC3template root@template")],
+            ),
+            ("db1", vec![key("ssh-ed25519 AAAAC3db1 root@db1")]),
+        ];
+
+        let shared = shared_host_keys(&host_keys);
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].0.comment, "root@template");
+        assert_eq!(shared[0].1, ["web1", "web2"]);
+    }
+}
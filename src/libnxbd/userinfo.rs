@@ -1,19 +1,51 @@
-use super::nixcommands::{get_remote_builders, get_system, NixError, RemoteBuilder};
+use super::nixcommands::{
+    get_remote_builders, get_system, remote_builders_from_spec, NixError, PrivilegeEscalation,
+    RemoteBuilder,
+};
 use super::sshkeys::SshKeyInfo;
 use std::env;
+use std::fs;
 use std::process::Command;
 
+/// Pairs of (build system, platform) where the build system runs binaries of
+/// the platform natively, so listing it in `extra-platforms` doesn't imply
+/// emulation.
+const NATIVE_EXTRA_PLATFORMS: &[(&str, &str)] = &[
+    ("x86_64-linux", "i686-linux"),
+    ("aarch64-darwin", "x86_64-darwin"),
+];
+
 #[derive(Debug)]
 pub struct UserInfo {
     pub username: String,
+    /// User that deployments log in as on the target hosts, if not `username`
+    pub remote_user: Option<String>,
+    /// How deployments run commands as root on the target hosts
+    pub privilege_escalation: PrivilegeEscalation,
     pub ssh_keys: Vec<SshKeyInfo>,
     pub system: String,
     pub extra_platforms: Vec<String>,
     pub remote_builders: Vec<RemoteBuilder>,
+    pub binfmt_platforms: Vec<String>,
 }
 
 impl UserInfo {
     pub fn collect() -> Result<Self, NixError> {
+        let (system, extra_platforms) = get_system()?;
+        let remote_builders = get_remote_builders()?;
+
+        Ok(UserInfo {
+            system,
+            extra_platforms,
+            remote_builders,
+            binfmt_platforms: binfmt_platforms(),
+            ..Self::collect_without_nix()?
+        })
+    }
+
+    /// Collects the user information that is available without querying nix.
+    /// Build capabilities are left empty.
+    pub fn collect_without_nix() -> Result<Self, NixError> {
         let username = env::var("USER")
             .map_err(|_| NixError::Eval("Failed to get username from environment".to_string()))?;
 
@@ -30,18 +62,30 @@ impl UserInfo {
             })
             .unwrap_or_else(Vec::new);
 
-        let (system, extra_platforms) = get_system()?;
-        let remote_builders = get_remote_builders()?;
-
         Ok(UserInfo {
             username,
+            remote_user: None,
+            privilege_escalation: PrivilegeEscalation::default(),
             ssh_keys,
-            system,
-            extra_platforms,
-            remote_builders,
+            system: String::new(),
+            extra_platforms: Vec::new(),
+            remote_builders: Vec::new(),
+            binfmt_platforms: Vec::new(),
         })
     }
 
+    /// Replaces the configured remote builders with the ones of a `builders`
+    /// setting value given for this run
+    pub fn override_builders(&mut self, builders: &str) -> Result<(), NixError> {
+        self.remote_builders = remote_builders_from_spec(builders)?;
+        Ok(())
+    }
+
+    /// The user that logs in on deployment targets
+    pub fn remote_username(&self) -> &str {
+        self.remote_user.as_deref().unwrap_or(&self.username)
+    }
+
     pub fn can_build_natively(&self, target_system: &str) -> bool {
         // Can build natively if:
         // 1. Target system matches user's system, or
@@ -54,4 +98,49 @@ impl UserInfo {
                 .iter()
                 .any(|rb| rb.system == target_system)
     }
+
+    pub fn can_build_locally(&self, target_system: &str) -> bool {
+        target_system == self.system || self.extra_platforms.iter().any(|p| p == target_system)
+    }
+
+    /// Local builds for the target system run under qemu emulation, which is
+    /// typically much slower than a native build.
+    pub fn builds_via_emulation(&self, target_system: &str) -> bool {
+        target_system != self.system
+            && self.can_build_locally(target_system)
+            && !NATIVE_EXTRA_PLATFORMS.contains(&(self.system.as_str(), target_system))
+    }
+
+    /// Explains why the target system can't be built locally, if it can't.
+    pub fn local_build_problem(&self, target_system: &str) -> Option<String> {
+        if self.can_build_locally(target_system) {
+            None
+        } else if self.binfmt_platforms.iter().any(|p| p == target_system) {
+            Some(format!(
+                "binfmt emulation for {target_system} is registered, but nix doesn't list it in `extra-platforms`"
+            ))
+        } else {
+            Some(format!(
+                "emulation for {target_system} is not set up (see `boot.binfmt.emulatedSystems`)"
+            ))
+        }
+    }
+}
+
+/// Reads the platforms registered for binfmt emulation. NixOS names the
+/// entries after the nix system (e.g. `aarch64-linux`), other distributions
+/// typically use `qemu-<arch>`.
+fn binfmt_platforms() -> Vec<String> {
+    let Ok(entries) = fs::read_dir("/proc/sys/fs/binfmt_misc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter_map(|name| match name.strip_prefix("qemu-") {
+            Some(arch) => Some(format!("{arch}-linux")),
+            None if name.ends_with("-linux") => Some(name),
+            None => None,
+        })
+        .collect()
 }
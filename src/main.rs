@@ -1,28 +1,76 @@
 mod cli;
 mod libnxbd;
 
-use crate::cli::{Cli, Command};
+use crate::cli::{Cli, Command, OutputFormat, PlanFormat, StatusLayout};
 use clap::{CommandFactory, Parser};
 use libnxbd::{
     configcheck::{
-        get_standard_checks, load_ignored_checks, merge_ignore_maps, run_all_checks,
-        save_failed_checks_to_ignore_file, CheckGroupResult,
+        diff_against_baseline, get_standard_checks, ignore_map_for_system, load_ignore_files,
+        merge_ignore_maps, parse_check_baseline, run_all_checks, save_failed_checks_to_ignore_file,
+        CheckBaseline, CheckGroupResult, CheckResult, Severity,
     },
+    deployplan::{plan, BuildStrategy, PlanOptions},
+    evalcache::EvalCache,
+    flakeref::{closest_match, glob_matches},
+    hostlog::{HostLog, HostLogger},
+    hostmutex::HostMutexes,
+    humanize::{format_bytes, format_duration, format_elapsed},
     nixcommands::{
-        activate_profile, check_system_status, copy_to_host, nixos_configuration_flakerefs,
-        realise_drv_remotely, realise_toplevel_output_paths, reboot_host, switch_to_configuration,
-        SystemStatus,
+        activate_profile, add_gc_root, check_system_status, closure_size, collect_garbage,
+        copy_between_hosts, copy_from_host, copy_to_host, diff_closures, list_generations,
+        lock_host, machine_architecture, nixos_configuration_attributes,
+        nixos_configuration_flakerefs, platform_runs_on, realise_drv_remotely,
+        realise_toplevel_output_paths, reboot_host, retry_on_connection_error, rollback_system,
+        set_eval_options, ssh_host_keys, switch_to_configuration, system_generation,
+        toplevel_drv_path, wait_until_rebooted, ActivationMode, BootSpace, BuildOptions,
+        ConnectionOptions, EvalOptions, GcResult, RemoteHost, SystemStatus, TargetHosts,
+        STAGED_GC_ROOT,
     },
-    nixosattributes::{nixos_deploy_info, ConfigInfo},
+    nixosattributes::{config_infos_from_json, hostname_matches, nixos_deploy_info, ConfigInfo},
+    projectconfig::{project_config_path, ProjectConfig, PROJECT_CONFIG_FILE},
+    runlog,
+    sharedresults::SharedResults,
+    sshkeys::{shared_host_keys, SshKeyInfo},
     userinfo::UserInfo,
     FlakeReference, NixError,
 };
 use nix::unistd;
 use owo_colors::OwoColorize;
 use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fs::{self, create_dir_all};
-use std::io;
+use std::io::{self, IsTerminal, Write};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+/// Like `println!`, but also writes the line to the `--log-file`
+macro_rules! outln {
+    () => {{
+        println!();
+        runlog::log("");
+    }};
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{line}");
+        runlog::log(&line);
+    }};
+}
+
+/// Like `eprintln!`, but also writes the line to the `--log-file`
+macro_rules! errln {
+    () => {{
+        eprintln!();
+        runlog::log("");
+    }};
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        eprintln!("{line}");
+        runlog::log(&line);
+    }};
+}
 
 #[derive(Debug)]
 enum NxbdError {
@@ -37,6 +85,46 @@ enum NxbdError {
         config_hostname: String,
         local_hostname: String,
     },
+    InvalidConfigJson {
+        path: String,
+        message: String,
+    },
+    InvalidBaseline {
+        path: String,
+        message: String,
+    },
+    InvalidProjectConfig {
+        message: String,
+    },
+    InvalidLogFile {
+        path: String,
+        message: String,
+    },
+    StaleUptime {
+        hosts: Vec<String>,
+    },
+    NotInteractive {
+        option: String,
+    },
+    NoSystemsMatch {
+        filter: String,
+    },
+    BuildsFailed {
+        failures: Vec<(FlakeReference, NixError)>,
+    },
+    InvalidTargetHost {
+        message: String,
+    },
+    UnknownCheck {
+        check: String,
+        /// The most similar existing check
+        suggestion: Option<String>,
+    },
+    UnknownSystems {
+        /// The unknown systems, with the most similar existing attribute
+        systems: Vec<(FlakeReference, Option<String>)>,
+        available: Vec<FlakeReference>,
+    },
     Nix(NixError),
     Io(io::Error),
 }
@@ -76,6 +164,65 @@ impl fmt::Display for NxbdError {
                 write!(f, "Hostname mismatch: system config has '{}' but local system is '{}'\nTo proceed, either:\n - Fix the hostname\n - Rerun with --ignore-hostname",
                     config_hostname, local_hostname)
             }
+            Self::InvalidConfigJson { path, message } => {
+                write!(f, "Failed to read configurations from {path}: {message}")
+            }
+            Self::InvalidBaseline { path, message } => {
+                write!(
+                    f,
+                    "Failed to read the check baseline from {path}: {message}"
+                )
+            }
+            Self::InvalidProjectConfig { message } => {
+                write!(f, "Failed to read {PROJECT_CONFIG_FILE}: {message}")
+            }
+            Self::InvalidLogFile { path, message } => {
+                write!(f, "Failed to open the log file {path}: {message}")
+            }
+            Self::StaleUptime { hosts } => {
+                write!(
+                    f,
+                    "The following hosts exceed the maximum uptime: {}",
+                    hosts.join(", ")
+                )
+            }
+            Self::NotInteractive { option } => {
+                write!(f, "{option} needs an interactive terminal")
+            }
+            Self::NoSystemsMatch { filter } => {
+                write!(f, "No systems match the filter `{filter}`")
+            }
+            Self::BuildsFailed { failures } => {
+                writeln!(f, "The following systems failed to build:")?;
+                for (system, error) in failures {
+                    writeln!(f, "  - {system}: {error}")?;
+                }
+                Ok(())
+            }
+            Self::InvalidTargetHost { message } => write!(f, "Invalid --target-host: {message}"),
+            Self::UnknownCheck { check, suggestion } => {
+                write!(f, "Unknown check `{check}`")?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, " (did you mean `{suggestion}`?)")?;
+                }
+                write!(f, "\nRun `nxbd checks` to list all checks")
+            }
+            Self::UnknownSystems { systems, available } => {
+                writeln!(f, "The following systems don't exist:")?;
+                for (system, suggestion) in systems {
+                    match suggestion {
+                        Some(attribute) => {
+                            writeln!(f, "  - {system} (did you mean `{attribute}`?)")?;
+                        }
+                        None => writeln!(f, "  - {system}")?,
+                    }
+                }
+                write!(f, "\nAvailable systems:")?;
+                for system in available {
+                    write!(f, "\n  - {system}")?;
+                }
+                Ok(())
+            }
             Self::Nix(e) => write!(f, "{}", e),
             Self::Io(e) => write!(f, "IO error: {}", e),
         }
@@ -96,6 +243,508 @@ impl From<io::Error> for NxbdError {
     }
 }
 
+/// Wait before the first retry of a failed connection, doubled for every
+/// further retry
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// `status --layout auto` shows a table for at least this many hosts
+const STATUS_TABLE_MIN_HOSTS: usize = 6;
+
+/// Runs a copy or build on `host`, retrying it after connection errors as
+/// often as the host's connection options allow
+fn with_retries<T>(
+    host: &RemoteHost,
+    log: &HostLog,
+    operation: impl FnMut() -> Result<T, NixError>,
+) -> Result<T, NixError> {
+    let retries = host.options.retries;
+    retry_on_connection_error(
+        retries,
+        RETRY_BASE_DELAY,
+        |attempt, delay, error| {
+            log.log(format!(
+                "→ {error}, retrying in {}s (retry {attempt}/{retries})",
+                delay.as_secs()
+            ));
+        },
+        operation,
+    )
+}
+
+/// How long the phases of a host's deployment took, for the summary
+#[derive(Debug, Default)]
+struct PhaseTimings {
+    build: Option<Duration>,
+    copy: Option<Duration>,
+    switch: Option<Duration>,
+}
+
+impl fmt::Display for PhaseTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phases: Vec<String> = [
+            ("build", self.build),
+            ("copy", self.copy),
+            ("switch", self.switch),
+        ]
+        .into_iter()
+        .filter_map(|(phase, elapsed)| {
+            elapsed.map(|elapsed| format!("{phase} {}", format_elapsed(elapsed)))
+        })
+        .collect();
+        if phases.is_empty() {
+            Ok(())
+        } else {
+            write!(f, " ({})", phases.join(", "))
+        }
+    }
+}
+
+/// Runs `phase` and records how long it took in `timing`
+fn timed<T>(timing: &mut Option<Duration>, phase: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = phase();
+    *timing = Some(started.elapsed());
+    result
+}
+
+/// Fails if the host's CPU can't run the system, which would otherwise
+/// only show up as confusing errors during the activation
+fn verify_platform(deploy_info: &ConfigInfo, host: &RemoteHost) -> Result<(), NixError> {
+    let machine = machine_architecture(Some(host))?;
+    if !platform_runs_on(&deploy_info.system, &machine) {
+        return Err(NixError::PlatformMismatch {
+            host: host.host.clone(),
+            platform: deploy_info.system.clone(),
+            machine,
+        });
+    }
+    Ok(())
+}
+
+/// Copies a locally built system to its host, without activating it
+fn stage_local_build(
+    deploy_info: &ConfigInfo,
+    host: &RemoteHost,
+    log: &HostLog,
+    timings: &mut PhaseTimings,
+) -> Result<(), NixError> {
+    log.phase("copying");
+    timed(&mut timings.copy, || {
+        with_retries(host, log, || {
+            copy_to_host(&deploy_info.toplevel_out, host, log)
+        })
+    })?;
+    log.log("→ Copied system closure");
+    add_gc_root(&deploy_info.toplevel_out, STAGED_GC_ROOT, true, Some(host))?;
+    Ok(())
+}
+
+/// Copies the system derivation to its host and builds it there, without
+/// activating it
+fn stage_remote_build(
+    deploy_info: &ConfigInfo,
+    host: &RemoteHost,
+    builds: &SharedBuilds,
+    log: &HostLog,
+    timings: &mut PhaseTimings,
+) -> Result<(), NixError> {
+    timed(&mut timings.build, || {
+        build_on_host_once(deploy_info, host, builds, log)
+    })?;
+    add_gc_root(&deploy_info.toplevel_out, STAGED_GC_ROOT, true, Some(host))?;
+    Ok(())
+}
+
+/// Copies the system derivation to `build_host` and builds it there
+fn build_on_host(
+    deploy_info: &ConfigInfo,
+    build_host: &RemoteHost,
+    log: &HostLog,
+) -> Result<(), NixError> {
+    log.phase(format!("copying derivation to {build_host}"));
+    with_retries(build_host, log, || {
+        copy_to_host(&deploy_info.toplevel_drv, build_host, log)
+    })?;
+    log.log(format!("→ Copied system derivation to {build_host}"));
+    log.phase(format!("building on {build_host}"));
+    with_retries(build_host, log, || {
+        realise_drv_remotely(&deploy_info.toplevel_drv, build_host)
+    })?;
+    log.log(format!("→ Built system on {build_host}"));
+    Ok(())
+}
+
+/// Results of remote builds by build host and derivation
+type SharedBuilds = SharedResults<Result<(), NixError>>;
+
+/// Like `build_on_host`, but systems with the same derivation are only built
+/// once per build host, even if they are deployed in parallel
+fn build_on_host_once(
+    deploy_info: &ConfigInfo,
+    build_host: &RemoteHost,
+    builds: &SharedBuilds,
+    log: &HostLog,
+) -> Result<(), NixError> {
+    let key = format!("{build_host} {}", deploy_info.toplevel_drv);
+    let (result, built) = builds.get_or_run(&key, || build_on_host(deploy_info, build_host, log));
+    if !built && result.is_ok() {
+        log.log(format!(
+            "→ Reused the build of the same system on {build_host}"
+        ));
+    }
+    result
+}
+
+/// Builds the system on the build host and copies it from there to its host,
+/// without activating it
+fn stage_build_host_build(
+    deploy_info: &ConfigInfo,
+    build_host: &RemoteHost,
+    host: &RemoteHost,
+    builds: &SharedBuilds,
+    log: &HostLog,
+    timings: &mut PhaseTimings,
+) -> Result<(), NixError> {
+    timed(&mut timings.build, || {
+        build_on_host_once(deploy_info, build_host, builds, log)
+    })?;
+    log.phase(format!("copying system from {build_host}"));
+    timed(&mut timings.copy, || {
+        with_retries(host, log, || {
+            copy_between_hosts(&deploy_info.toplevel_out, build_host, host, log)
+        })
+    })?;
+    log.log(format!("→ Copied system closure from {build_host}"));
+    add_gc_root(&deploy_info.toplevel_out, STAGED_GC_ROOT, true, Some(host))?;
+    Ok(())
+}
+
+/// Builds the system on the build host and copies it to this machine
+fn build_on_build_host(
+    deploy_info: &ConfigInfo,
+    build_host: &RemoteHost,
+    log: &HostLog,
+) -> Result<(), NixError> {
+    build_on_host(deploy_info, build_host, log)?;
+    log.phase(format!("copying system from {build_host}"));
+    copy_from_host(&deploy_info.toplevel_out, build_host, log)?;
+    log.log(format!("→ Copied system closure from {build_host}"));
+    Ok(())
+}
+
+fn activate_and_switch(
+    deploy_info: &ConfigInfo,
+    host: &RemoteHost,
+    mode: ActivationMode,
+    log: &HostLog,
+) -> Result<(), NixError> {
+    if mode.sets_boot_default() {
+        log.phase("activating");
+        activate_profile(&deploy_info.toplevel_out, true, Some(host))?;
+        log.log("→ Activated system profile");
+    }
+    log.phase("switching");
+    switch_to_configuration(&deploy_info.toplevel_out, mode.as_str(), true, Some(host))?;
+    log.log(format!("→ Switched to new configuration ({mode})"));
+    Ok(())
+}
+
+/// Fails if the host's system profile isn't the expected generation, it has
+/// failed systemd units or it can't be reached
+fn check_health(host: &RemoteHost, expected_generation: Option<&str>) -> Result<(), NixError> {
+    match check_system_status(Some(host), None)? {
+        SystemStatus::Reachable {
+            current_generation, ..
+        } if expected_generation.is_some_and(|expected| expected != current_generation) => {
+            Err(NixError::Unhealthy {
+                host: host.to_string(),
+                problem: format!("runs {current_generation} instead of the deployed system"),
+            })
+        }
+        SystemStatus::Reachable {
+            failed_units,
+            failed_unit_names,
+            ..
+        } if failed_units > 0 => Err(NixError::Unhealthy {
+            host: host.to_string(),
+            problem: format!("failed units: {}", failed_unit_names.join(", ")),
+        }),
+        SystemStatus::Reachable { .. } => Ok(()),
+        SystemStatus::Unreachable => Err(NixError::Unhealthy {
+            host: host.to_string(),
+            problem: "unreachable".to_string(),
+        }),
+        SystemStatus::StatusUnavailable { stderr_tail } => Err(NixError::Unhealthy {
+            host: host.to_string(),
+            problem: format!("its status couldn't be read: {}", stderr_tail.join(" ")),
+        }),
+    }
+}
+
+enum Confirmation {
+    Continue,
+    Stop,
+    Rollback,
+}
+
+fn confirm_next_host(next_system: &FlakeReference) -> io::Result<Confirmation> {
+    loop {
+        eprint!("Continue with {next_system}? [y/N/rollback] ");
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(Confirmation::Stop);
+        }
+        runlog::log(&format!(
+            "Continue with {next_system}? [y/N/rollback] {}",
+            answer.trim()
+        ));
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(Confirmation::Continue),
+            "" | "n" | "no" => return Ok(Confirmation::Stop),
+            "rollback" => return Ok(Confirmation::Rollback),
+            _ => errln!("Please answer y, n or rollback"),
+        }
+    }
+}
+
+/// Asks whether to deploy to the given hosts, the default is no
+fn confirm_deployment(hosts: &[&str], mode: ActivationMode) -> io::Result<bool> {
+    errln!("Hosts to deploy to ({mode}):");
+    for host in hosts {
+        errln!("  {host}");
+    }
+    eprint!("Continue? [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    runlog::log(&format!("Continue? [y/N] {}", answer.trim()));
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// ` [closure: 1.2 GiB]`, or nothing if the size can't be determined
+fn closure_size_note(path: &str, remote_host: Option<&RemoteHost>) -> String {
+    closure_size(path, remote_host)
+        .map(|size| format!(" [closure: {}]", format_bytes(size)))
+        .unwrap_or_default()
+}
+
+fn print_system_status(info: &ConfigInfo, status: &Result<SystemStatus, NixError>) {
+    match status {
+        Ok(SystemStatus::Unreachable) => {
+            outln!("  {} System not reachable", "✗".red());
+        }
+        Ok(SystemStatus::StatusUnavailable { stderr_tail }) => {
+            print_status_unavailable(stderr_tail);
+        }
+        &Ok(SystemStatus::Reachable {
+            ref current_generation,
+            needs_reboot,
+            uptime_seconds,
+            failed_units,
+            ref failed_unit_names,
+            update_available,
+            boot_space,
+            ref deployed_revision,
+        }) => {
+            outln!(
+                "  {} systemd units: {}",
+                passed_symbol(failed_units == 0),
+                if failed_units == 0 {
+                    "all OK".to_string()
+                } else {
+                    format!("{} failed", failed_units).to_string()
+                }
+            );
+            for unit in failed_unit_names {
+                outln!("    - {}", unit.red());
+            }
+
+            let generation_status = *current_generation == info.toplevel_out;
+            outln!(
+                "  {} System generation {}",
+                passed_symbol(generation_status),
+                if generation_status {
+                    "up to date"
+                } else {
+                    "outdated"
+                }
+            );
+            if let Some(deployed_revision) = deployed_revision {
+                print_deployed_revision(info, deployed_revision);
+            }
+
+            outln!(
+                "  {} Reboot required: {}",
+                if needs_reboot {
+                    "!".yellow().to_string()
+                } else {
+                    "✓".green().to_string()
+                },
+                if needs_reboot { "yes" } else { "no" }
+            );
+            if update_available {
+                outln!("  {} Newer kernel available, deploy pending", "!".yellow());
+            }
+            if let Some(boot_space) = boot_space {
+                print_boot_space(info, &boot_space);
+            }
+
+            outln!("    Uptime: {}", format_duration(uptime_seconds));
+            if let Some(state_version) = &info.state_version {
+                outln!("    stateVersion: {state_version}");
+            }
+        }
+        Err(e) => outln!("  {} Error getting system status: {}", "✗".red(), e),
+    }
+}
+
+fn print_status_unavailable(stderr_tail: &[String]) {
+    outln!(
+        "  {} System reachable, but couldn't read its status",
+        "✗".red()
+    );
+    for line in stderr_tail {
+        outln!("    {line}");
+    }
+}
+
+/// Prints rows as left-aligned columns under the given headers
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    outln!("{}", format_row(headers.to_vec()).bold());
+    for row in rows {
+        outln!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+}
+
+/// One line of the `status` table
+fn status_table_row(info: &ConfigInfo, status: &Result<SystemStatus, NixError>) -> Vec<String> {
+    let host = info.fqdn_or_host_name.clone();
+    match status {
+        Ok(SystemStatus::Reachable {
+            current_generation,
+            needs_reboot,
+            uptime_seconds,
+            failed_units,
+            ..
+        }) => vec![
+            host,
+            "yes".to_string(),
+            if *failed_units == 0 {
+                "ok".to_string()
+            } else {
+                format!("{failed_units} failed")
+            },
+            if *current_generation == info.toplevel_out {
+                "up to date".to_string()
+            } else {
+                "outdated".to_string()
+            },
+            if *needs_reboot { "yes" } else { "no" }.to_string(),
+            format_duration(*uptime_seconds),
+        ],
+        Ok(SystemStatus::Unreachable) => vec![host, "no".to_string()],
+        Ok(SystemStatus::StatusUnavailable { .. }) => {
+            vec![host, "yes".to_string(), "status unreadable".to_string()]
+        }
+        Err(_) => vec![host, "error".to_string()],
+    }
+}
+
+/// Reports SSH host keys that several of the hosts share
+fn print_shared_host_keys(hosts: &[&str], connection: &ConnectionOptions) {
+    let results: Vec<(&str, Result<Vec<SshKeyInfo>, NixError>)> = hosts
+        .par_iter()
+        .map(|host| (*host, ssh_host_keys(Some(&connection.remote_host(host)))))
+        .collect();
+
+    outln!("\nSSH Host Keys:");
+    let mut host_keys = Vec::new();
+    for (host, result) in results {
+        match result {
+            Ok(keys) => host_keys.push((host, keys)),
+            Err(e) => outln!("  {} {host}: {e}", "✗".red()),
+        }
+    }
+    let shared = shared_host_keys(&host_keys);
+    if shared.is_empty() {
+        outln!("  {} No host keys are shared between hosts", "✓".green());
+    }
+    for (key, hosts) in shared {
+        let origin = if key.comment.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", key.comment)
+        };
+        outln!(
+            "  {} {} key{origin} is shared by {}",
+            "!".yellow(),
+            key.key_type,
+            hosts.join(", ")
+        );
+    }
+}
+
+/// Shows which revision the host was deployed from, and warns if the flake
+/// has moved on since
+fn print_deployed_revision(info: &ConfigInfo, deployed_revision: &str) {
+    match &info.configuration_revision {
+        Some(revision) if revision != deployed_revision => outln!(
+            "  {} Deployed revision: {deployed_revision}, the flake is at {revision}",
+            "!".yellow()
+        ),
+        Some(_) => outln!("  {} Deployed revision: {deployed_revision}", "✓".green()),
+        None => outln!("    Deployed revision: {deployed_revision}"),
+    }
+}
+
+/// Warns if the bootloader's generations may not fit on the boot partition
+fn print_boot_space(info: &ConfigInfo, boot_space: &BootSpace) {
+    let limit = if info.boot_systemd {
+        info.boot_systemd_generations
+    } else if info.boot_grub {
+        info.boot_grub_generations
+    } else {
+        return;
+    };
+    let limit = limit.and_then(|limit| u32::try_from(limit).ok());
+    if !boot_space.too_small_for(limit) {
+        return;
+    }
+    let problem = match limit {
+        Some(limit) => format!(
+            "{limit} generations of about {} each may not fit into {}",
+            format_bytes(boot_space.generation_size),
+            format_bytes(boot_space.size)
+        ),
+        None => format!(
+            "only {} free, a new generation needs about {}",
+            format_bytes(boot_space.available),
+            format_bytes(boot_space.generation_size)
+        ),
+    };
+    outln!("  {} /boot may run out of space: {problem}", "!".yellow());
+}
+
 fn passed_symbol(passed: bool) -> String {
     if passed {
         "✅".green().to_string()
@@ -104,33 +753,242 @@ fn passed_symbol(passed: bool) -> String {
     }
 }
 
-fn passed_ignore_symbol(passed: bool, ignored: bool) -> String {
-    if !passed && ignored {
+/// Shows the checks that started or stopped failing since the baseline
+fn print_baseline_diff(
+    baseline: &CheckBaseline,
+    results: &[(&FlakeReference, Vec<CheckGroupResult>)],
+    fail_on_warn: bool,
+) {
+    errln!("Changes since the baseline:");
+    let mut unchanged = true;
+    for (system, check_group_results) in results {
+        let baseline_failures = baseline
+            .get(&system.attribute)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let diff = diff_against_baseline(baseline_failures, check_group_results, fail_on_warn);
+        if diff.is_empty() {
+            continue;
+        }
+        unchanged = false;
+        errln!("  {}", system.to_string().cyan().bold());
+        for (group, check) in &diff.newly_failing {
+            errln!("    {} {group}.{check} now fails", "+".red());
+        }
+        for (group, check) in &diff.newly_passing {
+            errln!("    {} {group}.{check} now passes", "-".green());
+        }
+    }
+    if unchanged {
+        errln!("  {} No checks changed", passed_symbol(true));
+    }
+    errln!();
+}
+
+fn check_result_symbol(check: &CheckResult) -> String {
+    if check.passed {
+        passed_symbol(true)
+    } else if check.ignored {
         "🙈".to_string()
+    } else if check.severity == Severity::Info {
+        "ℹ️".blue().to_string()
+    } else if check.severity == Severity::Warning {
+        "⚠️".yellow().to_string()
+    } else {
+        passed_symbol(false)
+    }
+}
+
+fn flakerefs_or_default(
+    refs: &[FlakeReference],
+    filter: Option<&str>,
+    exclude: &[String],
+) -> Result<Vec<FlakeReference>, NxbdError> {
+    let mut refs = if refs.is_empty() {
+        nixos_configuration_flakerefs(".")?
+    } else {
+        validate_flakerefs(refs)?;
+        refs.to_owned()
+    };
+    if let Some(filter) = filter {
+        refs.retain(|flakeref| glob_matches(filter, &flakeref.attribute));
+        if refs.is_empty() {
+            return Err(NxbdError::NoSystemsMatch {
+                filter: filter.to_string(),
+            });
+        }
+    }
+    refs.retain(|flakeref| {
+        !exclude
+            .iter()
+            .any(|pattern| glob_matches(pattern, &flakeref.attribute))
+    });
+    Ok(refs)
+}
+
+/// Fails with a list of the available systems if any of the systems doesn't
+/// exist, which is much clearer than the evaluation error nix would give
+fn validate_flakerefs(refs: &[FlakeReference]) -> Result<(), NxbdError> {
+    let mut urls: Vec<&str> = refs.iter().map(|flakeref| flakeref.url.as_str()).collect();
+    urls.sort_unstable();
+    urls.dedup();
+
+    let mut systems = Vec::new();
+    let mut available = Vec::new();
+    for url in urls {
+        let attributes = nixos_configuration_attributes(url)?;
+        let unknown: Vec<_> = refs
+            .iter()
+            .filter(|flakeref| flakeref.url == url && !attributes.contains(&flakeref.attribute))
+            .map(|flakeref| {
+                let suggestion = closest_match(&flakeref.attribute, &attributes);
+                (flakeref.clone(), suggestion.map(String::from))
+            })
+            .collect();
+        if !unknown.is_empty() {
+            systems.extend(unknown);
+            available.extend(attributes.into_iter().map(|attribute| FlakeReference {
+                url: url.to_string(),
+                attribute,
+            }));
+        }
+    }
+
+    if systems.is_empty() {
+        Ok(())
     } else {
-        passed_symbol(passed)
+        Err(NxbdError::UnknownSystems { systems, available })
+    }
+}
+
+/// Makes sure that every `--target-host` applies to a selected system
+fn check_target_hosts(
+    target_hosts: &TargetHosts,
+    systems: &[FlakeReference],
+) -> Result<(), NxbdError> {
+    if let Some(address) = &target_hosts.default {
+        if systems.len() > 1 {
+            return Err(NxbdError::InvalidTargetHost {
+                message: format!(
+                    "{address} can't be used for {} systems, use ATTR={address} to select one",
+                    systems.len()
+                ),
+            });
+        }
+    }
+    for attribute in target_hosts.by_attribute.keys() {
+        if !systems.iter().any(|system| &system.attribute == attribute) {
+            return Err(NxbdError::InvalidTargetHost {
+                message: format!("{attribute} is not among the selected systems"),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates a system and replaces its address with its `--target-host`.
+/// Only the address changes, checks still see the configured host name.
+fn deploy_info_at_target(
+    system: &FlakeReference,
+    cache: Option<&EvalCache>,
+    target_hosts: &TargetHosts,
+) -> Result<ConfigInfo, NixError> {
+    let mut info = nixos_deploy_info(system, cache)?;
+    if let Some(address) = target_hosts.for_attribute(&system.attribute) {
+        info.fqdn_or_host_name = address.to_string();
     }
+    Ok(info)
 }
 
-fn flakerefs_or_default(refs: &[FlakeReference]) -> Result<Vec<FlakeReference>, libnxbd::NixError> {
-    if refs.is_empty() {
-        nixos_configuration_flakerefs(".")
+/// Evaluation results of the selected systems
+type Configurations = Vec<(FlakeReference, Result<ConfigInfo, NixError>)>;
+
+/// Selects the systems and evaluates them in parallel. Systems that fail to
+/// evaluate are returned with their error.
+fn read_configurations(
+    systems: &[FlakeReference],
+    filter: Option<&str>,
+    exclude: &[String],
+    target_hosts: &TargetHosts,
+    cache: Option<&EvalCache>,
+) -> Result<Configurations, NxbdError> {
+    let system_attributes = flakerefs_or_default(systems, filter, exclude)?;
+    check_target_hosts(target_hosts, &system_attributes)?;
+
+    errln!(
+        "Reading configurations of {}...",
+        system_attributes
+            .iter()
+            .map(|s| format!(".#{}", s.attribute))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    Ok(system_attributes
+        .par_iter()
+        .map(|system| {
+            (
+                system.clone(),
+                deploy_info_at_target(system, cache, target_hosts),
+            )
+        })
+        .collect())
+}
+
+/// Like [`read_configurations`], but fails if any of the systems doesn't
+/// evaluate
+fn evaluate_systems(
+    systems: &[FlakeReference],
+    filter: Option<&str>,
+    exclude: &[String],
+    target_hosts: &TargetHosts,
+    cache: Option<&EvalCache>,
+) -> Result<Vec<(FlakeReference, ConfigInfo)>, NxbdError> {
+    let deploy_infos = read_configurations(systems, filter, exclude, target_hosts, cache)?;
+
+    let mut evaluated = Vec::new();
+    let mut failures = Vec::new();
+    for (system, result) in deploy_infos {
+        match result {
+            Ok(info) => evaluated.push((system, info)),
+            Err(err) => failures.push((system, err)),
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(evaluated)
     } else {
-        Ok(refs.to_owned())
+        Err(NxbdError::EvaluationFails { failures })
     }
 }
 
+fn keep_result_symlink(
+    dir: &str,
+    system: &FlakeReference,
+    toplevel: &str,
+) -> Result<(), NxbdError> {
+    create_dir_all(dir)?;
+    add_gc_root(
+        toplevel,
+        &format!("{dir}/{}", system.attribute),
+        false,
+        None,
+    )?;
+    Ok(())
+}
+
 fn run_system_checks(
     info: &ConfigInfo,
     user_info: &UserInfo,
     system_ignore_map: Option<&libnxbd::configcheck::IgnoreMap>,
+    fail_on_warn: bool,
 ) -> Result<Vec<(String, String)>, NixError> {
     let results = run_all_checks(info, user_info, system_ignore_map);
     let mut failures = Vec::new();
 
     for group in &results {
         for check in &group.checks {
-            if !check.passed && !check.ignored {
+            if check.blocks(fail_on_warn) {
                 failures.push((group.id.clone(), check.id.clone()));
             }
         }
@@ -139,15 +997,63 @@ fn run_system_checks(
     Ok(failures)
 }
 
+/// Prints failed checks as `{"<attribute>": [["<group>", "<check>"], ...]}`
+fn print_failed_checks_json(failures: &[(FlakeReference, Vec<(String, String)>)]) {
+    let failures: BTreeMap<&str, &Vec<(String, String)>> = failures
+        .iter()
+        .map(|(system, checks)| (system.attribute.as_str(), checks))
+        .collect();
+    if let Ok(json) = serde_json::to_string(&failures) {
+        outln!("{json}");
+    }
+}
+
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+    let mut cli = Cli::parse();
+    if let Err(e) = run(&mut cli) {
+        if let (OutputFormat::Json, NxbdError::ChecksFailed { failures, .. }) = (cli.format, &e) {
+            print_failed_checks_json(failures);
+        }
+        errln!("Error: {}", e);
+        process::exit(1);
     }
 }
 
-fn run() -> Result<(), NxbdError> {
-    let cli = Cli::parse();
+fn run(cli: &mut Cli) -> Result<(), NxbdError> {
+    // Options on the command line win over the project's defaults
+    let project_config = match project_config_path(cli.flake_url()) {
+        Some(path) => ProjectConfig::load(&path)
+            .map_err(|message| NxbdError::InvalidProjectConfig { message })?,
+        None => ProjectConfig::default(),
+    };
+    cli.apply_project_config(&project_config);
+    let cli = &*cli;
+
+    if let Some(path) = &cli.log_file {
+        runlog::open(Path::new(path)).map_err(|e| NxbdError::InvalidLogFile {
+            path: path.clone(),
+            message: e.to_string(),
+        })?;
+        runlog::log(&format!(
+            "=== {} ===",
+            std::env::args().collect::<Vec<_>>().join(" ")
+        ));
+    }
+
+    set_eval_options(EvalOptions {
+        show_trace: cli.show_trace,
+        impure: cli.impure,
+        offline: cli.offline,
+        no_registries: cli.no_registries,
+    });
+    // Every parallel evaluation or deployment holds a thread while it waits
+    // for its nix or ssh process, so the pool size limits the concurrency
+    if let Some(max_parallel) = cli.max_parallel {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(max_parallel)
+            .build_global()
+            .expect("The thread pool is only configured once");
+    }
 
     match &cli.command {
         Command::GenerateDocs { output_dir } => {
@@ -180,7 +1086,10 @@ fn run() -> Result<(), NxbdError> {
                 }
 
                 content.push_str("## Global Options\n\n");
-                content.push_str("- `--verbose`: Show detailed information during execution\n\n");
+                content.push_str("- `--verbose`: Show detailed information during execution\n");
+                content.push_str(
+                    "- `--no-cache`: Evaluate configurations without using the evaluation cache\n\n",
+                );
 
                 fs::write(format!("{}/commands/index.md", output_dir), content)?;
             }
@@ -285,6 +1194,9 @@ fn run() -> Result<(), NxbdError> {
                     ));
                     content.push_str("**Description:**\n");
                     content.push_str(&format!("{}\n\n", check.description));
+                    content.push_str("**Severity:**\n");
+                    content.push_str(&check.severity.to_string());
+                    content.push_str("\n\n");
                     content.push_str("**How to fix:**\n");
                     content.push_str(&format!("{}\n\n", check.advice));
                 }
@@ -292,13 +1204,56 @@ fn run() -> Result<(), NxbdError> {
                 fs::write(format!("{}/checks/{}.md", output_dir, group.id), content)?;
             }
 
-            eprintln!("Documentation generated in {}", output_dir);
+            errln!("Documentation generated in {}", output_dir);
+            return Ok(());
+        }
+        Command::Checks {
+            explain: Some(check_id),
+        } => {
+            let groups = get_standard_checks();
+            let check = groups.iter().find_map(|group| {
+                let check = group
+                    .checks
+                    .iter()
+                    .find(|check| format!("{}.{}", group.id, check.id) == *check_id)?;
+                Some((group, check))
+            });
+            let Some((group, check)) = check else {
+                let available: Vec<String> = groups
+                    .iter()
+                    .flat_map(|group| {
+                        group
+                            .checks
+                            .iter()
+                            .map(move |check| format!("{}.{}", group.id, check.id))
+                    })
+                    .collect();
+                return Err(NxbdError::UnknownCheck {
+                    check: check_id.clone(),
+                    suggestion: closest_match(check_id, &available).map(str::to_string),
+                });
+            };
+
+            outln!(
+                "{}.{} ({})\n",
+                group.id.cyan().bold(),
+                check.id.yellow(),
+                check.severity
+            );
+            outln!("{}\n", check.description);
+            outln!("{}\n{}\n", "How to fix:".bold(), check.advice);
+            if !check.inspects.is_empty() {
+                outln!("{}", "Inspected configuration attributes:".bold());
+                for field in check.inspects {
+                    outln!("  - {field}");
+                }
+            }
             return Ok(());
         }
-        Command::Checks => {
-            println!("Available configuration checks:\n");
+        Command::Checks { explain: None } => {
+            outln!("Available configuration checks:\n");
             for group in get_standard_checks() {
-                println!(
+                outln!(
                     "\n{} - {}\n{}\n",
                     group.id.cyan().bold(),
                     group.name.bold(),
@@ -306,9 +1261,10 @@ fn run() -> Result<(), NxbdError> {
                 );
 
                 for check in group.checks {
-                    println!(
-                        "  {} - {}\n    {}\n",
+                    outln!(
+                        "  {} ({}) - {}\n    {}\n",
                         check.id.yellow(),
+                        check.severity,
                         check.description,
                         check.advice.dimmed()
                     );
@@ -319,41 +1275,84 @@ fn run() -> Result<(), NxbdError> {
         _ => {}
     }
 
-    let user_info = UserInfo::collect()?;
+    let mut user_info = if let Command::Check {
+        from_json: Some(_), ..
+    } = &cli.command
+    {
+        UserInfo::collect_without_nix()?
+    } else {
+        UserInfo::collect()?
+    };
+
+    // Impure evaluations can depend on more than the flake's sources
+    let eval_cache = if cli.no_cache || cli.impure {
+        None
+    } else {
+        EvalCache::from_env()
+    };
 
     if cli.verbose {
-        println!("\nLocal Deployment Configuration:");
-        println!("  User: {}", user_info.username.cyan());
+        outln!("\nLocal Deployment Configuration:");
+        outln!("  User: {}", user_info.username.cyan());
 
         if !user_info.ssh_keys.is_empty() {
-            println!("\n  SSH Keys:");
+            outln!("\n  SSH Keys:");
             for key in &user_info.ssh_keys {
-                println!("    - {}", key.dimmed());
+                outln!("    - {}", key.dimmed());
             }
         }
 
-        let mut build_platforms = vec![user_info.system.clone()];
-        build_platforms.extend(user_info.extra_platforms.clone());
+        if !user_info.system.is_empty() {
+            let mut build_platforms = vec![user_info.system.clone()];
+            build_platforms.extend(user_info.extra_platforms.iter().map(|platform| {
+                if user_info.builds_via_emulation(platform) {
+                    format!("{platform} (emulated)")
+                } else {
+                    platform.clone()
+                }
+            }));
 
-        println!("\n  Build Capabilities:");
-        println!("    Local: {}", build_platforms.join(", ").cyan());
+            outln!("\n  Build Capabilities:");
+            outln!("    Local: {}", build_platforms.join(", ").cyan());
 
-        if !user_info.remote_builders.is_empty() {
-            let remote_systems: Vec<_> = user_info
-                .remote_builders
-                .iter()
-                .map(|rb| format!("{} via {}", rb.system, rb.ssh_host))
-                .collect();
-            println!("    Remote: {}", remote_systems.join(", ").cyan());
+            if !user_info.remote_builders.is_empty() {
+                let remote_systems: Vec<_> = user_info
+                    .remote_builders
+                    .iter()
+                    .map(|rb| format!("{} via {}", rb.system, rb.ssh_host))
+                    .collect();
+                outln!("    Remote: {}", remote_systems.join(", ").cyan());
+            }
         }
-        println!();
+        outln!();
     }
 
     match &cli.command {
-        Command::Build { systems } => {
-            let system_attributes = flakerefs_or_default(systems)?;
+        Command::Build {
+            systems,
+            keep_result_symlinks,
+            builders,
+            no_substitute,
+            max_jobs,
+            cores,
+            nix_arg,
+            build_host,
+            keep_going,
+        } => {
+            let build_host = build_host
+                .as_deref()
+                .map(|host| ConnectionOptions::from_env().remote_host(host));
+            let logger = HostLogger::from_verbose(cli.verbose);
+            let build_options = BuildOptions {
+                builders: builders.clone(),
+                no_substitute: *no_substitute,
+                max_jobs: max_jobs.clone(),
+                cores: *cores,
+                extra_args: nix_arg.clone(),
+            };
+            let system_attributes = flakerefs_or_default(systems, cli.filter.as_deref(), &[])?;
             if system_attributes.len() > 1 {
-                eprintln!(
+                errln!(
                     "{}",
                     format!(
                         "→ Building systems: {}",
@@ -367,61 +1366,151 @@ fn run() -> Result<(), NxbdError> {
                 );
             }
             // TODO: Build only locally buildable systems
-            for system in &system_attributes {
-                let result = nixos_deploy_info(system)?;
-                eprintln!("{}", format!("→ Building system: {}", system).white());
-                realise_toplevel_output_paths(&[system.clone()])?;
-                eprintln!(
+            let build = |system: &FlakeReference| -> Result<(), NxbdError> {
+                let result = nixos_deploy_info(system, eval_cache.as_ref())?;
+                errln!("{}", format!("→ Building system: {}", system).white());
+                match &build_host {
+                    Some(build_host) => build_on_build_host(
+                        &result,
+                        build_host,
+                        &logger.for_host(&system.attribute),
+                    )?,
+                    None => realise_toplevel_output_paths(&[system.clone()], &build_options)?,
+                }
+                if let Some(dir) = keep_result_symlinks {
+                    keep_result_symlink(dir, system, &result.toplevel_out)?;
+                }
+                errln!(
                     "{}",
-                    format!("→ Built store path for {}: {}", system, result.toplevel_out).white()
+                    format!(
+                        "→ Built store path for {}: {}{}",
+                        system,
+                        result.toplevel_out,
+                        closure_size_note(&result.toplevel_out, None)
+                    )
+                    .white()
                 );
+                Ok(())
+            };
+
+            let mut failures = Vec::new();
+            for system in &system_attributes {
+                match build(system) {
+                    Ok(()) => {}
+                    Err(NxbdError::Nix(error)) if *keep_going => {
+                        errln!("{}", format!("✗ {system}: {error}").red());
+                        failures.push((system.clone(), error));
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            if !failures.is_empty() {
+                return Err(NxbdError::BuildsFailed { failures });
             }
         }
         Command::SwitchRemote {
             systems,
+            exclude,
             ignore_checks,
             reboot,
+            wait_for_reboot,
+            reboot_timeout,
             ignored_checks,
+            ignore_file,
+            keep_result_symlinks,
+            builders,
+            no_substitute,
+            max_jobs,
+            cores,
+            nix_arg,
+            local_build_then_copy,
+            build_host,
+            confirm_each,
+            health_gate,
+            only_changed,
+            halt_on_failure,
+            batch_size,
+            yes,
+            dump_plan,
+            no_lock,
+            lock_timeout,
+            dry_run,
+            activation_mode,
+            fail_on_warn,
+            retries,
+            no_substitute_on_destination,
+            substituters,
+            connection,
         } => {
-            let system_attributes = flakerefs_or_default(systems)?;
-
-            eprintln!(
-                "Reading configurations of {}...",
-                system_attributes
-                    .iter()
-                    .map(|s| format!(".#{}", s.attribute))
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            );
-
-            // Parallelize deploy info collection
-            let deploy_infos: Vec<(FlakeReference, Result<ConfigInfo, NixError>)> =
-                system_attributes
-                    .par_iter()
-                    .map(|system| (system.clone(), nixos_deploy_info(system)))
-                    .collect();
-
-            // Check if any configurations had evaluation errors
-            let evaluation_errors: Vec<(FlakeReference, NixError)> = deploy_infos
+            let target_hosts = connection.target_hosts();
+            let connection = ConnectionOptions {
+                retries: *retries,
+                no_substitute_on_destination: *no_substitute_on_destination,
+                substituters: substituters.clone(),
+                ..connection.options()
+            };
+            user_info.remote_user.clone_from(&connection.user);
+            user_info.privilege_escalation = connection.privilege_escalation;
+            if *confirm_each && !io::stdin().is_terminal() {
+                return Err(NxbdError::NotInteractive {
+                    option: "--confirm-each".to_string(),
+                });
+            }
+            if let Some(builders) = builders {
+                user_info.override_builders(builders)?;
+            }
+            let build_options = BuildOptions {
+                builders: builders.clone(),
+                no_substitute: *no_substitute,
+                max_jobs: max_jobs.clone(),
+                cores: *cores,
+                extra_args: nix_arg.clone(),
+            };
+            let deploy_infos = evaluate_systems(
+                systems,
+                cli.filter.as_deref(),
+                exclude,
+                &target_hosts,
+                eval_cache.as_ref(),
+            )?;
+
+            let systems: Vec<(&FlakeReference, &ConfigInfo)> = deploy_infos
                 .iter()
-                .filter_map(|(system, result)| match result {
-                    Err(err) => Some((system.clone(), err.clone())),
-                    _ => None,
-                })
+                .map(|(system, info)| (system, info))
                 .collect();
+            let mut deploy_plan = plan(
+                &systems,
+                &user_info,
+                &PlanOptions {
+                    local_build_then_copy: *local_build_then_copy,
+                    query_hosts: dump_plan.is_some() || *only_changed,
+                    connection: connection.clone(),
+                    activation_mode: *activation_mode,
+                    build_host: build_host.clone(),
+                },
+            );
 
-            if !evaluation_errors.is_empty() {
-                return Err(NxbdError::EvaluationFails {
-                    failures: evaluation_errors,
-                });
+            if let Some(PlanFormat::Json) = dump_plan {
+                let json = serde_json::to_string_pretty(&deploy_plan).map_err(io::Error::from)?;
+                outln!("{json}");
+                return Ok(());
+            }
+
+            let mut unchanged = Vec::new();
+            if *only_changed {
+                let up_to_date;
+                (up_to_date, deploy_plan.deployments) = deploy_plan
+                    .deployments
+                    .into_iter()
+                    .partition(|deployment| deployment.up_to_date == Some(true));
+                unchanged = up_to_date;
             }
 
-            println!(
+            outln!(
                 "Switching systems: {}",
                 deploy_infos
                     .iter()
-                    .filter_map(|(_, info)| info.as_ref().ok())
-                    .map(|info| info.fqdn_or_host_name.as_str())
+                    .map(|(_, info)| info.fqdn_or_host_name.as_str())
                     .collect::<Vec<_>>()
                     .join(" ")
             );
@@ -429,34 +1518,32 @@ fn run() -> Result<(), NxbdError> {
             // Run checks first (unless ignored)
             if !ignore_checks {
                 // Load ignored checks from file
-                let ignored_checks_map = load_ignored_checks(".nxbd-ignore.yaml");
+                let ignored_checks_map = load_ignore_files(ignore_file);
 
                 let mut all_failures = Vec::new();
                 for (system, info) in &deploy_infos {
-                    match info {
-                        Ok(info) => {
-                            // Extract the right ignore map for the current system
-                            let mut system_ignore_map = ignored_checks_map
-                                .as_ref()
-                                .and_then(|map| map.get(&system.attribute))
-                                .cloned();
-
-                            // Merge with command line ignored checks if provided
-                            if let Some(cmd_ignores) = &ignored_checks {
-                                system_ignore_map = if let Some(map) = system_ignore_map {
-                                    Some(merge_ignore_maps(&map, cmd_ignores))
-                                } else {
-                                    Some(cmd_ignores.clone())
-                                };
-                            }
+                    // Extract the right ignore map for the current system
+                    let mut system_ignore_map = ignored_checks_map
+                        .as_ref()
+                        .and_then(|map| ignore_map_for_system(map, &system.attribute));
 
-                            let failures =
-                                run_system_checks(info, &user_info, system_ignore_map.as_ref())?;
-                            if !failures.is_empty() {
-                                all_failures.push((system.clone(), failures));
-                            }
-                        }
-                        Err(e) => return Err(e.clone().into()),
+                    // Merge with command line ignored checks if provided
+                    if let Some(cmd_ignores) = &ignored_checks {
+                        system_ignore_map = if let Some(map) = system_ignore_map {
+                            Some(merge_ignore_maps(&map, cmd_ignores))
+                        } else {
+                            Some(cmd_ignores.clone())
+                        };
+                    }
+
+                    let failures = run_system_checks(
+                        info,
+                        &user_info,
+                        system_ignore_map.as_ref(),
+                        *fail_on_warn,
+                    )?;
+                    if !failures.is_empty() {
+                        all_failures.push((system.clone(), failures));
                     }
                 }
 
@@ -468,127 +1555,617 @@ fn run() -> Result<(), NxbdError> {
                 }
             }
 
-            // Split systems into local and remote builds based on build capability
-            let (local_builds, remote_builds): (Vec<_>, Vec<_>) = deploy_infos
+            // --confirm-each asks before every host anyway, and a dry run
+            // doesn't change what the hosts run
+            let interactive = io::stdin().is_terminal() && io::stdout().is_terminal();
+            if interactive && !*yes && !*confirm_each && !*dry_run {
+                let hosts: Vec<&str> = deploy_plan
+                    .deployments
+                    .iter()
+                    .map(|deployment| deployment.host)
+                    .collect();
+                if !confirm_deployment(&hosts, *activation_mode)? {
+                    errln!("Deployment cancelled");
+                    return Ok(());
+                }
+            }
+
+            let local_builds: Vec<(&FlakeReference, &ConfigInfo)> = deploy_plan
+                .deployments
                 .iter()
-                .filter_map(|(system, info_result)| {
-                    info_result.as_ref().ok().map(|info| (system, info))
-                })
-                .partition(|(_, info)| user_info.can_build_natively(&info.system));
+                .filter(|deployment| deployment.build.is_local())
+                .map(|deployment| (deployment.system, deployment.info))
+                .collect();
 
-            // Deploy systems that can be built locally
+            for (system, info) in &local_builds {
+                if user_info.builds_via_emulation(&info.system) {
+                    errln!(
+                        "{}",
+                        format!(
+                            "Note: building {system} ({}) via emulation, this can be slow",
+                            info.system
+                        )
+                        .yellow()
+                    );
+                } else if let Some(problem) = user_info.local_build_problem(&info.system) {
+                    if *local_build_then_copy {
+                        errln!(
+                            "{}",
+                            format!("Warning: building {system} locally may fail: {problem}")
+                                .yellow()
+                        );
+                    }
+                }
+            }
+
+            // Deploy systems that can be built locally. They are built
+            // together, so they share their build time.
+            let mut local_build_time = None;
             if !local_builds.is_empty() {
                 let local_systems: Vec<FlakeReference> =
                     local_builds.iter().map(|(sa, _)| (*sa).clone()).collect();
-                realise_toplevel_output_paths(&local_systems)?;
-            }
-
-            let local_results: Vec<(FlakeReference, Result<(), NixError>)> = local_builds
-                .into_iter()
-                .map(|(sa, deploy_info)| {
-                    let result =
-                        copy_to_host(&deploy_info.toplevel_out, &deploy_info.fqdn_or_host_name)
-                            .and_then(|_| {
-                                activate_profile(
-                                    &deploy_info.toplevel_out,
-                                    true,
-                                    Some(&deploy_info.fqdn_or_host_name),
-                                )
-                            })
-                            .and_then(|_| {
-                                switch_to_configuration(
-                                    &deploy_info.toplevel_out,
-                                    "switch",
-                                    true,
-                                    Some(&deploy_info.fqdn_or_host_name),
-                                )
-                            });
-                    (sa.clone(), result)
-                })
+                let started = Instant::now();
+                realise_toplevel_output_paths(&local_systems, &build_options)?;
+                local_build_time = Some(started.elapsed());
+                if let Some(dir) = keep_result_symlinks {
+                    for (system, info) in &local_builds {
+                        keep_result_symlink(dir, system, &info.toplevel_out)?;
+                    }
+                }
+            }
+
+            // Two attributes may resolve to the same host; their deployments must not overlap
+            let host_mutexes = HostMutexes::default();
+            let shared_builds = SharedBuilds::default();
+            let logger = HostLogger::from_verbose(cli.verbose);
+            let lock_owner = format!(
+                "{}@{}",
+                user_info.username,
+                unistd::gethostname()
+                    .ok()
+                    .and_then(|name| name.into_string().ok())
+                    .unwrap_or_default()
+            );
+
+            let deployments: Vec<(&FlakeReference, &ConfigInfo, BuildStrategy)> = deploy_plan
+                .deployments
+                .iter()
+                .map(|deployment| (deployment.system, deployment.info, deployment.build))
                 .collect();
 
-            // Deploy systems that need remote building
-            let remote_results: Vec<(FlakeReference, Result<(), NixError>)> = remote_builds
-                .into_iter()
-                .map(|(sa, deploy_info)| {
-                    println!(
-                        "{}",
-                        format!(
-                            "→ Building on remote host: {}",
-                            deploy_info.fqdn_or_host_name
-                        )
-                        .white()
+            let build_host = build_host.as_deref().map(|host| {
+                ConnectionOptions {
+                    retries: *retries,
+                    ..ConnectionOptions::from_env()
+                }
+                .remote_host(host)
+            });
+            let health_gate = *health_gate || batch_size.is_some();
+            let deploy = |deploy_info: &ConfigInfo, build: BuildStrategy| {
+                let remote_host = connection.remote_host(&deploy_info.fqdn_or_host_name);
+                let log = logger.for_host(&deploy_info.fqdn_or_host_name);
+                let mut timings = PhaseTimings {
+                    build: local_build_time.filter(|_| build.is_local()),
+                    ..PhaseTimings::default()
+                };
+                host_mutexes.with_lock(&deploy_info.fqdn_or_host_name, || {
+                    let _deploy_lock = (!*no_lock)
+                        .then(|| lock_host(&remote_host, &lock_owner, *lock_timeout))
+                        .transpose()?;
+                    verify_platform(deploy_info, &remote_host)?;
+                    match (build, &build_host) {
+                        (BuildStrategy::TargetHost, _) => {
+                            stage_remote_build(
+                                deploy_info,
+                                &remote_host,
+                                &shared_builds,
+                                &log,
+                                &mut timings,
+                            )?;
+                        }
+                        (BuildStrategy::BuildHost, Some(build_host)) => {
+                            stage_build_host_build(
+                                deploy_info,
+                                build_host,
+                                &remote_host,
+                                &shared_builds,
+                                &log,
+                                &mut timings,
+                            )?;
+                        }
+                        _ => stage_local_build(deploy_info, &remote_host, &log, &mut timings)?,
+                    }
+                    if *dry_run {
+                        return Ok(timings);
+                    }
+                    timed(&mut timings.switch, || {
+                        activate_and_switch(deploy_info, &remote_host, *activation_mode, &log)
+                    })?;
+                    if health_gate && activation_mode.activates() {
+                        log.phase("checking health");
+                        // In test mode, the system profile keeps pointing to the old system
+                        let expected_generation = activation_mode
+                            .sets_boot_default()
+                            .then_some(deploy_info.toplevel_out.as_str());
+                        check_health(&remote_host, expected_generation)?;
+                        log.log("→ Runs the new system without failed units");
+                    }
+                    Ok(timings)
+                })
+            };
+
+            let mut results: Vec<(FlakeReference, Result<PhaseTimings, NixError>)> = Vec::new();
+            let mut rolled_back = None;
+            let mut skipped: &[(&FlakeReference, &ConfigInfo, BuildStrategy)] = &[];
+            if *confirm_each {
+                for (index, (sa, deploy_info, build)) in deployments.iter().enumerate() {
+                    results.push(((*sa).clone(), deploy(deploy_info, *build)));
+
+                    let Some((next_system, _, _)) = deployments.get(index + 1) else {
+                        continue;
+                    };
+
+                    let remote_host = connection.remote_host(&deploy_info.fqdn_or_host_name);
+                    print_system_status(
+                        deploy_info,
+                        &check_system_status(Some(&remote_host), Some(&deploy_info.kernel)),
                     );
-                    let result =
-                        copy_to_host(&deploy_info.toplevel_drv, &deploy_info.fqdn_or_host_name)
-                            .and_then(|_| {
-                                realise_drv_remotely(
-                                    &deploy_info.toplevel_drv,
-                                    &deploy_info.fqdn_or_host_name,
-                                )
-                            })
-                            .and_then(|_| {
-                                activate_profile(
-                                    &deploy_info.toplevel_out,
-                                    true,
-                                    Some(&deploy_info.fqdn_or_host_name),
-                                )
+                    match confirm_next_host(next_system)? {
+                        Confirmation::Continue => {}
+                        Confirmation::Stop => {
+                            skipped = &deployments[index + 1..];
+                            break;
+                        }
+                        Confirmation::Rollback => {
+                            errln!("Rolling back {remote_host}...");
+                            rollback_system(true, Some(&remote_host))?;
+                            rolled_back = results.pop().map(|(system, _)| system);
+                            skipped = &deployments[index + 1..];
+                            break;
+                        }
+                    }
+                }
+            } else if *halt_on_failure || batch_size.is_some() {
+                let size = batch_size.unwrap_or(1);
+                let batch_count = deployments.len().div_ceil(size);
+                for (index, batch) in deployments.chunks(size).enumerate() {
+                    if batch_size.is_some() {
+                        let hosts: Vec<&str> = batch
+                            .iter()
+                            .map(|(_, info, _)| info.fqdn_or_host_name.as_str())
+                            .collect();
+                        errln!(
+                            "{}",
+                            format!("Batch {}/{batch_count}: {}", index + 1, hosts.join(", "))
+                                .white()
+                        );
+                    }
+                    let batch_results: Vec<(FlakeReference, Result<PhaseTimings, NixError>)> =
+                        batch
+                            .par_iter()
+                            .map(|(sa, deploy_info, build)| {
+                                ((*sa).clone(), deploy(deploy_info, *build))
                             })
-                            .and_then(|_| {
-                                switch_to_configuration(
-                                    &deploy_info.toplevel_out,
-                                    "switch",
-                                    true,
-                                    Some(&deploy_info.fqdn_or_host_name),
-                                )
-                            });
-                    (sa.clone(), result)
-                })
-                .collect();
+                            .collect();
+                    let failed = batch_results.iter().any(|(_, result)| result.is_err());
+                    results.extend(batch_results);
+                    if failed {
+                        errln!(
+                            "{}",
+                            "Deployment failed, not deploying the remaining hosts".red()
+                        );
+                        skipped = &deployments[(index + 1) * size..];
+                        break;
+                    }
+                }
+            } else {
+                // Hosts are independent, so a failing host doesn't stop the others
+                results = deployments
+                    .par_iter()
+                    .map(|(sa, deploy_info, build)| ((*sa).clone(), deploy(deploy_info, *build)))
+                    .collect();
+            }
 
-            // Combine results for summary
-            let results: Vec<_> = local_results.into_iter().chain(remote_results).collect();
+            // Systems built from here are deployed first, but the summary
+            // follows the order in which the systems were given
+            results.sort_by_key(|(system, _)| deploy_infos.iter().position(|(s, _)| s == system));
 
-            println!("\nDeployment Summary:");
+            outln!("\nDeployment Summary:");
+            let mut rebooting = Vec::new();
             for (system, result) in results {
+                let size_note = deploy_infos
+                    .iter()
+                    .find(|(s, _)| s == &system)
+                    .map(|(_, info)| info)
+                    .filter(|_| result.is_ok())
+                    .map(|info| {
+                        let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                        closure_size_note(&info.toplevel_out, Some(&remote_host))
+                    })
+                    .unwrap_or_default();
                 match result {
-                    Ok(()) => {
+                    Ok(timings) if *dry_run => {
+                        outln!(
+                            "  {} {}{timings} (staged, not activated){size_note}",
+                            "✓".green(),
+                            system
+                        );
+                    }
+                    Ok(timings) => {
                         let (status_suffix, do_reboot) = deploy_infos
                             .iter()
                             .find(|(s, _)| s == &system)
-                            .and_then(|(_, i)| i.as_ref().ok())
-                            .and_then(|info| {
-                                check_system_status(Some(&info.fqdn_or_host_name)).ok()
+                            .and_then(|(_, info)| {
+                                let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                                check_system_status(Some(&remote_host), None).ok()
                             })
                             .map_or(("", false), |sys_status| match sys_status {
-                                SystemStatus::Reachable { needs_reboot, .. } => (
-                                    if needs_reboot {
-                                        " (reboot required)"
-                                    } else {
-                                        ""
-                                    },
-                                    needs_reboot,
-                                ),
-                                SystemStatus::Unreachable => ("", false),
+                                // In boot mode, nothing changes until the next reboot
+                                SystemStatus::Reachable { needs_reboot, .. }
+                                    if needs_reboot || *activation_mode == ActivationMode::Boot =>
+                                {
+                                    (" (reboot required)", true)
+                                }
+                                SystemStatus::Reachable { .. }
+                                | SystemStatus::Unreachable
+                                | SystemStatus::StatusUnavailable { .. } => ("", false),
                             });
 
-                        println!("  {} {}{}", "✓".green(), system, status_suffix);
+                        outln!(
+                            "  {} {}{timings}{}{size_note}",
+                            "✓".green(),
+                            system,
+                            status_suffix
+                        );
 
                         if do_reboot && *reboot {
-                            if let Some(info) = deploy_infos
-                                .iter()
-                                .find(|(s, _)| s == &system)
-                                .and_then(|(_, i)| i.as_ref().ok())
+                            if let Some((_, info)) = deploy_infos.iter().find(|(s, _)| s == &system)
                             {
-                                print!("    Rebooting... ");
-                                match reboot_host(&info.fqdn_or_host_name) {
-                                    Ok(()) => println!("initiated"),
-                                    Err(e) => println!("failed: {}", e),
+                                let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                                let rebooted_at = Instant::now();
+                                match reboot_host(Some(&remote_host)) {
+                                    Ok(()) => {
+                                        outln!("    Rebooting {remote_host}... initiated");
+                                        rebooting.push((system.clone(), info, rebooted_at));
+                                    }
+                                    Err(e) => {
+                                        outln!("    Rebooting {remote_host}... failed: {e}");
+                                    }
                                 }
                             }
                         }
                     }
-                    Err(e) => println!("  {} {} ({})", "✗".red(), system, e),
+                    Err(e) => outln!("  {} {} ({})", "✗".red(), system, e),
+                }
+            }
+            if let Some(system) = rolled_back {
+                outln!("  {} {} (rolled back)", "↩".yellow(), system);
+            }
+            for deployment in &unchanged {
+                outln!("  {} {} (unchanged)", "✓".green(), deployment.system);
+            }
+            for (system, _, _) in skipped {
+                outln!("  {} {} (skipped)", "-".yellow(), system);
+            }
+
+            if *wait_for_reboot && !rebooting.is_empty() {
+                outln!("\nWaiting for rebooted hosts...");
+                let timeout = Duration::from_secs(*reboot_timeout);
+                let statuses: Vec<_> = rebooting
+                    .par_iter()
+                    .map(|(system, info, rebooted_at)| {
+                        let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                        (
+                            system,
+                            info,
+                            wait_until_rebooted(&remote_host, *rebooted_at, timeout),
+                        )
+                    })
+                    .collect();
+                for (system, info, status) in statuses {
+                    match status {
+                        Some(SystemStatus::Reachable {
+                            current_generation,
+                            needs_reboot,
+                            ..
+                        }) => {
+                            if current_generation != info.toplevel_out {
+                                outln!(
+                                    "  {} {} (runs {} instead of the deployed system)",
+                                    "✗".red(),
+                                    system,
+                                    current_generation
+                                );
+                            } else if needs_reboot {
+                                outln!("  {} {} (still requires a reboot)", "✗".red(), system);
+                            } else {
+                                outln!("  {} {} (rebooted and healthy)", "✓".green(), system);
+                            }
+                        }
+                        Some(
+                            SystemStatus::Unreachable | SystemStatus::StatusUnavailable { .. },
+                        )
+                        | None => outln!(
+                            "  {} {} (did not come back within {})",
+                            "✗".red(),
+                            system,
+                            format_duration(*reboot_timeout)
+                        ),
+                    }
+                }
+            }
+        }
+        Command::Copy {
+            systems,
+            to,
+            builders,
+            no_substitute_on_destination,
+            substituters,
+            connection,
+        } => {
+            let target_hosts = connection.target_hosts();
+            let connection = ConnectionOptions {
+                no_substitute_on_destination: *no_substitute_on_destination,
+                substituters: substituters.clone(),
+                ..connection.options()
+            };
+            let build_options = BuildOptions {
+                builders: builders.clone(),
+                ..BuildOptions::default()
+            };
+            let deploy_infos = evaluate_systems(
+                systems,
+                cli.filter.as_deref(),
+                &[],
+                &target_hosts,
+                eval_cache.as_ref(),
+            )?;
+
+            let system_attributes: Vec<FlakeReference> = deploy_infos
+                .iter()
+                .map(|(system, _)| system.clone())
+                .collect();
+            realise_toplevel_output_paths(&system_attributes, &build_options)?;
+
+            let logger = HostLogger::from_verbose(cli.verbose);
+            let results: Vec<(&FlakeReference, RemoteHost, Result<(), NixError>)> = deploy_infos
+                .par_iter()
+                .map(|(system, info)| {
+                    let remote_host =
+                        connection.remote_host(to.as_deref().unwrap_or(&info.fqdn_or_host_name));
+                    let log = logger.for_host(&remote_host.host);
+                    log.phase("copying");
+                    let result = with_retries(&remote_host, &log, || {
+                        copy_to_host(&info.toplevel_out, &remote_host, &log)
+                    });
+                    (system, remote_host, result)
+                })
+                .collect();
+
+            outln!("\nCopy Summary:");
+            for (system, remote_host, result) in results {
+                match result {
+                    Ok(()) => outln!("  {} {} → {}", "✓".green(), system, remote_host),
+                    Err(e) => outln!("  {} {} → {} ({})", "✗".red(), system, remote_host, e),
+                }
+            }
+        }
+        Command::Diff {
+            systems,
+            builders,
+            connection,
+        } => {
+            let target_hosts = connection.target_hosts();
+            let connection = connection.options();
+            let build_options = BuildOptions {
+                builders: builders.clone(),
+                ..BuildOptions::default()
+            };
+            let deploy_infos = evaluate_systems(
+                systems,
+                cli.filter.as_deref(),
+                &[],
+                &target_hosts,
+                eval_cache.as_ref(),
+            )?;
+
+            let system_attributes: Vec<FlakeReference> = deploy_infos
+                .iter()
+                .map(|(system, _)| system.clone())
+                .collect();
+            realise_toplevel_output_paths(&system_attributes, &build_options)?;
+
+            let logger = HostLogger::from_verbose(cli.verbose);
+            for (system, info) in &deploy_infos {
+                outln!("\n=== {} ===", system.to_string().cyan().bold());
+
+                let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                let current_generation = match check_system_status(Some(&remote_host), None) {
+                    Ok(SystemStatus::Reachable {
+                        current_generation, ..
+                    }) => current_generation,
+                    Ok(SystemStatus::Unreachable) => {
+                        outln!("  {} System not reachable", "✗".red());
+                        continue;
+                    }
+                    Ok(SystemStatus::StatusUnavailable { stderr_tail }) => {
+                        print_status_unavailable(&stderr_tail);
+                        continue;
+                    }
+                    Err(e) => {
+                        outln!("  {} Error getting system status: {}", "✗".red(), e);
+                        continue;
+                    }
+                };
+
+                if current_generation == info.toplevel_out {
+                    outln!("  {} System generation up to date", passed_symbol(true));
+                    continue;
+                }
+
+                let log = logger.for_host(&remote_host.host);
+                let diff = copy_to_host(&info.toplevel_out, &remote_host, &log).and_then(|()| {
+                    diff_closures(&current_generation, &info.toplevel_out, Some(&remote_host))
+                });
+                match diff {
+                    Ok(diff) if diff.trim().is_empty() => {
+                        outln!("  No package changes");
+                    }
+                    Ok(diff) => {
+                        for line in diff.lines() {
+                            outln!("  {line}");
+                        }
+                    }
+                    Err(e) => outln!("  {} Failed to diff closures: {}", "✗".red(), e),
+                }
+            }
+        }
+        Command::Gc {
+            systems,
+            older_than,
+            connection,
+        } => {
+            let target_hosts = connection.target_hosts();
+            let connection = connection.options();
+            let deploy_infos = evaluate_systems(
+                systems,
+                cli.filter.as_deref(),
+                &[],
+                &target_hosts,
+                eval_cache.as_ref(),
+            )?;
+
+            let results: Vec<(&FlakeReference, Result<GcResult, NixError>)> = deploy_infos
+                .par_iter()
+                .map(|(system, info)| {
+                    let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                    (
+                        system,
+                        collect_garbage(older_than.as_deref(), Some(&remote_host)),
+                    )
+                })
+                .collect();
+
+            outln!("\nGarbage Collection Summary:");
+            for (system, result) in results {
+                match result {
+                    Ok(gc) => outln!(
+                        "  {} {} ({} freed, {} store paths deleted)",
+                        "✓".green(),
+                        system,
+                        gc.freed,
+                        gc.paths_deleted
+                    ),
+                    Err(e) => outln!("  {} {} ({})", "✗".red(), system, e),
+                }
+            }
+        }
+        Command::Generations {
+            systems,
+            connection,
+        } => {
+            let target_hosts = connection.target_hosts();
+            let connection = connection.options();
+            let deploy_infos = evaluate_systems(
+                systems,
+                cli.filter.as_deref(),
+                &[],
+                &target_hosts,
+                eval_cache.as_ref(),
+            )?;
+
+            let generations: Vec<_> = deploy_infos
+                .par_iter()
+                .map(|(system, info)| {
+                    let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                    (system, info, list_generations(Some(&remote_host)))
+                })
+                .collect();
+
+            for (system, info, result) in generations {
+                outln!("\n=== {} ===", system.to_string().cyan().bold());
+                let generations = match result {
+                    Ok(generations) => generations,
+                    Err(e) => {
+                        outln!("  {} Error listing generations: {}", "✗".red(), e);
+                        continue;
+                    }
+                };
+
+                outln!("  {:>6}  {:<19}", "Gen".bold(), "Date".bold());
+                for generation in generations {
+                    let mut markers = Vec::new();
+                    if generation.current {
+                        markers.push("current".green().to_string());
+                    }
+                    if generation.store_path.as_deref() == Some(info.toplevel_out.as_str()) {
+                        markers.push("matches flake".cyan().to_string());
+                    }
+                    outln!(
+                        "  {:>6}  {:<19}  {}",
+                        generation.number,
+                        generation.date,
+                        markers.join(", ")
+                    );
+                }
+            }
+        }
+        Command::Rollback {
+            systems,
+            reboot,
+            connection,
+        } => {
+            let target_hosts = connection.target_hosts();
+            let connection = connection.options();
+            let deploy_infos = evaluate_systems(
+                systems,
+                cli.filter.as_deref(),
+                &[],
+                &target_hosts,
+                eval_cache.as_ref(),
+            )?;
+
+            let mut results = Vec::new();
+            for (system, info) in &deploy_infos {
+                let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                outln!("Rolling back {remote_host}...");
+                let result = system_generation(Some(&remote_host)).and_then(|before| {
+                    rollback_system(true, Some(&remote_host))?;
+                    Ok((before, system_generation(Some(&remote_host))?))
+                });
+                results.push((system, remote_host, result));
+            }
+
+            outln!("\nRollback Summary:");
+            for (system, remote_host, result) in results {
+                match result {
+                    Ok((before, after)) => {
+                        let needs_reboot = matches!(
+                            check_system_status(Some(&remote_host), None),
+                            Ok(SystemStatus::Reachable {
+                                needs_reboot: true,
+                                ..
+                            })
+                        );
+                        outln!(
+                            "  {} {} (generation {before} → {after}){}",
+                            "✓".green(),
+                            system,
+                            if needs_reboot {
+                                " (reboot required)"
+                            } else {
+                                ""
+                            }
+                        );
+
+                        if needs_reboot && *reboot {
+                            match reboot_host(Some(&remote_host)) {
+                                Ok(()) => outln!("    Rebooting {remote_host}... initiated"),
+                                Err(e) => outln!("    Rebooting {remote_host}... failed: {e}"),
+                            }
+                        }
+                    }
+                    Err(e) => outln!("  {} {} ({})", "✗".red(), system, e),
                 }
             }
         }
@@ -597,6 +2174,10 @@ fn run() -> Result<(), NxbdError> {
             ignore_hostname,
             ignore_checks,
             ignored_checks,
+            ignore_file,
+            activation_mode,
+            fail_on_warn,
+            reboot,
         } => {
             let local_hostname = unistd::gethostname()
                 .expect("Failed getting hostname")
@@ -610,20 +2191,20 @@ fn run() -> Result<(), NxbdError> {
                     attribute: local_hostname.clone(),
                 },
             };
-            println!("Switching system: {system_attribute}");
+            validate_flakerefs(std::slice::from_ref(system_attribute))?;
+            outln!("Switching system: {system_attribute}");
 
-            let deploy_info = nixos_deploy_info(&system_attribute)?;
+            let deploy_info = nixos_deploy_info(system_attribute, eval_cache.as_ref())?;
 
             // Run checks first (unless ignored)
             if !ignore_checks {
                 // Load ignored checks once
-                let ignored_checks_map = load_ignored_checks(".nxbd-ignore.yaml");
+                let ignored_checks_map = load_ignore_files(ignore_file);
 
                 // Extract the right ignore map for the current system
                 let mut system_ignore_map = ignored_checks_map
                     .as_ref()
-                    .and_then(|map| map.get(&system_attribute.attribute))
-                    .cloned();
+                    .and_then(|map| ignore_map_for_system(map, &system_attribute.attribute));
 
                 // Merge with command line ignored checks if provided
                 if let Some(cmd_ignores) = &ignored_checks {
@@ -634,8 +2215,12 @@ fn run() -> Result<(), NxbdError> {
                     };
                 }
 
-                let failures =
-                    run_system_checks(&deploy_info, &user_info, system_ignore_map.as_ref())?;
+                let failures = run_system_checks(
+                    &deploy_info,
+                    &user_info,
+                    system_ignore_map.as_ref(),
+                    *fail_on_warn,
+                )?;
                 if !failures.is_empty() {
                     return Err(NxbdError::ChecksFailed {
                         failures: vec![(system_attribute.clone(), failures)],
@@ -645,57 +2230,115 @@ fn run() -> Result<(), NxbdError> {
             }
 
             // Check hostname match unless ignored
-            if !ignore_hostname {
-                let config_hostname = &deploy_info.host_name;
-                if config_hostname != &local_hostname {
-                    return Err(NxbdError::LocalHostnameMismatch {
-                        config_hostname: config_hostname.clone(),
-                        local_hostname: local_hostname.clone(),
-                    });
-                }
+            if !ignore_hostname
+                && !hostname_matches(
+                    &local_hostname,
+                    &deploy_info.host_name,
+                    &deploy_info.fqdn_or_host_name,
+                )
+            {
+                return Err(NxbdError::LocalHostnameMismatch {
+                    config_hostname: deploy_info.host_name.clone(),
+                    local_hostname: local_hostname.clone(),
+                });
             }
 
             let toplevel = deploy_info.toplevel_out.clone();
-            realise_toplevel_output_paths(&[system_attribute.clone()])?;
-            activate_profile(&toplevel, true, None)?;
-            switch_to_configuration(&toplevel, "switch", true, None)?;
+            realise_toplevel_output_paths(&[system_attribute.clone()], &BuildOptions::default())?;
+            if activation_mode.sets_boot_default() {
+                activate_profile(&toplevel, true, None)?;
+            }
+            switch_to_configuration(&toplevel, activation_mode.as_str(), true, None)?;
 
-            match check_system_status(None)? {
+            match check_system_status(None, None)? {
                 SystemStatus::Reachable { needs_reboot, .. } => {
-                    if needs_reboot {
-                        println!("System update complete. Reboot required.");
+                    if needs_reboot && *reboot {
+                        outln!("System update complete. Rebooting...");
+                        reboot_host(None)?;
+                    } else if needs_reboot {
+                        outln!("System update complete. Reboot required.");
                     } else {
-                        println!("System update complete.");
+                        outln!("System update complete.");
                     }
                 }
-                SystemStatus::Unreachable => {
-                    println!("System update complete. Reboot status unknown.");
+                SystemStatus::Unreachable | SystemStatus::StatusUnavailable { .. } => {
+                    outln!("System update complete. Reboot status unknown.");
                 }
             }
         }
         Command::Check {
             systems,
+            exclude,
             save_ignore,
             ignore_file,
             ignored_checks,
+            from_json,
+            baseline_from_running,
+            diff_advice,
+            baseline,
+            fail_on_warn,
+            keep_going,
+            connection,
         } => {
-            let system_attributes = flakerefs_or_default(systems)?;
-            let file_ignored_checks = load_ignored_checks(&ignore_file);
-
-            eprintln!(
-                "Reading configurations of {}...",
-                system_attributes
-                    .iter()
-                    .map(|s| format!(".#{}", s.attribute))
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            );
+            let target_hosts = connection.target_hosts();
+            let connection = connection.options();
+            let file_ignored_checks = load_ignore_files(ignore_file);
+            let baseline = baseline
+                .as_ref()
+                .map(|path| {
+                    parse_check_baseline(&fs::read_to_string(path)?).map_err(|message| {
+                        NxbdError::InvalidBaseline {
+                            path: path.clone(),
+                            message,
+                        }
+                    })
+                })
+                .transpose()?;
 
             let deploy_infos: Vec<(FlakeReference, Result<ConfigInfo, NixError>)> =
-                system_attributes
-                    .par_iter()
-                    .map(|system| (system.clone(), nixos_deploy_info(system)))
-                    .collect();
+                if let Some(path) = from_json {
+                    let contents = fs::read_to_string(path)?;
+                    let mut config_infos =
+                        config_infos_from_json(&contents).map_err(|message| {
+                            NxbdError::InvalidConfigJson {
+                                path: path.clone(),
+                                message,
+                            }
+                        })?;
+                    if let Some(filter) = cli.filter.as_deref() {
+                        config_infos.retain(|(attribute, _)| glob_matches(filter, attribute));
+                        if config_infos.is_empty() {
+                            return Err(NxbdError::NoSystemsMatch {
+                                filter: filter.to_string(),
+                            });
+                        }
+                    }
+                    config_infos
+                        .into_iter()
+                        .filter(|(attribute, _)| {
+                            !exclude
+                                .iter()
+                                .any(|pattern| glob_matches(pattern, attribute))
+                        })
+                        .map(|(attribute, info)| {
+                            (
+                                FlakeReference {
+                                    url: ".".to_string(),
+                                    attribute,
+                                },
+                                Ok(info),
+                            )
+                        })
+                        .collect()
+                } else {
+                    read_configurations(
+                        systems,
+                        cli.filter.as_deref(),
+                        exclude,
+                        &target_hosts,
+                        eval_cache.as_ref(),
+                    )?
+                };
 
             // Check if any deploy infos failed to evaluate
             let failed_systems: Vec<_> = deploy_infos
@@ -704,12 +2347,14 @@ fn run() -> Result<(), NxbdError> {
                 .collect();
 
             if !failed_systems.is_empty() {
-                eprintln!("\nFailed to evaluate the following systems:");
+                errln!("\nFailed to evaluate the following systems:");
                 for (system, error) in &failed_systems {
-                    eprintln!("  {} - {}", system, error);
+                    errln!("  {} - {}", system, error);
+                }
+                if !*keep_going {
+                    let first_error = failed_systems[0].1.clone();
+                    return Err(NixError::from(first_error).into());
                 }
-                let first_error = failed_systems[0].1.clone();
-                return Err(NixError::from(first_error).into());
             }
 
             let all_results: Vec<(&FlakeReference, Vec<CheckGroupResult>)> = deploy_infos
@@ -719,8 +2364,7 @@ fn run() -> Result<(), NxbdError> {
                         // Extract the right ignore map for the current system
                         let mut system_ignore_map = file_ignored_checks
                             .as_ref()
-                            .and_then(|map| map.get(&system.attribute))
-                            .cloned();
+                            .and_then(|map| ignore_map_for_system(map, &system.attribute));
 
                         // Merge with command line ignored checks if provided
                         if let Some(cmd_ignores) = &ignored_checks {
@@ -739,17 +2383,84 @@ fn run() -> Result<(), NxbdError> {
                 })
                 .collect();
 
+            let running_statuses: Vec<(&FlakeReference, Result<SystemStatus, NixError>)> =
+                if *baseline_from_running {
+                    deploy_infos
+                        .par_iter()
+                        .filter_map(|(system, info)| {
+                            info.as_ref().ok().map(|info| {
+                                let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                                (system, check_system_status(Some(&remote_host), None))
+                            })
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
             for (system, check_group_results) in &all_results {
-                eprintln!("\n=== {} ===", system.to_string().cyan().bold());
+                errln!("\n=== {} ===", system.to_string().cyan().bold());
 
-                let all_passed_or_ignored = check_group_results.iter().all(|group| {
-                    group
-                        .checks
-                        .iter()
-                        .all(|check| check.passed || check.ignored)
-                });
+                let running_status = running_statuses
+                    .iter()
+                    .find(|(s, _)| s == system)
+                    .map(|(_, status)| status);
+                let evaluated_info = deploy_infos
+                    .iter()
+                    .find(|(s, _)| s == *system)
+                    .and_then(|(_, info)| info.as_ref().ok());
+                match (running_status, evaluated_info) {
+                    (
+                        Some(Ok(SystemStatus::Reachable {
+                            current_generation, ..
+                        })),
+                        Some(info),
+                    ) if *current_generation != info.toplevel_out => {
+                        errln!(
+                            "{}",
+                            "! The running generation differs from the evaluated configuration. \
+                             These results describe the flake, not the running system."
+                                .yellow()
+                        );
+                    }
+                    (Some(Ok(SystemStatus::Reachable { .. })), _) => {
+                        errln!(
+                            "{} The running generation matches the evaluated configuration",
+                            passed_symbol(true)
+                        );
+                    }
+                    (Some(Ok(SystemStatus::Unreachable)), _) => {
+                        errln!(
+                            "{}",
+                            "! Could not compare with the running generation: system not reachable"
+                                .yellow()
+                        );
+                    }
+                    (Some(Ok(SystemStatus::StatusUnavailable { .. })), _) => {
+                        errln!(
+                            "{}",
+                            "! Could not compare with the running generation: its status couldn't be read"
+                                .yellow()
+                        );
+                    }
+                    (Some(Err(e)), _) => {
+                        errln!(
+                            "{}",
+                            format!("! Could not compare with the running generation: {e}")
+                                .yellow()
+                        );
+                    }
+                    (None, _) => {}
+                }
+
+                if let Some(state_version) = evaluated_info.and_then(|i| i.state_version.as_ref()) {
+                    errln!("  stateVersion: {state_version}");
+                }
 
-                if all_passed_or_ignored {
+                if check_group_results
+                    .iter()
+                    .all(CheckGroupResult::all_passed_or_ignored)
+                {
                     let total_checks: usize =
                         check_group_results.iter().map(|g| g.checks.len()).sum();
                     let total_ignored: usize = check_group_results
@@ -757,7 +2468,7 @@ fn run() -> Result<(), NxbdError> {
                         .map(|g| g.checks.iter().filter(|c| c.ignored).count())
                         .sum();
 
-                    eprintln!(
+                    errln!(
                         "{} {} checks passed ({} ignored fails)",
                         passed_symbol(true),
                         total_checks,
@@ -770,12 +2481,7 @@ fn run() -> Result<(), NxbdError> {
                 }
 
                 for group_result in check_group_results {
-                    let no_unignored_failures = group_result
-                        .checks
-                        .iter()
-                        .all(|check| check.passed || check.ignored);
-
-                    if no_unignored_failures && !cli.verbose {
+                    if group_result.all_passed_or_ignored() && !cli.verbose {
                         continue;
                     }
 
@@ -783,7 +2489,7 @@ fn run() -> Result<(), NxbdError> {
                     let passed_count = group_result
                         .checks
                         .iter()
-                        .filter(|check| check.passed)
+                        .filter(|check| !check.is_failure())
                         .count();
                     let ignored_count = group_result
                         .checks
@@ -791,7 +2497,7 @@ fn run() -> Result<(), NxbdError> {
                         .filter(|check| check.ignored)
                         .count();
 
-                    eprintln!(
+                    errln!(
                         "\n{} - {} ({} checks, {} passed, {} ignored)",
                         group_result.id.cyan().bold(),
                         group_result.name.bold(),
@@ -799,42 +2505,55 @@ fn run() -> Result<(), NxbdError> {
                         passed_count,
                         ignored_count
                     );
-                    eprintln!("{}", group_result.description);
-                    eprintln!();
+                    errln!("{}", group_result.description);
+                    errln!();
 
                     for check_result in &group_result.checks {
                         if !cli.verbose && check_result.passed {
                             continue;
                         }
-                        eprintln!(
+                        errln!(
                             "  {} {} - {}",
-                            passed_ignore_symbol(check_result.passed, check_result.ignored),
+                            check_result_symbol(check_result),
                             check_result.id.yellow(),
                             check_result.description
                         );
-                        if !check_result.passed {
-                            eprintln!("    - {}", check_result.advice.dimmed());
+                        if check_result.severity == Severity::Info {
+                            if let Some(message) = &check_result.message {
+                                errln!("    - {}", message.dimmed());
+                            }
+                        } else if !check_result.passed {
+                            errln!("    - {}", check_result.advice.dimmed());
+                            if *diff_advice {
+                                if let Some(finding) = &check_result.finding {
+                                    errln!("    - {}", finding.to_string().dimmed());
+                                }
+                            }
                         }
                     }
                 }
             }
 
-            println!();
+            outln!();
+
+            if let Some(baseline) = &baseline {
+                print_baseline_diff(baseline, &all_results, *fail_on_warn);
+            }
 
             let had_failures = all_results.iter().any(|(_, results)| {
-                results.iter().any(|group| {
-                    group
-                        .checks
-                        .iter()
-                        .any(|check| !check.passed && !check.ignored)
-                })
+                results
+                    .iter()
+                    .any(|group| group.checks.iter().any(|check| check.blocks(*fail_on_warn)))
             });
 
             if *save_ignore {
-                if let Err(e) = save_failed_checks_to_ignore_file(&ignore_file, &all_results) {
-                    eprintln!("Failed to save ignore file: {}", e);
-                } else {
-                    println!("Created {} with failed checks", ignore_file);
+                // With layered ignore files, only the last one is written
+                if let Some(ignore_file) = ignore_file.last() {
+                    if let Err(e) = save_failed_checks_to_ignore_file(ignore_file, &all_results) {
+                        errln!("Failed to save ignore file: {}", e);
+                    } else {
+                        outln!("Created {} with failed checks", ignore_file);
+                    }
                 }
             } else if had_failures {
                 let failures: Vec<(FlakeReference, Vec<(String, String)>)> = all_results
@@ -846,7 +2565,7 @@ fn run() -> Result<(), NxbdError> {
                                 group
                                     .checks
                                     .iter()
-                                    .filter(|check| !check.passed && !check.ignored)
+                                    .filter(|check| check.blocks(*fail_on_warn))
                                     .map(|check| (group.id.clone(), check.id.clone()))
                             })
                             .collect();
@@ -863,27 +2582,38 @@ fn run() -> Result<(), NxbdError> {
                     is_switch: false,
                 });
             }
-        }
-
-        Command::Status { systems } => {
-            let system_attributes = flakerefs_or_default(systems)?;
-
-            eprintln!(
-                "Reading configurations of {}...",
-                system_attributes
-                    .iter()
-                    .map(|s| format!(".#{}", s.attribute))
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            );
 
-            let deploy_infos: Vec<(FlakeReference, Result<ConfigInfo, NixError>)> =
-                system_attributes
-                    .par_iter()
-                    .map(|system| (system.clone(), nixos_deploy_info(system)))
-                    .collect();
+            // With --keep-going, evaluation errors only fail the run now
+            if !failed_systems.is_empty() {
+                return Err(NxbdError::EvaluationFails {
+                    failures: failed_systems
+                        .into_iter()
+                        .map(|(system, error)| (system.clone(), error.clone()))
+                        .collect(),
+                });
+            }
+        }
 
-            println!(
+        Command::Status {
+            systems,
+            exclude,
+            max_uptime,
+            fail_on_stale_uptime,
+            layout,
+            host_keys,
+            connection,
+        } => {
+            let target_hosts = connection.target_hosts();
+            let connection = connection.options();
+            let deploy_infos = read_configurations(
+                systems,
+                cli.filter.as_deref(),
+                exclude,
+                &target_hosts,
+                eval_cache.as_ref(),
+            )?;
+
+            outln!(
                 "Querying status of {}...",
                 deploy_infos
                     .iter()
@@ -907,71 +2637,154 @@ fn run() -> Result<(), NxbdError> {
                 })
                 .par_bridge()
                 .map(|(system, info)| {
-                    (
-                        system,
-                        info,
-                        check_system_status(Some(&info.fqdn_or_host_name)),
-                    )
+                    let remote_host = connection.remote_host(&info.fqdn_or_host_name);
+                    let status = check_system_status(Some(&remote_host), Some(&info.kernel));
+                    (system, info, status)
                 })
                 .collect();
 
             // Finally, print all results
-            println!("\nSystem Status:");
+            outln!("\nSystem Status:");
+            let table = match layout {
+                StatusLayout::Table => true,
+                StatusLayout::Hosts => false,
+                StatusLayout::Auto => system_statuses.len() >= STATUS_TABLE_MIN_HOSTS,
+            };
+            if table {
+                outln!();
+                let rows: Vec<Vec<String>> = system_statuses
+                    .iter()
+                    .map(|(_, info, status)| status_table_row(info, status))
+                    .collect();
+                print_table(
+                    &[
+                        "HOST",
+                        "REACHABLE",
+                        "UNITS",
+                        "GENERATION",
+                        "REBOOT",
+                        "UPTIME",
+                    ],
+                    &rows,
+                );
+                for (_, info, status) in &system_statuses {
+                    match status {
+                        Err(e) => outln!(
+                            "{} {}: error getting system status: {}",
+                            "✗".red(),
+                            info.fqdn_or_host_name,
+                            e
+                        ),
+                        Ok(SystemStatus::StatusUnavailable { stderr_tail }) => {
+                            outln!("{}:", info.fqdn_or_host_name);
+                            print_status_unavailable(stderr_tail);
+                        }
+                        Ok(_) => {}
+                    }
+                }
+            }
+            let mut stale_hosts = Vec::new();
             for (system, info, status) in system_statuses {
-                println!("\n=== {} ===", system.to_string().cyan().bold());
+                if !table {
+                    outln!("\n=== {} ===", system.to_string().cyan().bold());
+                    print_system_status(info, &status);
+                }
 
-                match status {
-                    Ok(SystemStatus::Unreachable) => {
-                        println!("  {} System not reachable", "✗".red());
-                    }
+                if let (
+                    Some(max_uptime),
                     Ok(SystemStatus::Reachable {
-                        current_generation,
                         needs_reboot,
                         uptime_seconds,
-                        failed_units,
-                    }) => {
-                        println!(
-                            "  {} systemd units: {}",
-                            passed_symbol(failed_units == 0),
-                            if failed_units == 0 {
-                                "all OK".to_string()
+                        ..
+                    }),
+                ) = (max_uptime, &status)
+                {
+                    if uptime_seconds > max_uptime {
+                        // Below the table, warnings have to name their host
+                        let prefix = if table {
+                            format!("{}: ", info.fqdn_or_host_name)
+                        } else {
+                            "  ".to_string()
+                        };
+                        outln!(
+                            "{prefix}{} Uptime exceeds {}{}",
+                            "!".yellow(),
+                            format_duration(*max_uptime),
+                            if *needs_reboot {
+                                " and a reboot is pending"
                             } else {
-                                format!("{} failed", failed_units).to_string()
+                                ""
                             }
                         );
+                        stale_hosts.push(info.fqdn_or_host_name.clone());
+                    }
+                }
+            }
 
-                        let generation_status = current_generation == info.toplevel_out;
-                        println!(
-                            "  {} System generation {}",
-                            passed_symbol(generation_status),
-                            if generation_status {
-                                "up to date"
-                            } else {
-                                "outdated"
-                            }
-                        );
+            if *host_keys {
+                let hosts: Vec<&str> = deploy_infos
+                    .iter()
+                    .filter_map(|(_, info)| info.as_ref().ok())
+                    .map(|info| info.fqdn_or_host_name.as_str())
+                    .collect();
+                print_shared_host_keys(&hosts, &connection);
+            }
 
-                        println!(
-                            "  {} Reboot required: {}",
-                            if needs_reboot {
-                                "!".yellow().to_string()
-                            } else {
-                                "✓".green().to_string()
-                            },
-                            if needs_reboot { "yes" } else { "no" }
-                        );
+            if *fail_on_stale_uptime && !stale_hosts.is_empty() {
+                return Err(NxbdError::StaleUptime { hosts: stale_hosts });
+            }
+        }
+        Command::Validate { systems, exclude } => {
+            let system_attributes = flakerefs_or_default(systems, cli.filter.as_deref(), exclude)?;
+            errln!(
+                "Evaluating {}...",
+                system_attributes
+                    .iter()
+                    .map(|s| format!(".#{}", s.attribute))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            );
+            let results: Vec<(&FlakeReference, Result<String, NixError>)> = system_attributes
+                .par_iter()
+                .map(|system| (system, toplevel_drv_path(system)))
+                .collect();
 
-                        let days = uptime_seconds / 86400;
-                        let hours = (uptime_seconds % 86400) / 3600;
-                        let minutes = (uptime_seconds % 3600) / 60;
-                        println!("    Uptime: {}d {}h {}m", days, hours, minutes);
+            let mut failures = Vec::new();
+            for (system, result) in results {
+                match result {
+                    Ok(_) => outln!("  {} {}", "✓".green(), system),
+                    Err(e) => {
+                        outln!("  {} {}", "✗".red(), system);
+                        failures.push((system.clone(), e));
                     }
-                    Err(e) => println!("  {} Error getting system status: {}", "✗".red(), e),
                 }
             }
+            if !failures.is_empty() {
+                return Err(NxbdError::EvaluationFails { failures });
+            }
+        }
+        Command::Inspect { system } => {
+            let info = nixos_deploy_info(system, eval_cache.as_ref())?;
+            let json = serde_json::to_string_pretty(&info).map_err(io::Error::from)?;
+            outln!("{json}");
+        }
+        Command::Ssh {
+            system,
+            args,
+            connection,
+        } => {
+            let target_hosts = connection.target_hosts();
+            check_target_hosts(&target_hosts, std::slice::from_ref(system))?;
+            let info = deploy_info_at_target(system, eval_cache.as_ref(), &target_hosts)?;
+            let remote_host = connection.options().remote_host(&info.fqdn_or_host_name);
+            // Only returns if ssh couldn't be started
+            let error = process::Command::new("ssh")
+                .args(remote_host.ssh_args())
+                .args(args)
+                .exec();
+            return Err(error.into());
         }
-        Command::Checks => {}
-        Command::GenerateDocs { output_dir: _ } => {}
+        Command::Checks { .. } | Command::GenerateDocs { .. } => {}
     }
     Ok(())
 }